@@ -1,6 +1,10 @@
 extern crate criterion;
 
-use criterion::{criterion_group, criterion_main, Criterion};
+use centreon_generic_snmp::compute::ast::ExprResult;
+use centreon_generic_snmp::generic::Command;
+use centreon_generic_snmp::snmp::SnmpResult;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::collections::HashMap;
 use std::hint::black_box;
 
 fn average_for(v: &Vec<f64>) -> f64 {
@@ -46,5 +50,57 @@ fn benchmark_averages(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, benchmark_averages);
+/// Interfaces definition similar to `examples/new-if.json`, evaluated
+/// against synthetic values instead of a live SNMP walk.
+const INTERFACES: &str = r#"{
+  "collect": { "snmp": [] },
+  "compute": {
+    "metrics": [
+      { "name": "traffic_in", "prefix": "{if.descr}", "value": "{if.in} * 8",
+        "uom": "b/s", "min": 0, "max_expr": "{if.speed}", "threshold-suffix": "in" },
+      { "name": "traffic_out", "prefix": "{if.descr}", "value": "{if.out} * 8",
+        "uom": "b/s", "min": 0, "max_expr": "{if.speed}", "threshold-suffix": "out" },
+      { "name": "usage", "prefix": "{if.descr}",
+        "value": "({if.in} + {if.out}) * 800 / {if.speed}", "uom": "%",
+        "min": 0, "max": 100, "warning": "80", "critical": "90" }
+    ],
+    "aggregations": [
+      { "name": "usage.avg", "value": "Average({metrics.usage})", "uom": "%" },
+      { "name": "usage.max", "value": "Max({metrics.usage})", "uom": "%" }
+    ]
+  },
+  "output": { "ok": "All {count} interfaces are ok" }
+}"#;
+
+fn interfaces(count: usize) -> Vec<SnmpResult> {
+    let vector = |f: fn(usize) -> f64| ExprResult::Vector((0..count).map(f).collect());
+    let items = HashMap::from([
+        (
+            "if.descr".to_string(),
+            ExprResult::StrVector((0..count).map(|i| format!("eth{}", i)).collect()),
+        ),
+        ("if.in".to_string(), vector(|i| (i * 1000) as f64)),
+        ("if.out".to_string(), vector(|i| (i * 500) as f64)),
+        ("if.speed".to_string(), vector(|_| 1e9)),
+        ("count".to_string(), ExprResult::Vector(vec![count as f64])),
+    ]);
+    vec![SnmpResult::new(items)]
+}
+
+fn benchmark_pipeline(c: &mut Criterion) {
+    let mut cmd: Command = serde_json::from_str(INTERFACES).unwrap();
+    cmd.compile().unwrap();
+
+    for count in [10, 500] {
+        c.bench_function(&format!("pipeline_{}_interfaces", count), |b| {
+            b.iter_batched(
+                || interfaces(count),
+                |collect| cmd.evaluate(black_box(collect), &[], &[], false).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+criterion_group!(benches, benchmark_averages, benchmark_pipeline);
 criterion_main!(benches);
@@ -1,26 +1,69 @@
 //! Abstract syntax tree and expression evaluation.
 
-use crate::snmp::SnmpResult;
+use crate::compute::symbols::Symbols;
 use log::{info, trace, warn};
-use std::str;
+use regex::Regex;
+use std::borrow::Cow;
 
 /// An expression node in the AST.
 #[derive(Debug)]
-pub enum Expr<'input> {
+pub enum Expr {
     /// A variable identifier (e.g., parsed from `{varname}`).
-    Id(&'input [u8]),
+    Id(String),
     /// A numeric constant.
     Number(f64),
     /// Addition of two expressions.
-    OpPlus(Box<Expr<'input>>, Box<Expr<'input>>),
+    OpPlus(Box<Expr>, Box<Expr>),
     /// Subtraction of two expressions.
-    OpMinus(Box<Expr<'input>>, Box<Expr<'input>>),
+    OpMinus(Box<Expr>, Box<Expr>),
     /// Multiplication of two expressions.
-    OpStar(Box<Expr<'input>>, Box<Expr<'input>>),
+    OpStar(Box<Expr>, Box<Expr>),
     /// Division of two expressions.
-    OpSlash(Box<Expr<'input>>, Box<Expr<'input>>),
-    /// A function call (e.g., `Average()`, `Min()`, `Max()`).
-    Fn(Func, Box<Expr<'input>>),
+    OpSlash(Box<Expr>, Box<Expr>),
+    /// A function call (e.g., `Average()`, `Min()`, `Max()`, `Count()`).
+    Fn(Func, Box<Expr>),
+    /// A string literal (e.g., `"up"`).
+    Str(String),
+    /// A comparison of two expressions, as strings if one of them is a string.
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+    /// A regex match (`=~`), negated (`!~`) when the flag is set.
+    Match(Box<Expr>, Regex, bool),
+    /// Logical and of two expressions.
+    And(Box<Expr>, Box<Expr>),
+    /// Logical or of two expressions.
+    Or(Box<Expr>, Box<Expr>),
+    /// Logical negation of an expression.
+    Not(Box<Expr>),
+}
+
+/// Comparison operators.
+#[derive(Debug, Copy, Clone)]
+pub enum CmpOp {
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+impl CmpOp {
+    fn apply<T: PartialOrd + ?Sized>(&self, a: &T, b: &T) -> bool {
+        match self {
+            CmpOp::Eq => a == b,
+            CmpOp::Ne => a != b,
+            CmpOp::Lt => a < b,
+            CmpOp::Le => a <= b,
+            CmpOp::Gt => a > b,
+            CmpOp::Ge => a >= b,
+        }
+    }
 }
 
 /// Aggregation functions for collapsing vectors to scalars.
@@ -32,10 +75,12 @@ pub enum Func {
     Min,
     /// Compute the maximum value.
     Max,
+    /// Count the values, strings included.
+    Count,
 }
 
 /// Result of evaluating an expression: either a numeric value/vector or a string.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ExprResult {
     /// A vector of floating-point values.
     Vector(Vec<f64>),
@@ -49,227 +94,78 @@ pub enum ExprResult {
     Empty,
 }
 
-impl std::ops::Add for ExprResult {
+/// Applies `f` element-wise on two numeric operands without copying them, a
+/// scalar operand being applied to every element of the other one. The
+/// elements missing from the shorter of two vectors are taken as `identity`.
+///
+/// # Panics
+/// Panics if an operand holds strings.
+fn arithmetic(
+    left: &ExprResult,
+    right: &ExprResult,
+    f: impl Fn(f64, f64) -> f64,
+    identity: f64,
+    verb: &str,
+) -> ExprResult {
+    let (Some((a, a_scalar)), Some((b, b_scalar))) = (left.numbers(), right.numbers()) else {
+        panic!("Invalid operation");
+    };
+    match (a_scalar, b_scalar) {
+        (true, true) => ExprResult::Number(f(a[0], b[0])),
+        (true, false) => ExprResult::Vector(b.iter().map(|y| f(a[0], *y)).collect()),
+        (false, true) => ExprResult::Vector(a.iter().map(|x| f(*x, b[0])).collect()),
+        (false, false) => {
+            if a.len() != b.len() {
+                warn!(
+                    "Trying to {} arrays of different lengths: {} and {}",
+                    verb,
+                    a.len(),
+                    b.len()
+                );
+            }
+            let len = a.len().max(b.len());
+            ExprResult::Vector(
+                (0..len)
+                    .map(|i| {
+                        let x = a.get(i).copied().unwrap_or(identity);
+                        let y = b.get(i).copied().unwrap_or(identity);
+                        f(x, y)
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+impl std::ops::Add for &ExprResult {
     type Output = ExprResult;
 
     fn add(self, other: Self) -> Self::Output {
-        match (self, other) {
-            (ExprResult::Number(a), ExprResult::Number(b)) => ExprResult::Number(a + b),
-            (ExprResult::Vector(a), ExprResult::Vector(b)) => {
-                let len_a = a.len();
-                let len_b = b.len();
-                if len_a == len_b {
-                    let mut result = Vec::with_capacity(len_a);
-                    for i in 0..len_a {
-                        result.push(a[i] + b[i]);
-                    }
-                    ExprResult::Vector(result)
-                } else {
-                    warn!(
-                        "Trying to add to arrays of different lengths: {} and {}",
-                        len_a, len_b
-                    );
-                    if len_a > len_b {
-                        let mut result = a;
-                        for (idx, value) in b.iter().enumerate() {
-                            result[idx] += value;
-                        }
-                        ExprResult::Vector(result)
-                    } else {
-                        let mut result = b;
-                        for (idx, value) in a.iter().enumerate() {
-                            result[idx] += value;
-                        }
-                        ExprResult::Vector(result)
-                    }
-                }
-            }
-            (ExprResult::Number(a), ExprResult::Vector(b)) => {
-                let mut result = b;
-                for value in result.iter_mut() {
-                    *value += a;
-                }
-                ExprResult::Vector(result)
-            }
-            (ExprResult::Vector(a), ExprResult::Number(b)) => {
-                let mut result = a;
-                for value in result.iter_mut() {
-                    *value += b;
-                }
-                ExprResult::Vector(result)
-            }
-            _ => panic!("Invalid operation"),
-        }
+        arithmetic(self, other, |a, b| a + b, 0.0, "add")
     }
 }
 
-impl std::ops::Sub for ExprResult {
+impl std::ops::Sub for &ExprResult {
     type Output = ExprResult;
 
     fn sub(self, other: Self) -> Self::Output {
-        match (self, other) {
-            (ExprResult::Number(a), ExprResult::Number(b)) => ExprResult::Number(a - b),
-            (ExprResult::Vector(a), ExprResult::Vector(b)) => {
-                let len_a = a.len();
-                let len_b = b.len();
-                if len_a == len_b {
-                    let mut result = Vec::with_capacity(len_a);
-                    for i in 0..len_a {
-                        result.push(a[i] - b[i]);
-                    }
-                    ExprResult::Vector(result)
-                } else {
-                    warn!(
-                        "Trying to subtract arrays of different lengths: {} and {}",
-                        len_a, len_b
-                    );
-                    if len_a > len_b {
-                        let mut result = a;
-                        for (idx, value) in b.iter().enumerate() {
-                            result[idx] -= value;
-                        }
-                        ExprResult::Vector(result)
-                    } else {
-                        let mut result = b;
-                        for (idx, value) in result.iter_mut().enumerate() {
-                            if idx < a.len() {
-                                *value = a[idx] - *value;
-                            } else {
-                                *value = -*value;
-                            }
-                        }
-                        ExprResult::Vector(result)
-                    }
-                }
-            }
-            (ExprResult::Number(a), ExprResult::Vector(b)) => {
-                let mut result = vec![0_f64; b.len()];
-                for (idx, value) in result.iter_mut().enumerate() {
-                    *value = a - b[idx];
-                }
-                ExprResult::Vector(result)
-            }
-            (ExprResult::Vector(a), ExprResult::Number(b)) => {
-                let mut result = a;
-                for value in result.iter_mut() {
-                    *value -= b;
-                }
-                ExprResult::Vector(result)
-            }
-            _ => panic!("Invalid operation"),
-        }
+        arithmetic(self, other, |a, b| a - b, 0.0, "subtract")
     }
 }
 
-impl std::ops::Mul for ExprResult {
+impl std::ops::Mul for &ExprResult {
     type Output = ExprResult;
 
     fn mul(self, other: Self) -> Self::Output {
-        match (self, other) {
-            (ExprResult::Number(a), ExprResult::Number(b)) => ExprResult::Number(a * b),
-            (ExprResult::Vector(a), ExprResult::Vector(b)) => {
-                let len_a = a.len();
-                let len_b = b.len();
-                if len_a == len_b {
-                    let mut result = Vec::with_capacity(len_a);
-                    for i in 0..len_a {
-                        result.push(a[i] * b[i]);
-                    }
-                    ExprResult::Vector(result)
-                } else {
-                    warn!(
-                        "Trying to multiply arrays of different lengths: {} and {}",
-                        len_a, len_b
-                    );
-                    if len_a > len_b {
-                        let mut result = a;
-                        for (idx, value) in b.iter().enumerate() {
-                            result[idx] *= value;
-                        }
-                        ExprResult::Vector(result)
-                    } else {
-                        let mut result = b;
-                        for (idx, value) in a.iter().enumerate() {
-                            result[idx] *= value;
-                        }
-                        ExprResult::Vector(result)
-                    }
-                }
-            }
-            (ExprResult::Number(a), ExprResult::Vector(b)) => {
-                let mut result = b.clone();
-                for value in result.iter_mut() {
-                    *value *= a;
-                }
-                ExprResult::Vector(result)
-            }
-            (ExprResult::Vector(a), ExprResult::Number(b)) => {
-                let mut result = a.clone();
-                for value in result.iter_mut() {
-                    *value *= b;
-                }
-                ExprResult::Vector(result)
-            }
-            _ => panic!("Invalid operation"),
-        }
+        arithmetic(self, other, |a, b| a * b, 1.0, "multiply")
     }
 }
 
-impl std::ops::Div for ExprResult {
+impl std::ops::Div for &ExprResult {
     type Output = ExprResult;
 
     fn div(self, other: Self) -> Self::Output {
-        match (self, other) {
-            (ExprResult::Number(a), ExprResult::Number(b)) => ExprResult::Number(a / b),
-            (ExprResult::Vector(a), ExprResult::Vector(b)) => {
-                let len_a = a.len();
-                let len_b = b.len();
-                if len_a == len_b {
-                    let mut result = Vec::with_capacity(len_a);
-                    for i in 0..len_a {
-                        result.push(a[i] / b[i]);
-                    }
-                    ExprResult::Vector(result)
-                } else {
-                    warn!(
-                        "Trying to divide arrays of different lengths: {} and {}",
-                        len_a, len_b
-                    );
-                    if len_a > len_b {
-                        let mut result = a;
-                        for (idx, value) in b.iter().enumerate() {
-                            result[idx] /= value;
-                        }
-                        ExprResult::Vector(result)
-                    } else {
-                        let mut result = b;
-                        for (idx, value) in result.iter_mut().enumerate() {
-                            if idx < a.len() {
-                                *value = a[idx] / *value;
-                            } else {
-                                *value = 1_f64 / *value;
-                            }
-                        }
-                        ExprResult::Vector(result)
-                    }
-                }
-            }
-            (ExprResult::Number(a), ExprResult::Vector(b)) => {
-                let mut result = vec![0_f64; b.len()];
-                for (idx, value) in result.iter_mut().enumerate() {
-                    *value = a / b[idx];
-                }
-                ExprResult::Vector(result)
-            }
-            (ExprResult::Vector(a), ExprResult::Number(b)) => {
-                let mut result = a;
-                for value in result.iter_mut() {
-                    *value /= b;
-                }
-                ExprResult::Vector(result)
-            }
-            _ => panic!("Invalid operation"),
-        }
+        arithmetic(self, other, |a, b| a / b, 1.0, "divide")
     }
 }
 
@@ -294,6 +190,9 @@ impl ExprResult {
                         vv.iter().map(|n| crate::output::float_string(n)).collect(),
                     );
                 }
+                ExprResult::Number(n) => {
+                    *self = ExprResult::Str(crate::output::float_string(n));
+                }
                 _ => panic!("Unable to join objects others than strings"),
             },
             ExprResult::StrVector(v) => match other {
@@ -332,6 +231,10 @@ impl ExprResult {
                 ExprResult::Str(s) => {
                     *v = v.iter().map(|a| format!("{}{}", a, s)).collect();
                 }
+                ExprResult::Number(n) => {
+                    let n = crate::output::float_string(n);
+                    *v = v.iter().map(|a| format!("{}{}", a, n)).collect();
+                }
                 _ => panic!("Unable to join objects others than strings"),
             },
             ExprResult::Str(s) => match other {
@@ -359,102 +262,286 @@ impl ExprResult {
     }
 }
 
-impl<'input> Expr<'input> {
-    /// Check that all macros exist in the collected results
-    pub fn validate_macros(&self, collect: &Vec<SnmpResult>) -> Result<(), String> {
+impl ExprResult {
+    /// Returns `true` if the element `idx` of this result is true, i.e. a
+    /// number different from 0. A scalar applies to every index.
+    pub fn is_true_at(&self, idx: usize) -> bool {
         match self {
-            Expr::Id(key) => {
-                let k = str::from_utf8(key).unwrap();
-                for result in collect {
-                    if result.items.contains_key(k) {
-                        return Ok(());
-                    }
+            ExprResult::Number(n) => is_true(*n),
+            ExprResult::Vector(v) => v.get(idx).is_some_and(|n| is_true(*n)),
+            _ => false,
+        }
+    }
+
+    /// Returns the numeric values of this result and whether it is a scalar,
+    /// or `None` if it holds strings.
+    fn numbers(&self) -> Option<(&[f64], bool)> {
+        match self {
+            ExprResult::Number(n) => Some((std::slice::from_ref(n), true)),
+            ExprResult::Vector(v) => Some((v, false)),
+            ExprResult::Empty => Some((&[], false)),
+            _ => None,
+        }
+    }
+
+    /// Returns the values of this result as strings and whether it is a scalar.
+    fn strings(&self) -> (Cow<'_, [String]>, bool) {
+        match self {
+            ExprResult::Str(s) => (Cow::Borrowed(std::slice::from_ref(s)), true),
+            ExprResult::StrVector(v) => (Cow::Borrowed(v), false),
+            ExprResult::Number(n) => (Cow::Owned(vec![crate::output::float_string(n)]), true),
+            ExprResult::Vector(v) => (
+                Cow::Owned(v.iter().map(crate::output::float_string).collect()),
+                false,
+            ),
+            ExprResult::Empty => (Cow::Borrowed(&[]), false),
+        }
+    }
+}
+
+fn is_true(n: f64) -> bool {
+    n != 0.0 && !n.is_nan()
+}
+
+fn bool_value(b: bool) -> f64 {
+    if b { 1.0 } else { 0.0 }
+}
+
+/// Applies `f` element-wise on two operands, a scalar operand being applied
+/// to every element of the other one. The result is a boolean as a number
+/// (`1` or `0`), scalar only if both operands are scalars.
+fn zip_with<T: ?Sized, U: std::borrow::Borrow<T>>(
+    left: (&[U], bool),
+    right: (&[U], bool),
+    f: impl Fn(&T, &T) -> bool,
+) -> ExprResult {
+    let (a, a_scalar) = left;
+    let (b, b_scalar) = right;
+    match (a_scalar, b_scalar) {
+        (true, true) => ExprResult::Number(bool_value(f(a[0].borrow(), b[0].borrow()))),
+        (true, false) => ExprResult::Vector(
+            b.iter()
+                .map(|y| bool_value(f(a[0].borrow(), y.borrow())))
+                .collect(),
+        ),
+        (false, true) => ExprResult::Vector(
+            a.iter()
+                .map(|x| bool_value(f(x.borrow(), b[0].borrow())))
+                .collect(),
+        ),
+        (false, false) => {
+            if a.len() != b.len() {
+                warn!(
+                    "Trying to compare arrays of different lengths: {} and {}",
+                    a.len(),
+                    b.len()
+                );
+            }
+            let len = a.len().max(b.len());
+            ExprResult::Vector(
+                (0..len)
+                    .map(|i| match (a.get(i), b.get(i)) {
+                        (Some(x), Some(y)) => bool_value(f(x.borrow(), y.borrow())),
+                        _ => 0.0,
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+impl Expr {
+    /// Check that all macros exist in the symbol table
+    pub fn validate_macros(&self, symbols: &Symbols) -> Result<(), String> {
+        match self {
+            Expr::Id(k) => {
+                if symbols.contains(k) {
+                    Ok(())
+                } else {
+                    Err(format!("Undefined macro in expression: {{{}}}", k))
                 }
-                Err(format!("Undefined macro in expression: {{{}}}", k))
             }
             Expr::Number(_) => Ok(()),
             Expr::OpPlus(left, right)
             | Expr::OpMinus(left, right)
             | Expr::OpStar(left, right)
             | Expr::OpSlash(left, right) => {
-                left.validate_macros(collect)?;
-                right.validate_macros(collect)?;
+                left.validate_macros(symbols)?;
+                right.validate_macros(symbols)?;
                 Ok(())
             }
-            Expr::Fn(_, expr) => expr.validate_macros(collect),
+            Expr::Fn(_, expr) | Expr::Match(expr, _, _) | Expr::Not(expr) => {
+                expr.validate_macros(symbols)
+            }
+            Expr::Str(_) => Ok(()),
+            Expr::Cmp(_, left, right) | Expr::And(left, right) | Expr::Or(left, right) => {
+                left.validate_macros(symbols)?;
+                right.validate_macros(symbols)
+            }
         }
     }
 
-    /// Recursively evaluates this expression against the collected SNMP results.
+    /// Appends the identifiers referenced by this expression to `ids`.
+    pub fn identifiers<'a>(&'a self, ids: &mut Vec<&'a str>) {
+        match self {
+            Expr::Id(k) => ids.push(k),
+            Expr::Number(_) | Expr::Str(_) => {}
+            Expr::OpPlus(left, right)
+            | Expr::OpMinus(left, right)
+            | Expr::OpStar(left, right)
+            | Expr::OpSlash(left, right)
+            | Expr::Cmp(_, left, right)
+            | Expr::And(left, right)
+            | Expr::Or(left, right) => {
+                left.identifiers(ids);
+                right.identifiers(ids);
+            }
+            Expr::Fn(_, expr) | Expr::Match(expr, _, _) | Expr::Not(expr) => expr.identifiers(ids),
+        }
+    }
+
+    /// Recursively evaluates this expression against the symbol table.
     ///
-    /// Resolves identifiers by searching through the `collect` vector, applies
-    /// operators element-wise for vectors, and evaluates functions.
-    pub fn eval(&self, collect: &Vec<SnmpResult>) -> Result<ExprResult, String> {
+    /// Resolves identifiers in `symbols`, applies operators element-wise for
+    /// vectors, and evaluates functions.
+    pub fn eval(&self, symbols: &Symbols) -> Result<ExprResult, String> {
+        self.value(symbols).map(Cow::into_owned)
+    }
+
+    /// Evaluates this expression, borrowing the values of identifiers from
+    /// `symbols` rather than copying them.
+    fn value<'s>(&self, symbols: &'s Symbols) -> Result<Cow<'s, ExprResult>, String> {
+        let owned = |result: ExprResult| Ok(Cow::Owned(result));
         match self {
-            Expr::Number(n) => Ok(ExprResult::Number(*n)),
-            Expr::Id(key) => {
-                let k = str::from_utf8(key).unwrap();
-                for result in collect {
-                    match result.items.get(k) {
-                        Some(item) => match item {
-                            ExprResult::Vector(n) => {
-                                if n.len() == 1 {
-                                    info!("ID '{}' has value {}", k, n[0]);
-                                    return Ok(ExprResult::Number(n[0]));
-                                } else {
-                                    info!("ID '{}' has value {:?}", k, n);
-                                    return Ok(ExprResult::Vector(n.clone()));
-                                }
-                            }
-                            _ => panic!("Should be a number"),
-                        },
-                        None => continue,
+            Expr::Number(n) => owned(ExprResult::Number(*n)),
+            Expr::Id(k) => match symbols.get(k) {
+                Some(ExprResult::Vector(n)) if n.len() == 1 => {
+                    info!("ID '{}' has value {}", k, n[0]);
+                    owned(ExprResult::Number(n[0]))
+                }
+                Some(ExprResult::StrVector(n)) if n.len() == 1 => {
+                    info!("ID '{}' has value {:?}", k, n);
+                    owned(ExprResult::Str(n[0].clone()))
+                }
+                Some(item) => {
+                    info!("ID '{}' has value {:?}", k, item);
+                    Ok(Cow::Borrowed(item))
+                }
+                None => owned(ExprResult::Number(0.0)),
+            },
+            Expr::OpPlus(left, right)
+            | Expr::OpMinus(left, right)
+            | Expr::OpStar(left, right)
+            | Expr::OpSlash(left, right) => {
+                let (left, right) = (left.value(symbols)?, right.value(symbols)?);
+                if left.numbers().is_none() || right.numbers().is_none() {
+                    return Err("Arithmetic operators only apply to numbers".to_string());
+                }
+                let (a, b) = (left.as_ref(), right.as_ref());
+                owned(match self {
+                    Expr::OpPlus(_, _) => a + b,
+                    Expr::OpMinus(_, _) => a - b,
+                    Expr::OpStar(_, _) => a * b,
+                    _ => a / b,
+                })
+            }
+            Expr::Str(s) => owned(ExprResult::Str(s.clone())),
+            Expr::Cmp(op, left, right) => {
+                let left = left.value(symbols)?;
+                let right = right.value(symbols)?;
+                match (left.numbers(), right.numbers()) {
+                    (Some(a), Some(b)) => owned(zip_with(a, b, |x: &f64, y: &f64| op.apply(x, y))),
+                    _ => {
+                        let (a, a_scalar) = left.strings();
+                        let (b, b_scalar) = right.strings();
+                        owned(zip_with(
+                            (&a, a_scalar),
+                            (&b, b_scalar),
+                            |x: &str, y: &str| op.apply(x, y),
+                        ))
                     }
                 }
-                Ok(ExprResult::Number(0.0))
             }
-            Expr::OpPlus(left, right) => Ok(left.eval(collect)? + right.eval(collect)?),
-            Expr::OpMinus(left, right) => Ok(left.eval(collect)? - right.eval(collect)?),
-            Expr::OpStar(left, right) => Ok(left.eval(collect)? * right.eval(collect)?),
-            Expr::OpSlash(left, right) => Ok(left.eval(collect)? / right.eval(collect)?),
+            Expr::Match(expr, re, negate) => {
+                let value = expr.value(symbols)?;
+                let (v, scalar) = value.strings();
+                let matches = v
+                    .iter()
+                    .map(|s| bool_value(re.is_match(s) != *negate))
+                    .collect::<Vec<f64>>();
+                if scalar {
+                    owned(ExprResult::Number(matches[0]))
+                } else {
+                    owned(ExprResult::Vector(matches))
+                }
+            }
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                let or = matches!(self, Expr::Or(_, _));
+                let (left, right) = (left.value(symbols)?, right.value(symbols)?);
+                match (left.numbers(), right.numbers()) {
+                    (Some(a), Some(b)) => owned(zip_with(a, b, |x: &f64, y: &f64| {
+                        if or {
+                            is_true(*x) || is_true(*y)
+                        } else {
+                            is_true(*x) && is_true(*y)
+                        }
+                    })),
+                    _ => Err("Logical operators only apply to numbers".to_string()),
+                }
+            }
+            Expr::Not(expr) => match expr.value(symbols)?.as_ref() {
+                ExprResult::Number(n) => owned(ExprResult::Number(bool_value(!is_true(*n)))),
+                ExprResult::Vector(v) => owned(ExprResult::Vector(
+                    v.iter().map(|n| bool_value(!is_true(*n))).collect(),
+                )),
+                _ => Err("Logical operators only apply to numbers".to_string()),
+            },
             Expr::Fn(func, expr) => {
-                let v = expr.eval(collect)?;
+                let v = expr.value(symbols)?;
                 match func {
-                    Func::Average => match v {
-                        ExprResult::Number(n) => Ok(ExprResult::Number(n)),
+                    Func::Average => match v.as_ref() {
+                        ExprResult::Number(n) => owned(ExprResult::Number(*n)),
                         ExprResult::Vector(v) => {
                             let mut sum = 0.0;
                             let mut count = 0;
-                            for value in v {
+                            for &value in v {
                                 if !value.is_nan() {
                                     sum += value;
                                     count += 1;
                                 }
                             }
                             if count > 0 {
-                                Ok(ExprResult::Number(sum / count as f64))
+                                owned(ExprResult::Number(sum / count as f64))
                             } else {
-                                Ok(ExprResult::Number(f64::NAN))
+                                owned(ExprResult::Number(f64::NAN))
                             }
                         }
                         _ => panic!("Invalid operation"),
                     },
-                    Func::Min => match v {
-                        ExprResult::Number(n) => Ok(ExprResult::Number(n)),
+                    Func::Min => match v.as_ref() {
+                        ExprResult::Number(n) => owned(ExprResult::Number(*n)),
                         ExprResult::Vector(v) => {
                             let min = v.iter().cloned().fold(f64::INFINITY, f64::min);
-                            Ok(ExprResult::Number(min))
+                            owned(ExprResult::Number(min))
                         }
                         _ => panic!("Invalid operation"),
                     },
-                    Func::Max => match v {
-                        ExprResult::Number(n) => Ok(ExprResult::Number(n)),
+                    Func::Max => match v.as_ref() {
+                        ExprResult::Number(n) => owned(ExprResult::Number(*n)),
                         ExprResult::Vector(v) => {
                             let max = v.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
-                            Ok(ExprResult::Number(max))
+                            owned(ExprResult::Number(max))
                         }
                         _ => panic!("Invalid operation"),
                     },
+                    Func::Count => match v.as_ref() {
+                        ExprResult::Number(_) | ExprResult::Str(_) => {
+                            owned(ExprResult::Number(1.0))
+                        }
+                        ExprResult::Vector(v) => owned(ExprResult::Number(v.len() as f64)),
+                        ExprResult::StrVector(v) => owned(ExprResult::Number(v.len() as f64)),
+                        _ => owned(ExprResult::Number(0.0)),
+                    },
                 }
             }
         }
@@ -463,7 +550,7 @@ impl<'input> Expr<'input> {
     /// Returns the byte representation of an identifier expression, or a default error message.
     pub fn eval_as_str(&self) -> &[u8] {
         if let Expr::Id(id) = self {
-            return id;
+            return id.as_bytes();
         } else {
             return b"Bad value";
         }
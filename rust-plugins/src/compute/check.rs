@@ -0,0 +1,437 @@
+//! Static type and shape checking of expressions and templates.
+//!
+//! Used by `--check-format` to find misuses that dummy values cannot reveal:
+//! arithmetic on strings, numbers compared with strings, vectors of
+//! different tables combined element-wise, and so on. The [`Checker`]
+//! collects every problem instead of stopping at the first one.
+
+use crate::compute::ast::{Expr, Func};
+use crate::compute::{CompiledValue, Expression, Metric, Template};
+use std::collections::HashMap;
+
+/// The kind of the elements of a value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Number,
+    Str,
+    /// Collected values whose kind depends on the agent; it is inferred from
+    /// how expressions use them.
+    Unknown,
+}
+
+impl Kind {
+    fn as_str(&self) -> &str {
+        match self {
+            Kind::Number => "a number",
+            Kind::Str => "a string",
+            Kind::Unknown => "a value",
+        }
+    }
+}
+
+/// Whether a value is a single element or one element per instance of a
+/// table. Vectors of different tables may have different lengths.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Scalar,
+    /// One element per row of the named table (a walked entry, or a filtered
+    /// metric).
+    Table(String),
+}
+
+/// The inferred type of a value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Type {
+    pub kind: Kind,
+    pub shape: Shape,
+}
+
+impl Type {
+    pub fn new(kind: Kind, shape: Shape) -> Type {
+        Type { kind, shape }
+    }
+
+    fn scalar(kind: Kind) -> Type {
+        Type::new(kind, Shape::Scalar)
+    }
+}
+
+/// Infers the types of expressions and templates over declared values.
+#[derive(Debug, Default)]
+pub struct Checker {
+    types: HashMap<String, Type>,
+    /// Kinds inferred for values of unknown kind, with the context of their
+    /// first use.
+    uses: HashMap<String, (Kind, String)>,
+    problems: Vec<String>,
+}
+
+impl Checker {
+    pub fn new() -> Checker {
+        Checker::default()
+    }
+
+    /// Declares a value that expressions may reference.
+    pub fn declare(&mut self, name: String, t: Type) {
+        self.types.insert(name, t);
+    }
+
+    /// Records a problem found in `context` (e.g. `Metric "cpu", field "value"`).
+    pub fn report(&mut self, context: &str, problem: String) {
+        self.problems.push(format!("{}: {}", context, problem));
+    }
+
+    /// Returns every problem reported so far.
+    pub fn problems(self) -> Vec<String> {
+        self.problems
+    }
+
+    /// Infers the type of `expr`, reporting its problems under `context`.
+    pub fn expression(&mut self, context: &str, expr: &Expression) -> Type {
+        self.infer(context, expr.ast())
+    }
+
+    /// Infers the type of `template`, reporting undefined macros under `context`.
+    ///
+    /// A template is a string, unless it only holds macros which are all numbers.
+    pub fn template(&mut self, context: &str, template: &Template) -> Type {
+        let mut kind = if template.has_text() {
+            Kind::Str
+        } else {
+            Kind::Number
+        };
+        let mut shape = Shape::Scalar;
+        for name in template.macros() {
+            let t = self.lookup(context, name);
+            kind = match (kind, t.kind) {
+                (Kind::Number, Kind::Number) => Kind::Number,
+                (Kind::Str, _) | (_, Kind::Str) => Kind::Str,
+                _ => Kind::Unknown,
+            };
+            shape = self.combine(context, "joins", shape, t.shape);
+        }
+        Type::new(kind, shape)
+    }
+
+    /// Reports a problem unless `shape` is a scalar or `expected`.
+    pub fn same_table(&mut self, context: &str, shape: &Shape, expected: &Shape) {
+        if let (Shape::Table(t), Shape::Table(e)) = (shape, expected) {
+            if t != e {
+                self.report(
+                    context,
+                    format!(
+                        "has one value per instance of '{}' but the metric has one per instance of '{}'",
+                        t, e
+                    ),
+                );
+            }
+        } else if let (Shape::Table(t), Shape::Scalar) = (shape, expected) {
+            self.report(
+                context,
+                format!(
+                    "has one value per instance of '{}' but the metric has a single value",
+                    t
+                ),
+            );
+        }
+    }
+
+    /// Checks a compiled metric (`kind` is `"Metric"` or `"Aggregation"`) and
+    /// declares its `metrics.<name>` or `aggregations.<name>` value.
+    pub fn metric(&mut self, kind: &str, metric: &Metric) {
+        let Ok(compiled) = metric.compiled() else {
+            return;
+        };
+        let field = |field: &str| format!("{} \"{}\", field \"{}\"", kind, metric.name, field);
+        let (value_kind, shape) = match &compiled.value {
+            CompiledValue::Number { value, .. } => {
+                let context = field("value");
+                let t = self.expression(&context, value);
+                self.expect(&context, "the metric value", value.ast(), &t, Kind::Number);
+                (Kind::Number, t.shape)
+            }
+            CompiledValue::Status {
+                value,
+                warning,
+                critical,
+            } => {
+                let t = self.template(&field("value"), value);
+                for (name, expr) in [("warning", warning), ("critical", critical)] {
+                    if let Some(expr) = expr {
+                        let context = field(name);
+                        let e = self.expression(&context, expr);
+                        self.expect(&context, "a status condition", expr.ast(), &e, Kind::Number);
+                        self.same_table(&context, &e.shape, &t.shape);
+                    }
+                }
+                (Kind::Str, t.shape)
+            }
+        };
+        let namespace = if kind == "Aggregation" {
+            if let Shape::Table(table) = &shape {
+                self.report(
+                    &field("value"),
+                    format!(
+                        "has one value per instance of '{}', use Average(), Min() or Max()",
+                        table
+                    ),
+                );
+            }
+            "aggregations"
+        } else {
+            "metrics"
+        };
+        if let (Some(prefix), Shape::Table(_)) = (&compiled.prefix, &shape) {
+            let context = field("prefix");
+            let t = self.template(&context, prefix);
+            if t.kind == Kind::Number {
+                self.report(
+                    &context,
+                    "evaluates to a number, expected a string".to_string(),
+                );
+            }
+            if t.shape == Shape::Scalar && prefix.macros().next().is_some() {
+                self.report(
+                    &context,
+                    "has a single value but the metric has one per instance".to_string(),
+                );
+            }
+            self.same_table(&context, &t.shape, &shape);
+        }
+        for (name, expr) in [
+            ("min_expr", &compiled.min),
+            ("max_expr", &compiled.max),
+            ("filter", &compiled.filter),
+        ] {
+            if let Some(expr) = expr {
+                let context = field(name);
+                let t = self.expression(&context, expr);
+                self.expect(&context, name, expr.ast(), &t, Kind::Number);
+                self.same_table(&context, &t.shape, &shape);
+            }
+        }
+        let shape = match shape {
+            Shape::Table(_) if namespace == "aggregations" => Shape::Scalar,
+            Shape::Table(_) if compiled.filter.is_some() => {
+                Shape::Table(format!("{}.{}", namespace, metric.name))
+            }
+            shape => shape,
+        };
+        self.declare(
+            format!("{}.{}", namespace, metric.name),
+            Type::new(value_kind, shape),
+        );
+    }
+
+    fn lookup(&mut self, context: &str, name: &str) -> Type {
+        match self.types.get(name) {
+            Some(t) => t.clone(),
+            None => {
+                self.report(context, format!("undefined macro {{{}}}", name));
+                Type::scalar(Kind::Unknown)
+            }
+        }
+    }
+
+    /// Merges the shapes of two operands combined element-wise.
+    fn combine(&mut self, context: &str, what: &str, a: Shape, b: Shape) -> Shape {
+        match (a, b) {
+            (Shape::Scalar, b) => b,
+            (a, Shape::Scalar) => a,
+            (Shape::Table(a), Shape::Table(b)) => {
+                if a != b {
+                    self.report(
+                        context,
+                        format!(
+                            "{} vectors of '{}' and '{}', which may have different lengths",
+                            what, a, b
+                        ),
+                    );
+                }
+                Shape::Table(a)
+            }
+        }
+    }
+
+    /// Checks that `expr`, of type `t`, can be used as `kind`. An identifier of
+    /// unknown kind is inferred to be of this kind.
+    pub fn expect(&mut self, context: &str, what: &str, expr: &Expr, t: &Type, kind: Kind) {
+        if t.kind == Kind::Unknown {
+            if let Expr::Id(name) = expr {
+                match self.uses.get(name) {
+                    Some((used, first)) if *used != kind => {
+                        let problem = format!(
+                            "{{{}}} is used as {} while it is used as {} by {}",
+                            name,
+                            kind.as_str(),
+                            used.as_str(),
+                            first
+                        );
+                        self.report(context, problem);
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.uses.insert(name.clone(), (kind, context.to_string()));
+                    }
+                }
+            }
+        } else if t.kind != kind {
+            self.report(
+                context,
+                format!(
+                    "{} expects {}, got {}",
+                    what,
+                    kind.as_str(),
+                    t.kind.as_str()
+                ),
+            );
+        }
+    }
+
+    fn infer(&mut self, context: &str, expr: &Expr) -> Type {
+        match expr {
+            Expr::Number(_) => Type::scalar(Kind::Number),
+            Expr::Str(_) => Type::scalar(Kind::Str),
+            Expr::Id(name) => self.lookup(context, name),
+            Expr::OpPlus(l, r) | Expr::OpMinus(l, r) | Expr::OpStar(l, r) | Expr::OpSlash(l, r) => {
+                let op = match expr {
+                    Expr::OpPlus(_, _) => "operator '+'",
+                    Expr::OpMinus(_, _) => "operator '-'",
+                    Expr::OpStar(_, _) => "operator '*'",
+                    _ => "operator '/'",
+                };
+                self.numbers(context, op, l, r)
+            }
+            Expr::And(l, r) => self.numbers(context, "operator '&&'", l, r),
+            Expr::Or(l, r) => self.numbers(context, "operator '||'", l, r),
+            Expr::Not(e) => {
+                let t = self.infer(context, e);
+                self.expect(context, "operator '!'", e, &t, Kind::Number);
+                Type::new(Kind::Number, t.shape)
+            }
+            Expr::Fn(func, e) => {
+                let name = match func {
+                    Func::Average => "Average()",
+                    Func::Min => "Min()",
+                    Func::Max => "Max()",
+                    Func::Count => "Count()",
+                };
+                let t = self.infer(context, e);
+                if !matches!(func, Func::Count) {
+                    self.expect(context, name, e, &t, Kind::Number);
+                }
+                Type::scalar(Kind::Number)
+            }
+            Expr::Cmp(_, l, r) => {
+                let (lt, rt) = (self.infer(context, l), self.infer(context, r));
+                match (lt.kind, rt.kind) {
+                    (Kind::Number, Kind::Str) | (Kind::Str, Kind::Number) => {
+                        self.report(context, "compares a number with a string".to_string())
+                    }
+                    (Kind::Unknown, kind) if kind != Kind::Unknown => {
+                        self.expect(context, "comparison", l, &lt, kind)
+                    }
+                    (kind, Kind::Unknown) if kind != Kind::Unknown => {
+                        self.expect(context, "comparison", r, &rt, kind)
+                    }
+                    _ => {}
+                }
+                let shape = self.combine(context, "compares", lt.shape, rt.shape);
+                Type::new(Kind::Number, shape)
+            }
+            Expr::Match(e, _, _) => {
+                let t = self.infer(context, e);
+                Type::new(Kind::Number, t.shape)
+            }
+        }
+    }
+
+    /// Infers an element-wise operation on two numbers.
+    fn numbers(&mut self, context: &str, op: &str, l: &Expr, r: &Expr) -> Type {
+        let (lt, rt) = (self.infer(context, l), self.infer(context, r));
+        self.expect(context, op, l, &lt, Kind::Number);
+        self.expect(context, op, r, &rt, Kind::Number);
+        let shape = self.combine(context, "combines", lt.shape, rt.shape);
+        Type::new(Kind::Number, shape)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_check_expressions() {
+        use crate::compute::Expression;
+        use crate::compute::check::{Checker, Kind, Shape, Type};
+
+        let mut checker = Checker::new();
+        let table = |name: &str| Shape::Table(name.to_string());
+        checker.declare("if.in".to_string(), Type::new(Kind::Unknown, table("if")));
+        checker.declare("if.status".to_string(), Type::new(Kind::Str, table("if")));
+        checker.declare(
+            "disk.used".to_string(),
+            Type::new(Kind::Unknown, table("disk")),
+        );
+        checker.declare(
+            "uptime".to_string(),
+            Type::new(Kind::Unknown, Shape::Scalar),
+        );
+
+        let mut infer = |expr: &str| checker.expression(expr, &Expression::parse(expr).unwrap());
+        assert_eq!(
+            infer("{if.in} * 8 / {uptime}"),
+            Type::new(Kind::Number, table("if"))
+        );
+        assert_eq!(
+            infer("Average({disk.used})"),
+            Type::new(Kind::Number, Shape::Scalar)
+        );
+        infer("{if.status} + 1");
+        infer("{if.in} + {disk.used}");
+        infer("{if.status} == 2");
+        infer("{uptime} == 'long'");
+        infer("{foo}");
+        assert_eq!(
+            checker.problems(),
+            vec![
+                "{if.status} + 1: operator '+' expects a number, got a string",
+                "{if.in} + {disk.used}: combines vectors of 'if' and 'disk', which may have different lengths",
+                "{if.status} == 2: compares a number with a string",
+                "{uptime} == 'long': {uptime} is used as a string while it is used as a number by {if.in} * 8 / {uptime}",
+                "{foo}: undefined macro {foo}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_template() {
+        use crate::generic::Command;
+        use crate::snmp::snmpwalk::SnmpWalk;
+
+        let mut cmd: Command = serde_json::from_str(
+            r#"{
+              "collect": { "snmp": [
+                { "name": "tcp", "oid": "1.3.6.1.2.1.6.9.0", "query": "Get" }
+              ] },
+              "compute": { "metrics": [ { "name": "tcp", "value": "{tcp}" } ] },
+              "output": { "ok": "{metrics.tcp} connections established" }
+            }"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        assert!(cmd.check().is_empty(), "{:?}", cmd.check());
+
+        // A scalar number joined with text is printed like a number
+        let walk = SnmpWalk::parse(".1.3.6.1.2.1.6.9.0 = Gauge32: 12").unwrap();
+        let collect = cmd.replay_collect(&walk, &[], &[]).unwrap();
+        let result = cmd.evaluate(collect, &[], &[], false).unwrap();
+        assert!(
+            result.output.starts_with("12 connections established |"),
+            "{}",
+            result.output
+        );
+        assert!(
+            cmd.execute("", "2c", "public", &vec![], &vec![], true)
+                .is_ok()
+        );
+    }
+}
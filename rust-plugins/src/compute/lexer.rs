@@ -29,15 +29,46 @@ pub enum Tok<'input> {
     LBrace,
     /// Right brace `}`.
     RBrace,
+    /// A string literal between double or single quotes, without its quotes.
+    Str(&'input str),
+    /// A regex literal `/.../` following `=~` or `!~`, without its slashes.
+    Regex(&'input str),
+    /// Equality operator `==`.
+    OpEq,
+    /// Inequality operator `!=`.
+    OpNe,
+    /// Comparison operator `<`.
+    OpLt,
+    /// Comparison operator `<=`.
+    OpLe,
+    /// Comparison operator `>`.
+    OpGt,
+    /// Comparison operator `>=`.
+    OpGe,
+    /// Regex match operator `=~`.
+    OpMatch,
+    /// Regex non-match operator `!~`.
+    OpNotMatch,
+    /// Logical and `&&`.
+    OpAnd,
+    /// Logical or `||`.
+    OpOr,
+    /// Logical not `!`.
+    OpNot,
 }
 
-/// Errors that can occur during lexical analysis.
+/// Errors that can occur during lexical analysis, with the offsets of the
+/// offending characters.
 #[derive(Debug, PartialEq)]
 pub enum LexicalError {
-    /// A character that cannot be tokenized was encountered.
-    NotPossible,
+    /// A character that cannot be tokenized was encountered at this offset.
+    NotPossible(usize),
     /// A brace was not properly matched (currently unused).
     UnmatchedBrace,
+    /// The string or regex literal starting at this offset is not terminated.
+    Unterminated(usize),
+    /// The literal between these offsets is not a valid regular expression.
+    BadRegex(usize, usize),
 }
 
 /// Tokenizes an expression string into a sequence of tokens.
@@ -45,6 +76,8 @@ pub enum LexicalError {
 pub struct Lexer<'input> {
     chars: &'input str,
     offset: usize,
+    /// True right after `=~` or `!~`, where a `/` starts a regex literal.
+    expect_regex: bool,
 }
 
 impl<'input> Lexer<'input> {
@@ -53,6 +86,65 @@ impl<'input> Lexer<'input> {
         Lexer {
             chars: input,
             offset: 0,
+            expect_regex: false,
+        }
+    }
+
+    /// Consumes a literal from `start` (its opening delimiter) up to the next
+    /// unescaped `delimiter`.
+    fn literal(
+        &mut self,
+        start: usize,
+        delimiter: u8,
+    ) -> Option<Spanned<Tok<'input>, usize, LexicalError>> {
+        let chars = self.chars.as_bytes();
+        let mut escaped = false;
+        for (i, c) in chars.iter().enumerate().skip(start + 1) {
+            if escaped {
+                escaped = false;
+            } else if *c == b'\\' {
+                escaped = true;
+            } else if *c == delimiter {
+                self.offset = i + 1;
+                let value = &self.chars[(start + 1)..i];
+                trace!(
+                    "Token literal from {} to {} with value '{}'",
+                    start,
+                    i + 1,
+                    value
+                );
+                let tok = if delimiter == b'/' {
+                    Tok::Regex(value)
+                } else {
+                    Tok::Str(value)
+                };
+                return Some(Ok((start, tok, i + 1)));
+            }
+        }
+        error!("Unterminated literal starting at {}", start);
+        self.offset = chars.len();
+        Some(Err(LexicalError::Unterminated(start)))
+    }
+
+    /// Returns a one or two characters operator, depending on whether `next`
+    /// follows the character at `i`.
+    fn operator(
+        &mut self,
+        i: usize,
+        next: u8,
+        double: Tok<'input>,
+        single: Option<Tok<'input>>,
+    ) -> Option<Spanned<Tok<'input>, usize, LexicalError>> {
+        if self.chars.as_bytes().get(i + 1) == Some(&next) {
+            self.offset = i + 2;
+            Some(Ok((i, double, i + 2)))
+        } else if let Some(tok) = single {
+            self.offset = i + 1;
+            Some(Ok((i, tok, i + 1)))
+        } else {
+            error!("Unknown operator at {}", i);
+            self.offset = i + 1;
+            Some(Err(LexicalError::NotPossible(i)))
         }
     }
 
@@ -116,9 +208,50 @@ impl<'input> Iterator for Lexer<'input> {
     type Item = Spanned<Tok<'input>, usize, LexicalError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let token = self.next_token();
+        self.expect_regex = matches!(
+            token,
+            Some(Ok((_, Tok::OpMatch, _))) | Some(Ok((_, Tok::OpNotMatch, _)))
+        );
+        token
+    }
+}
+
+impl<'input> Lexer<'input> {
+    fn next_token(&mut self) -> Option<Spanned<Tok<'input>, usize, LexicalError>> {
         for (i, c) in self.chars.as_bytes().iter().enumerate().skip(self.offset) {
             match *c {
                 b' ' | b'\t' => continue,
+                b'/' if self.expect_regex => {
+                    return self.literal(i, b'/');
+                }
+                b'"' | b'\'' => {
+                    return self.literal(i, *c);
+                }
+                b'=' => {
+                    if self.chars.as_bytes().get(i + 1) == Some(&b'~') {
+                        return self.operator(i, b'~', Tok::OpMatch, None);
+                    }
+                    return self.operator(i, b'=', Tok::OpEq, None);
+                }
+                b'!' => {
+                    if self.chars.as_bytes().get(i + 1) == Some(&b'~') {
+                        return self.operator(i, b'~', Tok::OpNotMatch, None);
+                    }
+                    return self.operator(i, b'=', Tok::OpNe, Some(Tok::OpNot));
+                }
+                b'<' => {
+                    return self.operator(i, b'=', Tok::OpLe, Some(Tok::OpLt));
+                }
+                b'>' => {
+                    return self.operator(i, b'=', Tok::OpGe, Some(Tok::OpGt));
+                }
+                b'&' => {
+                    return self.operator(i, b'&', Tok::OpAnd, None);
+                }
+                b'|' => {
+                    return self.operator(i, b'|', Tok::OpOr, None);
+                }
                 b'*' => {
                     self.offset = i + 1;
                     return Some(Ok((i, Tok::OpStar, i + 1)));
@@ -164,7 +297,7 @@ impl<'input> Iterator for Lexer<'input> {
                     // Unknown character
                     error!("Unknown character at {}: '{}'", i, *c as char);
                     self.offset = i + 1;
-                    return Some(Err(LexicalError::NotPossible));
+                    return Some(Err(LexicalError::NotPossible(i)));
                 }
             }
         }
@@ -173,8 +306,9 @@ impl<'input> Iterator for Lexer<'input> {
     }
 }
 
+#[cfg(test)]
 mod test {
-    use crate::compute::lexer::{Lexer, Tok};
+    use crate::compute::lexer::{Lexer, LexicalError, Tok};
 
     fn init() {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -213,4 +347,46 @@ mod test {
         assert_eq!(lexer.next(), Some(Ok((5, Tok::Num(3_f64), 6))));
         assert_eq!(lexer.next(), None);
     }
+
+    #[test]
+    fn test_lexer_comparison_and_string() {
+        init();
+        let input = "{a} == \"up\" && {b}!='down'";
+        let mut lexer = Lexer::new(input);
+        assert_eq!(lexer.next(), Some(Ok((0, Tok::LBrace, 1))));
+        assert_eq!(lexer.next(), Some(Ok((1, Tok::Id(b"a"), 2))));
+        assert_eq!(lexer.next(), Some(Ok((2, Tok::RBrace, 3))));
+        assert_eq!(lexer.next(), Some(Ok((4, Tok::OpEq, 6))));
+        assert_eq!(lexer.next(), Some(Ok((7, Tok::Str("up"), 11))));
+        assert_eq!(lexer.next(), Some(Ok((12, Tok::OpAnd, 14))));
+        assert_eq!(lexer.next(), Some(Ok((15, Tok::LBrace, 16))));
+        assert_eq!(lexer.next(), Some(Ok((16, Tok::Id(b"b"), 17))));
+        assert_eq!(lexer.next(), Some(Ok((17, Tok::RBrace, 18))));
+        assert_eq!(lexer.next(), Some(Ok((18, Tok::OpNe, 20))));
+        assert_eq!(lexer.next(), Some(Ok((20, Tok::Str("down"), 26))));
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn test_lexer_regex() {
+        init();
+        let input = "{a} !~ /^up\\/x/ / 2";
+        let mut lexer = Lexer::new(input);
+        assert_eq!(lexer.next(), Some(Ok((0, Tok::LBrace, 1))));
+        assert_eq!(lexer.next(), Some(Ok((1, Tok::Id(b"a"), 2))));
+        assert_eq!(lexer.next(), Some(Ok((2, Tok::RBrace, 3))));
+        assert_eq!(lexer.next(), Some(Ok((4, Tok::OpNotMatch, 6))));
+        assert_eq!(lexer.next(), Some(Ok((7, Tok::Regex("^up\\/x"), 15))));
+        assert_eq!(lexer.next(), Some(Ok((16, Tok::OpSlash, 17))));
+        assert_eq!(lexer.next(), Some(Ok((18, Tok::Num(2_f64), 19))));
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn test_lexer_unterminated_string() {
+        init();
+        let mut lexer = Lexer::new("\"up");
+        assert_eq!(lexer.next(), Some(Err(LexicalError::Unterminated(0))));
+        assert_eq!(lexer.next(), None);
+    }
 }
@@ -2,19 +2,24 @@
 //!
 //! This module provides a parser that evaluates mathematical expressions over
 //! SNMP-collected values, including support for arithmetic operations, functions
-//! (Average, Min, Max), and threshold parsing for alert conditions.
+//! (Average, Min, Max, Count), and threshold parsing for alert conditions.
 
 pub mod ast;
+pub mod check;
 pub mod lexer;
+pub mod symbols;
 pub mod threshold;
 
 use self::ast::ExprResult;
 use self::lexer::{LexicalError, Tok};
-use crate::snmp::SnmpResult;
+use self::symbols::Symbols;
+use self::threshold::InstanceThresholds;
+use crate::generic::error::Error;
 use lalrpop_util::{ParseError, lalrpop_mod};
 use log::{debug, trace};
-use regex::Regex;
+use schemars::JsonSchema;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 
 lalrpop_mod!(grammar);
 
@@ -23,10 +28,16 @@ lalrpop_mod!(grammar);
 /// The `value` expression is evaluated against collected SNMP data to produce
 /// a scalar or vector result. Optionally, min/max bounds and alert thresholds
 /// can be specified.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Metric {
     /// The name of the metric (appears in output).
     pub name: String,
+    /// Optional description of the metric, shown by `--help`.
+    pub description: Option<String>,
+    /// Whether the metric is numeric (default) or a textual status.
+    #[serde(rename = "type", default)]
+    pub kind: MetricType,
     /// Optional expression to generate a label/prefix for each element (used in vector metrics).
     pub prefix: Option<String>,
     /// The expression to evaluate (e.g., `"{snmp_oid} * 100"` or `"Average({vector})"`).
@@ -42,21 +53,90 @@ pub struct Metric {
     pub max_expr: Option<String>,
     /// Optional static maximum value.
     pub max: Option<f64>,
+    /// Optional boolean expression evaluated per instance (e.g.,
+    /// `"{disk.type} == 'hrStorageFixedDisk'"`). Instances evaluating to false
+    /// are dropped from perfdata, messages and from `{metrics.<name>}`.
+    pub filter: Option<String>,
     /// Identifier used with `--warning-<suffix>` and `--critical-<suffix>` CLI flags.
     #[serde(rename = "threshold-suffix")]
     pub threshold_suffix: Option<String>,
-    /// Warning threshold in Nagios format (e.g., `"10:20"`, `"@5:10"`).
+    /// Warning threshold in Nagios format (e.g., `"10:20"`, `"@5:10"`), or a
+    /// boolean expression for status metrics (e.g., `"{if.status} !~ /up/"`).
+    pub warning: Option<String>,
+    /// Critical threshold in Nagios format, or a boolean expression for status metrics.
+    pub critical: Option<String>,
+    /// Per-instance thresholds keyed by instance name, `re:<regex>` or `*`.
+    ///
+    /// `warning` and `critical` may also use the instance-keyed syntax
+    /// (e.g. `"/var=90,*=80"`); they take precedence over this map for
+    /// equally specific keys.
+    pub thresholds: Option<BTreeMap<String, InstanceThreshold>>,
+    /// Expressions parsed by [`Metric::compile`].
+    #[serde(skip)]
+    compiled: Option<CompiledMetric>,
+}
+
+/// Kind of value produced by a [`Metric`].
+#[derive(Deserialize, JsonSchema, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricType {
+    /// `value` is an arithmetic expression, reported in perfdata and checked
+    /// against Nagios thresholds.
+    #[default]
+    Number,
+    /// `value` is a string template (e.g., `"{if.status}"`), shown in messages
+    /// only; `warning` and `critical` are boolean expressions evaluated per
+    /// instance.
+    Status,
+}
+
+/// Warning and critical thresholds for the instances selected by a key of
+/// [`Metric::thresholds`].
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct InstanceThreshold {
+    /// Warning threshold in Nagios format.
     pub warning: Option<String>,
     /// Critical threshold in Nagios format.
     pub critical: Option<String>,
 }
 
+impl Metric {
+    /// Builds the warning and critical thresholds of this metric, ready to be
+    /// resolved for each instance.
+    ///
+    /// # Errors
+    /// Returns an error if a threshold or an instance key is invalid.
+    pub fn instance_thresholds(&self) -> Result<(InstanceThresholds, InstanceThresholds), Error> {
+        let mut warning = InstanceThresholds::new();
+        let mut critical = InstanceThresholds::new();
+        if let Some(w) = &self.warning {
+            warning.add_spec(w)?;
+        }
+        if let Some(c) = &self.critical {
+            critical.add_spec(c)?;
+        }
+        if let Some(thresholds) = &self.thresholds {
+            for (key, t) in thresholds {
+                if let Some(w) = &t.warning {
+                    warning.add(key, w)?;
+                }
+                if let Some(c) = &t.critical {
+                    critical.add(key, c)?;
+                }
+            }
+        }
+        Ok((warning, critical))
+    }
+}
+
 fn empty_string() -> String {
     "".to_string()
 }
 
 /// Describes all metrics and aggregations to compute from collected SNMP data.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Compute {
     /// The primary metrics, typically one per collected OID.
     pub metrics: Vec<Metric>,
@@ -64,22 +144,354 @@ pub struct Compute {
     pub aggregations: Option<Vec<Metric>>,
 }
 
-/// Parser for evaluating expressions against SNMP result data.
+/// An expression parsed once, ready to be evaluated against any symbol table.
+#[derive(Debug)]
+pub struct Expression {
+    expr: Box<ast::Expr>,
+}
+
+impl Expression {
+    /// Parses `expr` (e.g., `"{ifSpeed} / 1000000"`).
+    pub fn parse(expr: &str) -> Result<Expression, String> {
+        debug!("Parsing expression: {}", expr);
+        let lexer = lexer::Lexer::new(expr);
+        match grammar::ExprParser::new().parse(lexer) {
+            Ok(expr) => Ok(Expression { expr }),
+            Err(e) => Err(parse_error_message(expr, e)),
+        }
+    }
+
+    /// Evaluates the expression. In `check_format` mode, every macro must be
+    /// defined in `symbols`.
+    pub fn eval(&self, symbols: &Symbols, check_format: bool) -> Result<ExprResult, String> {
+        if check_format {
+            self.expr.validate_macros(symbols)?;
+        }
+        self.expr.eval(symbols)
+    }
+
+    /// Returns the syntax tree of the expression.
+    pub fn ast(&self) -> &ast::Expr {
+        &self.expr
+    }
+
+    /// Returns the names of the macros used by the expression.
+    pub fn macros(&self) -> Vec<&str> {
+        let mut ids = Vec::new();
+        self.expr.identifiers(&mut ids);
+        ids
+    }
+}
+
+/// Renders a parse error of `expr` as a message followed by the expression
+/// and a caret under the offending span, e.g.:
+///
+/// ```text
+/// unexpected '}' at column 7, expected one of '(', '{', a number, a string
+///     {a} + }
+///           ^
+/// ```
+fn parse_error_message(expr: &str, e: ParseError<usize, Tok, LexicalError>) -> String {
+    let quote = |start: usize, end: usize| expr.get(start..end).unwrap_or_default().to_string();
+    let (start, end, message, expected) = match e {
+        ParseError::InvalidToken { location } => {
+            (location, location + 1, "invalid token".to_string(), vec![])
+        }
+        ParseError::UnrecognizedEof { location, expected } => (
+            location,
+            location + 1,
+            "unexpected end of expression".to_string(),
+            expected,
+        ),
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            expected,
+        } => (
+            start,
+            end,
+            format!("unexpected '{}'", quote(start, end)),
+            expected,
+        ),
+        ParseError::ExtraToken {
+            token: (start, _, end),
+        } => (
+            start,
+            end,
+            format!(
+                "unexpected '{}' after the end of the expression",
+                quote(start, end)
+            ),
+            vec![],
+        ),
+        ParseError::User { error } => {
+            let (start, end, message) = match error {
+                LexicalError::NotPossible(i) => {
+                    let c = expr[i..].chars().next().unwrap_or(' ');
+                    (i, i + c.len_utf8(), format!("unexpected character '{}'", c))
+                }
+                LexicalError::Unterminated(i) => {
+                    (i, expr.len(), "unterminated literal".to_string())
+                }
+                LexicalError::BadRegex(start, end) => (
+                    start,
+                    end,
+                    format!("invalid regular expression {}", quote(start, end)),
+                ),
+                LexicalError::UnmatchedBrace => (0, expr.len(), "unmatched brace".to_string()),
+            };
+            (start, end, message, vec![])
+        }
+    };
+    let column = expr.get(..start).unwrap_or(expr).chars().count();
+    let width = expr.get(start..end).map_or(1, |s| s.chars().count().max(1));
+    let mut message = format!("{} at column {}", message, column + 1);
+    if !expected.is_empty() {
+        let expected: Vec<String> = expected.iter().map(|t| describe_token(t)).collect();
+        message.push_str(&format!(", expected one of {}", expected.join(", ")));
+    }
+    format!(
+        "{}\n    {}\n    {}{}",
+        message,
+        expr,
+        " ".repeat(column),
+        "^".repeat(width)
+    )
+}
+
+/// Describes a terminal of the grammar as listed by lalrpop in the expected
+/// tokens of a parse error (e.g. `"\"num\""`).
+fn describe_token(token: &str) -> String {
+    match token.trim_matches('"') {
+        "num" => "a number".to_string(),
+        "id" => "an identifier".to_string(),
+        "str" => "a string".to_string(),
+        "regex" => "a regex".to_string(),
+        t => format!("'{}'", t),
+    }
+}
+
+/// A piece of a [`Template`].
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Macro(String),
+}
+
+/// A string template with embedded identifiers (e.g., `"Interface {name}"`),
+/// split once into literals and macros.
+#[derive(Debug, Default)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Splits `template` on its `{identifier}` macros.
+    pub fn parse(template: &str) -> Template {
+        let mut parts = Vec::new();
+        let mut literal_start = 0;
+        let mut i = 0;
+        let bytes = template.as_bytes();
+        while i < bytes.len() {
+            if let Some(end) = macro_end(bytes, i) {
+                if i > literal_start {
+                    parts.push(Part::Literal(template[literal_start..i].to_string()));
+                }
+                parts.push(Part::Macro(template[i + 1..end - 1].to_string()));
+                literal_start = end;
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+        parts.push(Part::Literal(template[literal_start..].to_string()));
+        Template { parts }
+    }
+
+    /// Returns the names of the macros used by the template.
+    pub fn macros(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Macro(name) => Some(name.as_str()),
+            Part::Literal(_) => None,
+        })
+    }
+
+    /// Returns `true` if the template contains text besides its macros.
+    pub fn has_text(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Literal(s) if !s.is_empty()))
+    }
+
+    /// Replaces the macros with values from `symbols`, handling both scalar
+    /// and vector values. In `check_format` mode, an undefined macro is an
+    /// error; otherwise it is replaced by an empty string.
+    pub fn eval(&self, symbols: &Symbols, check_format: bool) -> Result<ExprResult, String> {
+        let mut result = ExprResult::Empty;
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(s) => result.join(&ExprResult::Str(s.clone())),
+                Part::Macro(name) => {
+                    match symbols.get(name) {
+                        Some(v) => result.join(v),
+                        None if check_format => {
+                            return Err(format!("Undefined macro in expression: {{{}}}", name));
+                        }
+                        None => result.join(&ExprResult::Str("".to_string())),
+                    }
+                    trace!(
+                        "Evaluation as string of macro '{}' returns {:?}",
+                        name, result
+                    );
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Returns the end of the `{identifier}` macro starting at `start`, if any.
+fn macro_end(bytes: &[u8], start: usize) -> Option<usize> {
+    if bytes[start] != b'{' {
+        return None;
+    }
+    match bytes.get(start + 1) {
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {}
+        _ => return None,
+    }
+    let mut i = start + 2;
+    while let Some(c) = bytes.get(i) {
+        match c {
+            b'}' => return Some(i + 1),
+            c if c.is_ascii_alphanumeric() || *c == b'_' || *c == b'.' => i += 1,
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// How the value of a [`CompiledMetric`] is computed and checked.
+#[derive(Debug)]
+pub enum CompiledValue {
+    /// A numeric value checked against Nagios thresholds.
+    Number {
+        value: Expression,
+        warning: InstanceThresholds,
+        critical: InstanceThresholds,
+    },
+    /// A textual value checked against boolean expressions.
+    Status {
+        value: Template,
+        warning: Option<Expression>,
+        critical: Option<Expression>,
+    },
+}
+
+/// The expressions of a [`Metric`], parsed once by [`Metric::compile`].
+#[derive(Debug)]
+pub struct CompiledMetric {
+    pub value: CompiledValue,
+    pub prefix: Option<Template>,
+    pub min: Option<Expression>,
+    pub max: Option<Expression>,
+    pub filter: Option<Expression>,
+}
+
+impl CompiledMetric {
+    /// Returns the names of the macros used by the metric's expressions and
+    /// templates.
+    pub fn macros(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        match &self.value {
+            CompiledValue::Number { value, .. } => names.extend(value.macros()),
+            CompiledValue::Status {
+                value,
+                warning,
+                critical,
+            } => {
+                names.extend(value.macros());
+                for expr in warning.iter().chain(critical) {
+                    names.extend(expr.macros());
+                }
+            }
+        }
+        if let Some(prefix) = &self.prefix {
+            names.extend(prefix.macros());
+        }
+        for expr in [&self.min, &self.max, &self.filter].into_iter().flatten() {
+            names.extend(expr.macros());
+        }
+        names
+    }
+}
+
+impl Metric {
+    /// Parses the expressions and thresholds of this metric. `kind` names the
+    /// metric in error messages (`"Metric"` or `"Aggregation"`).
+    ///
+    /// # Errors
+    /// Returns an error naming the field holding an invalid expression or threshold.
+    pub fn compile(&mut self, kind: &str) -> Result<(), Error> {
+        let field_error = |field: &str, e: String| Error::InvalidJSON {
+            message: format!("{} \"{}\", field \"{}\": {}", kind, self.name, field, e),
+        };
+        let parse = |field: &str, expr: &Option<String>| match expr {
+            Some(expr) => Expression::parse(expr)
+                .map(Some)
+                .map_err(|e| field_error(field, e)),
+            None => Ok(None),
+        };
+        let value = match self.kind {
+            MetricType::Number => {
+                let (warning, critical) = self.instance_thresholds()?;
+                CompiledValue::Number {
+                    value: Expression::parse(&self.value).map_err(|e| field_error("value", e))?,
+                    warning,
+                    critical,
+                }
+            }
+            MetricType::Status => CompiledValue::Status {
+                value: Template::parse(&self.value),
+                warning: parse("warning", &self.warning)?,
+                critical: parse("critical", &self.critical)?,
+            },
+        };
+        let compiled = CompiledMetric {
+            value,
+            prefix: self.prefix.as_deref().map(Template::parse),
+            min: parse("min_expr", &self.min_expr)?,
+            max: parse("max_expr", &self.max_expr)?,
+            filter: parse("filter", &self.filter)?,
+        };
+        self.compiled = Some(compiled);
+        Ok(())
+    }
+
+    /// Returns the expressions parsed by [`Metric::compile`].
+    ///
+    /// # Errors
+    /// Returns an error if the metric has not been compiled.
+    pub fn compiled(&self) -> Result<&CompiledMetric, Error> {
+        self.compiled.as_ref().ok_or_else(|| Error::NotCompiled {
+            name: self.name.clone(),
+        })
+    }
+}
+
+/// Parser for evaluating expressions against the values of a [`Symbols`] table.
 ///
 /// Evaluates mathematical expressions (e.g., `"{ifSpeed} / 1000000"`) and string
-/// interpolations (e.g., `"Interface {ifDescr}"`).
+/// interpolations (e.g., `"Interface {ifDescr}"`) in one go; prefer
+/// [`Expression`] and [`Template`] to evaluate the same expression repeatedly.
 pub struct Parser<'a> {
-    collect: &'a Vec<SnmpResult>,
-    parser: grammar::ExprParser,
+    symbols: &'a Symbols,
     check_format: bool,
 }
 
 impl<'a> Parser<'a> {
-    /// Creates a new parser over the given SNMP results.
-    pub fn new(collect: &'a Vec<SnmpResult>, check_format: bool) -> Parser<'a> {
+    /// Creates a new parser over the given symbol table.
+    pub fn new(symbols: &'a Symbols, check_format: bool) -> Parser<'a> {
         Parser {
-            collect,
-            parser: grammar::ExprParser::new(),
+            symbols,
             check_format,
         }
     }
@@ -87,72 +499,34 @@ impl<'a> Parser<'a> {
     /// Evaluates a mathematical expression and returns the result.
     ///
     /// Supports arithmetic operations, identifiers in braces (e.g., `{metric_name}`),
-    /// and functions like `Average()`, `Min()`, `Max()`.
-    pub fn eval(&self, expr: &'a str) -> Result<ExprResult, String> {
-        debug!("Parsing expression: {}", expr);
-        let lexer = lexer::Lexer::new(expr);
-        let res = self.parser.parse(lexer);
-        match res {
-            Ok(expr) => {
-                if self.check_format {
-                    expr.validate_macros(self.collect)?;
-                }
-                expr.eval(self.collect)
-            }
-            Err(e) => Err(format!("{:?}", e)),
-        }
+    /// and functions like `Average()`, `Min()`, `Max()`, `Count()`.
+    pub fn eval(&self, expr: &str) -> Result<ExprResult, String> {
+        Expression::parse(expr)?.eval(self.symbols, self.check_format)
     }
 
     /// Evaluates a string template with embedded identifiers (e.g., `"Interface {name}"`).
     ///
     /// Replaces `{identifier}` with values from SNMP results, handling both
     /// scalar and vector values appropriately.
-    pub fn eval_str(&self, expr: &'a str) -> Result<ExprResult, String> {
-        let re = Regex::new(r"\{[a-zA-Z_][a-zA-Z0-9_.]*\}").unwrap();
-        let mut suffix = expr;
-        let mut result: ExprResult = ExprResult::Empty;
-        trace!("[eval_str] suffix: {:?} - re: {:?}", &suffix, &re);
-        loop {
-            let found = re.find(suffix);
-            if let Some(m) = found {
-                let start = m.start();
-                let end = m.end();
-                if start > 0 {
-                    result.join(&ExprResult::Str(suffix[0..start].to_string()));
-                }
-                let macro_name = &suffix[start + 1..end - 1];
-                let mut found = false;
-                for snmp_result in self.collect {
-                    if let Some(v) = snmp_result.items.get(macro_name) {
-                        result.join(v);
-                        found = true;
-                        break;
-                    }
-                }
-                if !found {
-                    if self.check_format {
-                        return Err(format!("Undefined macro in expression: {{{}}}", macro_name));
-                    } else {
-                        result.join(&ExprResult::Str("".to_string()));
-                    }
-                }
-                debug!(
-                    "Evaluation as string of expression '{}' returns {:?}",
-                    suffix, result
-                );
-                suffix = &suffix[end..];
-            } else {
-                result.join(&ExprResult::Str(suffix.to_string()));
-                break;
-            }
-        }
-        Ok(result)
+    pub fn eval_str(&self, expr: &str) -> Result<ExprResult, String> {
+        Template::parse(expr).eval(self.symbols, self.check_format)
+    }
+
+    /// Evaluates an already parsed expression.
+    pub fn eval_expr(&self, expr: &Expression) -> Result<ExprResult, String> {
+        expr.eval(self.symbols, self.check_format)
+    }
+
+    /// Evaluates an already parsed string template.
+    pub fn eval_template(&self, template: &Template) -> Result<ExprResult, String> {
+        template.eval(self.symbols, self.check_format)
     }
 }
 
+#[cfg(test)]
 mod test {
+    use crate::compute::symbols::Symbols;
     use crate::compute::{Parser, ast::ExprResult, grammar, lexer};
-    use crate::snmp::SnmpResult;
     use log::{debug, info};
     use std::collections::HashMap;
 
@@ -167,7 +541,7 @@ mod test {
         let lexer = lexer::Lexer::new("123");
         let res = grammar::ExprParser::new().parse(lexer);
         assert!(res.is_ok());
-        let snmp_result = vec![];
+        let snmp_result = Symbols::new();
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Number(n) => assert!(n == 123_f64),
@@ -186,7 +560,7 @@ mod test {
         let lexer = lexer::Lexer::new("1 + 2");
         let res = grammar::ExprParser::new().parse(lexer);
         assert!(res.is_ok());
-        let snmp_result = vec![];
+        let snmp_result = Symbols::new();
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Number(n) => assert!(n == 3_f64),
@@ -236,7 +610,7 @@ mod test {
         let lexer = lexer::Lexer::new("2 * 3");
         let res = grammar::ExprParser::new().parse(lexer);
         assert!(res.is_ok());
-        let snmp_result = vec![];
+        let snmp_result = Symbols::new();
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Number(n) => assert!(n == 6_f64),
@@ -288,7 +662,7 @@ mod test {
         let lexer = lexer::Lexer::new("1 + (3 + 2 * 3) / 3");
         let res = grammar::ExprParser::new().parse(lexer);
         assert!(res.is_ok());
-        let snmp_result = vec![];
+        let snmp_result = Symbols::new();
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Number(n) => assert!(n == 4_f64),
@@ -304,7 +678,7 @@ mod test {
         assert!(res.is_ok());
         println!("{:?}", res);
         let items = HashMap::from([("abc".to_string(), ExprResult::Vector(vec![1_f64]))]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Number(n) => assert!(n == 2_f64),
@@ -331,7 +705,7 @@ mod test {
                 ExprResult::Vector(vec![5_f64, 6_f64, 7_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Vector(v) => assert!(v == vec![4_f64, 6_f64]),
@@ -360,7 +734,7 @@ mod test {
                 ExprResult::Vector(vec![5_f64, 6_f64, 7_f64, 8_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -379,7 +753,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -398,7 +772,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -422,7 +796,7 @@ mod test {
                 ExprResult::Vector(vec![5_f64, 6_f64, 7_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Vector(v) => assert!(v == vec![-2_f64, -1_f64]),
@@ -451,7 +825,7 @@ mod test {
                 ExprResult::Vector(vec![5_f64, 6_f64, 7_f64, 8_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -470,7 +844,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -489,7 +863,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -513,7 +887,7 @@ mod test {
                 ExprResult::Vector(vec![5_f64, 6_f64, 7_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Vector(v) => assert!(v == vec![3_f64, 12_f64]),
@@ -542,7 +916,7 @@ mod test {
                 ExprResult::Vector(vec![5_f64, 6_f64, 7_f64, 8_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -561,7 +935,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -580,7 +954,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -604,7 +978,7 @@ mod test {
                 ExprResult::Vector(vec![5_f64, 6_f64, 7_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Vector(v) => assert!(v == vec![3_f64, 4_f64]),
@@ -633,7 +1007,7 @@ mod test {
                 ExprResult::Vector(vec![2_f64, 2_f64, 3_f64, 8_f64]),
             ),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -652,7 +1026,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -671,7 +1045,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 5_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         debug!("{:?}", res);
         match res {
@@ -690,7 +1064,7 @@ mod test {
             ("free".to_string(), ExprResult::Vector(vec![29600_f64])),
             ("total".to_string(), ExprResult::Vector(vec![747712_f64])),
         ]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Number(n) => assert!(n == 96.04125652657707_f64),
@@ -708,7 +1082,7 @@ mod test {
             "abc".to_string(),
             ExprResult::Vector(vec![1_f64, 2_f64, 3_f64]),
         )]);
-        let snmp_result = vec![SnmpResult::new(items)];
+        let snmp_result = Symbols::from(items);
         let res = res.unwrap().eval(&snmp_result).unwrap();
         match res {
             ExprResult::Number(n) => assert!(n == 2_f64),
@@ -716,6 +1090,31 @@ mod test {
         }
     }
 
+    #[test]
+    fn count() {
+        init();
+        let items = HashMap::from([
+            (
+                "cpu".to_string(),
+                ExprResult::Vector(vec![1_f64, f64::NAN, 3_f64]),
+            ),
+            (
+                "descr".to_string(),
+                ExprResult::StrVector(vec!["lo".to_string(), "eth0".to_string()]),
+            ),
+        ]);
+        let symbols = Symbols::from(items);
+        let parser = Parser::new(&symbols, false);
+        match parser.eval("Count({cpu})").unwrap() {
+            ExprResult::Number(n) => assert_eq!(n, 3_f64),
+            _ => panic!("Expected a scalar value"),
+        }
+        match parser.eval("Count({descr})").unwrap() {
+            ExprResult::Number(n) => assert_eq!(n, 2_f64),
+            _ => panic!("Expected a scalar value"),
+        }
+    }
+
     #[test]
     fn join_str_str_identifier() {
         init();
@@ -729,7 +1128,7 @@ mod test {
                 ExprResult::StrVector(vec!["total-one".to_string(), "total-two".to_string()]),
             ),
         ]);
-        let collect = vec![SnmpResult::new(items)];
+        let collect = Symbols::from(items);
         let parser = Parser::new(&collect, false);
         let res = parser.eval_str("{free}foo{total}bar");
         assert!(res.is_ok());
@@ -757,7 +1156,7 @@ mod test {
                 ExprResult::StrVector(vec!["total-one".to_string(), "total-two".to_string()]),
             ),
         ]);
-        let collect = vec![SnmpResult::new(items)];
+        let collect = Symbols::from(items);
         let parser = Parser::new(&collect, false);
         let res = parser.eval_str("test{free}{total}foo{free}");
         assert!(res.is_ok());
@@ -779,7 +1178,7 @@ mod test {
             ("free".to_string(), ExprResult::Vector(vec![1.1, 2.2, 3.3])),
             ("total".to_string(), ExprResult::Vector(vec![2.1, 3.2, 4.3])),
         ]);
-        let collect = vec![SnmpResult::new(items)];
+        let collect = Symbols::from(items);
         let parser = Parser::new(&collect, false);
         let res = parser.eval_str("test{free}{total}foo{free}");
         assert!(res.is_ok());
@@ -804,4 +1203,118 @@ mod test {
             _ => panic!("Expected a string"),
         }
     }
+
+    #[test]
+    fn join_number_str() {
+        let mut a = ExprResult::Empty;
+        a.join(&ExprResult::Number(12.5));
+        a.join(&ExprResult::Str(" connections".to_string()));
+        match a {
+            ExprResult::Str(s) => assert_eq!(s, "12.5 connections"),
+            _ => panic!("Expected a string"),
+        }
+
+        let mut a = ExprResult::StrVector(vec!["a=".to_string(), "b=".to_string()]);
+        a.join(&ExprResult::Number(3.0));
+        match a {
+            ExprResult::StrVector(v) => assert_eq!(v, vec!["a=3", "b=3"]),
+            _ => panic!("Expected a string vector"),
+        }
+    }
+
+    #[test]
+    fn string_status_regex() {
+        init();
+        let items = HashMap::from([(
+            "if.status".to_string(),
+            ExprResult::StrVector(vec!["up".to_string(), "down".to_string()]),
+        )]);
+        let collect = Symbols::from(items);
+        let parser = Parser::new(&collect, false);
+        match parser.eval("{if.status} !~ /up/").unwrap() {
+            ExprResult::Vector(v) => assert_eq!(v, vec![0_f64, 1_f64]),
+            _ => panic!("Expected a vector value"),
+        }
+        match parser
+            .eval("{if.status} == 'up' || {if.status} == \"testing\"")
+            .unwrap()
+        {
+            ExprResult::Vector(v) => assert_eq!(v, vec![1_f64, 0_f64]),
+            _ => panic!("Expected a vector value"),
+        }
+    }
+
+    #[test]
+    fn numeric_comparison() {
+        init();
+        let items = HashMap::from([(
+            "used".to_string(),
+            ExprResult::Vector(vec![10_f64, 50_f64, 90_f64]),
+        )]);
+        let collect = Symbols::from(items);
+        let parser = Parser::new(&collect, false);
+        match parser.eval("{used} > 20 && !({used} >= 90)").unwrap() {
+            ExprResult::Vector(v) => assert_eq!(v, vec![0_f64, 1_f64, 0_f64]),
+            _ => panic!("Expected a vector value"),
+        }
+        match parser.eval("1 + 1 == 2").unwrap() {
+            ExprResult::Number(n) => assert_eq!(n, 1_f64),
+            _ => panic!("Expected a scalar value"),
+        }
+        assert!(parser.eval("{used} =~ /(/").is_err());
+    }
+
+    #[test]
+    fn borrowed_arithmetic() {
+        init();
+        let items = HashMap::from([
+            (
+                "in".to_string(),
+                ExprResult::Vector(vec![10_f64, 20_f64, 30_f64]),
+            ),
+            ("out".to_string(), ExprResult::Vector(vec![1_f64, 2_f64])),
+            (
+                "descr".to_string(),
+                ExprResult::StrVector(vec!["lo".to_string(), "eth0".to_string()]),
+            ),
+        ]);
+        let collect = Symbols::from(items);
+        let parser = Parser::new(&collect, false);
+        match parser.eval("({in} + {out}) * 8 / {out}").unwrap() {
+            ExprResult::Vector(v) => assert_eq!(v, vec![88_f64, 88_f64, 240_f64]),
+            _ => panic!("Expected a vector value"),
+        }
+        // The collected values are left untouched
+        match collect.get("in") {
+            Some(ExprResult::Vector(v)) => assert_eq!(v, &vec![10_f64, 20_f64, 30_f64]),
+            _ => panic!("Expected a vector value"),
+        }
+        assert_eq!(
+            parser.eval("{descr} - 1").err().unwrap(),
+            "Arithmetic operators only apply to numbers"
+        );
+    }
+
+    #[test]
+    fn parse_error_messages() {
+        use crate::compute::Expression;
+
+        let message = |expr: &str| Expression::parse(expr).unwrap_err();
+        assert_eq!(
+            message("{a} + }"),
+            "unexpected '}' at column 7, expected one of '(', '{', a number, an identifier, a string\n    {a} + }\n          ^"
+        );
+        assert_eq!(
+            message("Average({a}"),
+            "unexpected end of expression at column 12, expected one of ')'\n    Average({a}\n               ^"
+        );
+        assert_eq!(
+            message("{a} == 'up"),
+            "unterminated literal at column 8\n    {a} == 'up\n           ^^^"
+        );
+        assert_eq!(
+            message("{a} =~ /(/"),
+            "invalid regular expression /(/ at column 8\n    {a} =~ /(/\n           ^^^"
+        );
+    }
 }
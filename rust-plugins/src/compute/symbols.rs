@@ -0,0 +1,104 @@
+//! The namespace of values that expressions can reference.
+//!
+//! Collected values (`{if.descr}`), metrics (`{metrics.<name>}`) and
+//! aggregations (`{aggregations.<name>}`) all live in one [`Symbols`] table,
+//! built once per evaluation. Expressions borrow their operands from it
+//! instead of copying them.
+
+use crate::compute::ast::ExprResult;
+use crate::generic::error::Error;
+use crate::snmp::SnmpResult;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// Values available to expressions, indexed by name.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    values: HashMap<String, ExprResult>,
+}
+
+impl Symbols {
+    /// Creates an empty table.
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// Builds the table from the results of the SNMP queries.
+    ///
+    /// # Errors
+    /// Returns an error if two results define the same name.
+    pub fn from_results(results: Vec<SnmpResult>) -> Result<Symbols, Error> {
+        let mut symbols = Symbols::new();
+        for result in results {
+            for (name, value) in result.items {
+                symbols.insert(name, value)?;
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Adds a value to the table.
+    ///
+    /// # Errors
+    /// Returns an error if `name` is already defined.
+    pub fn insert(&mut self, name: String, value: ExprResult) -> Result<(), Error> {
+        match self.values.entry(name) {
+            Entry::Occupied(entry) => Err(Error::DuplicateName {
+                name: entry.key().clone(),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the value named `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&ExprResult> {
+        self.values.get(name)
+    }
+
+    /// Returns the value named `name` for modification, if any.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut ExprResult> {
+        self.values.get_mut(name)
+    }
+
+    /// Returns `true` if `name` is defined.
+    pub fn contains(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+}
+
+impl From<HashMap<String, ExprResult>> for Symbols {
+    fn from(values: HashMap<String, ExprResult>) -> Symbols {
+        Symbols { values }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_duplicate_name() {
+        use crate::compute::ast::ExprResult;
+        use crate::compute::symbols::Symbols;
+        use crate::snmp::SnmpResult;
+        use std::collections::HashMap;
+
+        let result = |name: &str| {
+            SnmpResult::new(HashMap::from([(
+                name.to_string(),
+                ExprResult::Vector(vec![1.0]),
+            )]))
+        };
+        let symbols = Symbols::from_results(vec![result("cpu"), result("mem")]).unwrap();
+        assert!(symbols.contains("cpu"));
+        assert!(symbols.contains("mem"));
+        match Symbols::from_results(vec![result("cpu"), result("cpu")]) {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "Duplicate name 'cpu': it is defined more than once by the collect or the metrics"
+            ),
+            Ok(_) => panic!("A duplicate name must be rejected"),
+        }
+    }
+}
@@ -4,9 +4,11 @@
 //! following the [Nagios plugin guidelines](https://nagios-plugins.org/doc/guidelines.html#THRESHOLDFORMAT).
 
 use crate::generic::error::Error;
+use regex::Regex;
 use std::f64::INFINITY;
 
 /// Represents an alert threshold range and its alert condition.
+#[derive(Debug)]
 pub struct Threshold {
     start: f64,
     end: f64,
@@ -129,8 +131,117 @@ impl Threshold {
     }
 }
 
+/// Selects the instances an instance-keyed threshold applies to.
+#[derive(Debug)]
+enum InstanceKey {
+    /// `*`: every instance.
+    Any,
+    /// `re:<regex>`: instances whose name matches the regular expression.
+    Regex(Regex),
+    /// Any other key: the instance with exactly this name.
+    Exact(String),
+}
+
+impl InstanceKey {
+    fn parse(key: &str) -> Result<InstanceKey, Error> {
+        let key = key.trim();
+        if key == "*" {
+            Ok(InstanceKey::Any)
+        } else if let Some(re) = key.strip_prefix("re:") {
+            Ok(InstanceKey::Regex(Regex::new(re)?))
+        } else {
+            Ok(InstanceKey::Exact(key.to_string()))
+        }
+    }
+
+    /// Lower is more specific: exact names win over regexes, which win over `*`.
+    fn rank(&self) -> u8 {
+        match self {
+            InstanceKey::Exact(_) => 0,
+            InstanceKey::Regex(_) => 1,
+            InstanceKey::Any => 2,
+        }
+    }
+
+    fn matches(&self, instance: &str) -> bool {
+        match self {
+            InstanceKey::Any => true,
+            InstanceKey::Regex(re) => re.is_match(instance),
+            InstanceKey::Exact(name) => name == instance,
+        }
+    }
+}
+
+/// Threshold strings resolved per metric instance.
+///
+/// Entries come from instance-keyed specifications like `"/var=90,/tmp=98,*=80"`
+/// (a plain threshold such as `"80"` is the same as `"*=80"`) or from the
+/// `thresholds` map of a metric. The most specific matching key wins; among
+/// equally specific keys, the first one added wins.
+#[derive(Debug, Default)]
+pub struct InstanceThresholds {
+    entries: Vec<(InstanceKey, String, Threshold)>,
+}
+
+impl InstanceThresholds {
+    /// Creates an empty set, resolving to no threshold for every instance.
+    pub fn new() -> InstanceThresholds {
+        InstanceThresholds {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds every entry of an instance-keyed specification.
+    ///
+    /// Keys are separated from thresholds by the last `=` of each
+    /// comma-separated entry, so a regex key cannot contain a `,`.
+    ///
+    /// # Errors
+    /// Returns an error if an entry, its key or its threshold is invalid.
+    pub fn add_spec(&mut self, spec: &str) -> Result<(), Error> {
+        if !spec.contains('=') {
+            return self.add("*", spec);
+        }
+        for entry in spec.split(',') {
+            match entry.rsplit_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => self.add(key, value.trim())?,
+                _ => {
+                    return Err(Error::BadInstanceThreshold {
+                        entry: entry.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a single threshold applied to the instances selected by `key`.
+    ///
+    /// # Errors
+    /// Returns an error if the key is an invalid regex or the threshold is invalid.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let threshold = Threshold::parse(value)?;
+        self.entries
+            .push((InstanceKey::parse(key)?, value.to_string(), threshold));
+        Ok(())
+    }
+
+    /// Returns the threshold applying to `instance`, if any, as written by
+    /// the user (for perfdata) and parsed.
+    pub fn resolve(&self, instance: &str) -> Option<(&str, &Threshold)> {
+        let mut best: Option<&(InstanceKey, String, Threshold)> = None;
+        for entry in self.entries.iter() {
+            if entry.0.matches(instance) && best.is_none_or(|b| entry.0.rank() < b.0.rank()) {
+                best = Some(entry);
+            }
+        }
+        best.map(|entry| (entry.1.as_str(), &entry.2))
+    }
+}
+
+#[cfg(test)]
 mod test {
-    use crate::compute::threshold::Threshold;
+    use crate::compute::threshold::{InstanceThresholds, Threshold};
     use crate::generic::error::Error;
     use std::f64::INFINITY;
     #[test]
@@ -392,4 +503,53 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_instance_thresholds_plain() {
+        let mut thresholds = InstanceThresholds::new();
+        thresholds.add_spec("80").unwrap();
+        assert_eq!(thresholds.resolve("/var").map(|t| t.0), Some("80"));
+        assert_eq!(thresholds.resolve("/tmp").map(|t| t.0), Some("80"));
+    }
+
+    #[test]
+    fn test_instance_thresholds_keyed() {
+        let mut thresholds = InstanceThresholds::new();
+        thresholds.add_spec("*=80,/var=90, /tmp=98").unwrap();
+        assert_eq!(thresholds.resolve("/var").map(|t| t.0), Some("90"));
+        assert_eq!(thresholds.resolve("/tmp").map(|t| t.0), Some("98"));
+        assert_eq!(thresholds.resolve("/home").map(|t| t.0), Some("80"));
+    }
+
+    #[test]
+    fn test_instance_thresholds_regex() {
+        let mut thresholds = InstanceThresholds::new();
+        thresholds.add_spec("re:^/data=95,/data2=99").unwrap();
+        assert_eq!(thresholds.resolve("/data1").map(|t| t.0), Some("95"));
+        assert_eq!(thresholds.resolve("/data2").map(|t| t.0), Some("99"));
+        assert_eq!(thresholds.resolve("/var").map(|t| t.0), None);
+    }
+
+    #[test]
+    fn test_instance_thresholds_first_added_wins() {
+        let mut thresholds = InstanceThresholds::new();
+        thresholds.add_spec("/var=90").unwrap();
+        thresholds.add("/var", "50").unwrap();
+        thresholds.add("*", "70").unwrap();
+        assert_eq!(thresholds.resolve("/var").map(|t| t.0), Some("90"));
+        assert_eq!(thresholds.resolve("/tmp").map(|t| t.0), Some("70"));
+    }
+
+    #[test]
+    fn test_instance_thresholds_bad_entry() {
+        let mut thresholds = InstanceThresholds::new();
+        match thresholds.add_spec("/var=90,80") {
+            Ok(_) => panic!("The specification should not be valid"),
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "Threshold: The entry '80' must follow 'instance=threshold'"
+            ),
+        }
+        assert!(thresholds.add_spec("/var=foo").is_err());
+    }
 }
@@ -0,0 +1,141 @@
+//! The file formats of definitions: JSON, YAML and TOML.
+//!
+//! All of them describe the same [`Command`](crate::generic::Command); YAML
+//! and TOML are parsed into a JSON value before being deserialized, so that
+//! `extends`, `include` and placeholders work the same in every format.
+
+use crate::generic::error::{Error, Result};
+use serde_json::Value;
+use std::path::Path;
+
+/// A definition file format.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+/// File extensions tried when a definition is looked up by name.
+pub const EXTENSIONS: &[&str] = &["json", "yaml", "yml", "toml"];
+
+impl Format {
+    /// Parses the name given to `--to`.
+    ///
+    /// # Errors
+    /// Returns an error if `name` is not a known format.
+    pub fn parse(name: &str) -> Result<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            _ => Err(Error::UnknownFormat {
+                format: name.to_string(),
+            }),
+        }
+    }
+
+    /// Returns the format of `text`, read from `path`: given by the file
+    /// extension, or else guessed from the content.
+    pub fn detect(path: Option<&Path>, text: &str) -> Format {
+        let extension = path
+            .and_then(|p| p.extension())
+            .and_then(|ext| ext.to_str());
+        match extension {
+            Some("json") => Format::Json,
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("toml") => Format::Toml,
+            _ => Format::sniff(text),
+        }
+    }
+
+    /// Guesses the format of `text`: JSON starts with `{`, TOML has a
+    /// `[table]` header or a `key = value` line before any YAML `key:` line.
+    fn sniff(text: &str) -> Format {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        match lines.next() {
+            Some(line) if line.starts_with('{') => Format::Json,
+            Some(line) if line.starts_with('[') && line.ends_with(']') => Format::Toml,
+            Some(line) => match (line.find('='), line.find(':')) {
+                (Some(eq), Some(colon)) if eq < colon => Format::Toml,
+                (Some(_), None) => Format::Toml,
+                _ => Format::Yaml,
+            },
+            None => Format::Json,
+        }
+    }
+
+    /// Parses `text` into a JSON value.
+    ///
+    /// # Errors
+    /// Returns an error with its position if `text` is malformed.
+    pub fn read(self, text: &str) -> Result<Value> {
+        match self {
+            Format::Json => Ok(serde_json::from_str(text)?),
+            Format::Yaml => serde_yaml::from_str(text).map_err(|e| Error::InvalidJSON {
+                message: format!("YAML: {}", e),
+            }),
+            Format::Toml => toml::from_str(text).map_err(|e| Error::InvalidJSON {
+                message: format!("TOML: {}", e),
+            }),
+        }
+    }
+
+    /// Writes `value` in this format.
+    ///
+    /// # Errors
+    /// Returns an error if `value` cannot be represented in this format,
+    /// e.g. a `null` in TOML.
+    pub fn write(self, value: &Value) -> Result<String> {
+        match self {
+            Format::Json => Ok(serde_json::to_string_pretty(value)?),
+            Format::Yaml => serde_yaml::to_string(value).map_err(|e| Error::InvalidJSON {
+                message: format!("YAML: {}", e),
+            }),
+            Format::Toml => toml::to_string_pretty(value).map_err(|e| Error::InvalidJSON {
+                message: format!("TOML: {}", e),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_detect() {
+        use crate::definitions::format::Format;
+        use std::path::Path;
+
+        let detect = |text: &str| Format::detect(None, text);
+        assert_eq!(detect("  {\n \"collect\": {} }"), Format::Json);
+        assert_eq!(detect("# comment\ncollect:\n  snmp: []"), Format::Yaml);
+        assert_eq!(detect("[[collect.snmp]]\nname = 'cpu'"), Format::Toml);
+        assert_eq!(detect("extends = \"cpu\""), Format::Toml);
+        assert_eq!(detect("extends: cpu\nok: 'a = b'"), Format::Yaml);
+        assert_eq!(
+            Format::detect(Some(Path::new("cpu.yml")), "{}"),
+            Format::Yaml
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        use crate::definitions::format::Format;
+        use crate::generic::Command;
+        use crate::modes::MODES;
+
+        for mode in MODES {
+            let value = Format::Json.read(mode.definition).unwrap();
+            for format in [Format::Yaml, Format::Toml] {
+                let text = format.write(&value).unwrap();
+                assert_eq!(Format::detect(None, &text), format, "{}", text);
+                let back = format.read(&text).unwrap();
+                assert_eq!(back, value, "Mode '{}' in {:?}", mode.name, format);
+                assert!(Command::from_value(back).is_ok());
+            }
+        }
+    }
+}
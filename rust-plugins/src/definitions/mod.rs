@@ -0,0 +1,334 @@
+//! Lookup of command definitions by name, and their `extends`/`include`
+//! composition.
+//!
+//! A definition is found, in order, as a path, as `<name>` or `<name>` with
+//! one of the [`format::EXTENSIONS`] in the directory of the definition referencing it, in the directories of
+//! `CENTREON_PLUGIN_DEFINITIONS` and [`DEFAULT_DIRECTORY`], and finally among
+//! the built-in [`modes`](crate::modes).
+//!
+//! A definition may be based on another one with `"extends": "<name>"` and
+//! merge shared blocks with `"include": ["<name>", ...]`. The definition
+//! itself takes precedence over its includes, a later include over an earlier
+//! one, and any of them over the definition it extends. Objects (`maps`,
+//! `output`...) are merged key by key, while the entries of `collect.snmp`,
+//! `compute.metrics` and `compute.aggregations` replace the entry of the same
+//! name, keeping its position, or are appended.
+//!
+//! Once merged, the [`placeholders`] of the definition are replaced.
+
+pub mod format;
+pub mod placeholders;
+pub mod scaffold;
+
+use crate::generic::Command;
+use crate::generic::error::{Error, Result};
+use crate::modes;
+use format::Format;
+use placeholders::Defines;
+use serde_json::{Map, Value};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the directories searched for definitions,
+/// separated like `PATH`.
+pub const PATH_VARIABLE: &str = "CENTREON_PLUGIN_DEFINITIONS";
+
+/// Directory searched for definitions after those of [`PATH_VARIABLE`].
+pub const DEFAULT_DIRECTORY: &str = "/usr/share/centreon-generic-snmp/definitions";
+
+/// The directories searched for definitions referenced by name.
+#[derive(Debug, Default)]
+pub struct SearchPath {
+    directories: Vec<PathBuf>,
+}
+
+/// Where a definition was found.
+enum Origin {
+    File(PathBuf),
+    Builtin,
+}
+
+impl Origin {
+    /// Returns the format of the definition `text` found here.
+    fn format(&self, text: &str) -> Format {
+        match self {
+            Origin::File(file) => Format::detect(Some(file), text),
+            Origin::Builtin => Format::Json,
+        }
+    }
+}
+
+impl SearchPath {
+    /// Creates a search path made of `directories`.
+    pub fn new(directories: Vec<PathBuf>) -> SearchPath {
+        SearchPath { directories }
+    }
+
+    /// Creates the search path from [`PATH_VARIABLE`], followed by
+    /// [`DEFAULT_DIRECTORY`].
+    pub fn from_env() -> SearchPath {
+        let mut directories: Vec<PathBuf> = match env::var_os(PATH_VARIABLE) {
+            Some(paths) => env::split_paths(&paths).collect(),
+            None => Vec::new(),
+        };
+        directories.push(PathBuf::from(DEFAULT_DIRECTORY));
+        SearchPath::new(directories)
+    }
+
+    /// Searches `directory` before the other directories.
+    pub fn prepend(&mut self, directory: PathBuf) {
+        self.directories.insert(0, directory);
+    }
+
+    /// Returns the searched directories.
+    pub fn directories(&self) -> &[PathBuf] {
+        &self.directories
+    }
+
+    /// Returns the file defining `name`, looked up as a path, then in
+    /// `relative_to` and in the directories of the search path.
+    fn find(&self, name: &str, relative_to: Option<&Path>) -> Option<PathBuf> {
+        if Path::new(name).is_file() && relative_to.is_none() {
+            return Some(PathBuf::from(name));
+        }
+        let directories = relative_to
+            .into_iter()
+            .chain(self.directories.iter().map(|d| d.as_path()));
+        for directory in directories {
+            let files = format::EXTENSIONS
+                .iter()
+                .map(|ext| directory.join(format!("{}.{}", name, ext)));
+            for file in std::iter::once(directory.join(name)).chain(files) {
+                if file.is_file() {
+                    return Some(file);
+                }
+            }
+        }
+        None
+    }
+
+    /// Reads the definition `name`, from a file or from a built-in mode.
+    fn read(&self, name: &str, relative_to: Option<&Path>) -> Result<(String, Origin)> {
+        if let Some(file) = self.find(name, relative_to) {
+            let text = fs::read_to_string(&file)?;
+            return Ok((text, Origin::File(file)));
+        }
+        match modes::find(name) {
+            Some(mode) => Ok((mode.definition.to_string(), Origin::Builtin)),
+            None => Err(Error::UnknownDefinition {
+                name: name.to_string(),
+                searched: self
+                    .directories
+                    .iter()
+                    .map(|d| d.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            }),
+        }
+    }
+
+    /// Loads the definition `name`, resolves its `extends` and `include`
+    /// entries, replaces its placeholders with `defines` and compiles it.
+    ///
+    /// # Errors
+    /// Returns an error if a definition cannot be found or read, if
+    /// definitions include each other, if a placeholder has no value, or if
+    /// the result is not a valid command.
+    pub fn load(&self, name: &str, defines: &Defines) -> Result<Command> {
+        let (text, origin) = self.read(name, None)?;
+        let format = origin.format(&text);
+        let value = format.read(&text)?;
+        if format == Format::Json
+            && !composed(&value)
+            && value.get("defines").is_none()
+            && !placeholders::has_placeholders(&text)
+            && defines.is_empty()
+        {
+            // Keeps the line and column of errors in the original text
+            return Command::from_json(&text);
+        }
+        let mut stack = vec![name.to_string()];
+        let mut value = self.resolve(value, &origin, &mut stack)?;
+        placeholders::substitute(&mut value, defines)?;
+        Command::from_value(value)
+    }
+
+    /// Merges the definitions referenced by `value` into it. `stack` holds
+    /// the definitions being resolved, to detect cycles.
+    fn resolve(&self, value: Value, origin: &Origin, stack: &mut Vec<String>) -> Result<Value> {
+        let Value::Object(mut object) = value else {
+            return Ok(value);
+        };
+        let extends = match object.remove("extends") {
+            None => None,
+            Some(Value::String(name)) => Some(name),
+            Some(_) => return Err(bad_field("extends", "must be the name of a definition")),
+        };
+        let includes = match object.remove("include") {
+            None => Vec::new(),
+            Some(Value::Array(names)) => names
+                .into_iter()
+                .map(|name| match name {
+                    Value::String(name) => Ok(name),
+                    _ => Err(bad_field("include", "must list names of definitions")),
+                })
+                .collect::<Result<Vec<String>>>()?,
+            Some(_) => return Err(bad_field("include", "must list names of definitions")),
+        };
+        let directory = match origin {
+            Origin::File(file) => file.parent(),
+            Origin::Builtin => None,
+        };
+        let mut merged = Value::Object(Map::new());
+        for name in extends.iter().chain(includes.iter()) {
+            if stack.contains(name) {
+                return Err(Error::IncludeCycle {
+                    cycle: format!("{} -> {}", stack.join(" -> "), name),
+                });
+            }
+            let (text, origin) = self.read(name, directory)?;
+            let value = origin.format(&text).read(&text)?;
+            stack.push(name.clone());
+            let value = self.resolve(value, &origin, stack)?;
+            stack.pop();
+            merge(&mut merged, value);
+        }
+        merge(&mut merged, Value::Object(object));
+        Ok(merged)
+    }
+}
+
+/// Returns `true` if the definition uses `extends` or `include`.
+fn composed(value: &Value) -> bool {
+    value.get("extends").is_some() || value.get("include").is_some()
+}
+
+fn bad_field(field: &str, message: &str) -> Error {
+    Error::InvalidJSON {
+        message: format!("{}: {}", field, message),
+    }
+}
+
+/// Merges `overlay` into `base`: objects key by key, arrays of named entries
+/// name by name. Anything else in `overlay` replaces `base`.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(current) => merge(current, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay))
+            if overlay.iter().all(|v| v.get("name").is_some()) =>
+        {
+            for entry in overlay {
+                match base
+                    .iter_mut()
+                    .find(|current| current.get("name") == entry.get("name"))
+                {
+                    Some(current) => *current = entry,
+                    None => base.push(entry),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_merge() {
+        use crate::definitions::merge;
+        use serde_json::json;
+
+        let mut base = json!({
+            "compute": { "metrics": [
+                { "name": "a", "value": "{a}", "uom": "%" },
+                { "name": "b", "value": "{b}" }
+            ] },
+            "output": { "ok": "base", "warning": "WARNING: " }
+        });
+        merge(
+            &mut base,
+            json!({
+                "compute": { "metrics": [
+                    { "name": "a", "value": "{a} * 8" },
+                    { "name": "c", "value": "{c}" }
+                ] },
+                "output": { "ok": "overlay" }
+            }),
+        );
+        assert_eq!(
+            base,
+            json!({
+                "compute": { "metrics": [
+                    { "name": "a", "value": "{a} * 8" },
+                    { "name": "b", "value": "{b}" },
+                    { "name": "c", "value": "{c}" }
+                ] },
+                "output": { "ok": "overlay", "warning": "WARNING: " }
+            })
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        use crate::definitions::SearchPath;
+        use crate::definitions::placeholders::Defines;
+        use std::fs;
+
+        let root = std::env::temp_dir().join(format!("definitions-{}", std::process::id()));
+        let shared = root.join("shared");
+        let vendor = root.join("vendor");
+        fs::create_dir_all(&shared).unwrap();
+        fs::create_dir_all(&vendor).unwrap();
+        fs::write(
+            shared.join("if-table.json"),
+            r#"{ "collect": { "snmp": [
+                 { "name": "if", "oid": "1.3.6.1.2.1.2.2.1", "query": "Walk",
+                   "labels": { ".2": "descr", ".8": "operStatus" } } ] } }"#,
+        )
+        .unwrap();
+        fs::write(
+            vendor.join("output.json"),
+            r#"{ "output": { "ok": "All interfaces are up", "critical": "Vendor CRITICAL: " } }"#,
+        )
+        .unwrap();
+        fs::write(
+            vendor.join("vendor-if.json"),
+            r#"{ "extends": "interfaces", "include": ["if-table", "output"],
+                 "output": { "ok": "Vendor interfaces are up" } }"#,
+        )
+        .unwrap();
+        fs::write(vendor.join("loop.json"), r#"{ "include": ["loop"] }"#).unwrap();
+
+        let path = SearchPath::new(vec![shared.clone()]);
+        // vendor-if.json is given as a path, output.json is found next to it,
+        // if-table.json in the search path and interfaces is built in
+        let cmd = path
+            .load(
+                vendor.join("vendor-if.json").to_str().unwrap(),
+                &Defines::new(),
+            )
+            .unwrap();
+        assert_eq!(cmd.output.ok, "Vendor interfaces are up");
+        assert_eq!(cmd.output.critical, "Vendor CRITICAL: ");
+        assert!(cmd.check().is_empty());
+
+        let err = path.load(vendor.join("loop.json").to_str().unwrap(), &Defines::new());
+        assert!(err.unwrap_err().to_string().contains("-> loop -> loop"));
+        let err = path
+            .load("unknown", &Defines::new())
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Unknown definition 'unknown'"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! `${name}` placeholders, replaced before a definition is deserialized.
+//!
+//! Their values come from `--define name=value`, or else from the defaults
+//! declared in the `defines` object of the definition:
+//!
+//! ```text
+//! { "defines": { "slot": 1, "scale": 1024 },
+//!   "collect": { "snmp": [ { "name": "temp", "oid": "1.3.6.1.4.1.9.${slot}.1", "query": "Get" } ] },
+//!   "compute": { "metrics": [ { "name": "temp", "value": "{temp} * ${scale}", "max": "${scale}" } ] } }
+//! ```
+//!
+//! A string made of a single placeholder takes the type of its value, so
+//! `"max": "${scale}"` becomes a number. Placeholders are also replaced in
+//! object keys.
+
+use crate::generic::error::{Error, Result};
+use regex::{Captures, Regex};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::LazyLock;
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_.-]*)\}").unwrap());
+
+/// Values given with `--define`, by name.
+pub type Defines = BTreeMap<String, String>;
+
+/// Parses the argument of `--define`, `name=value`.
+///
+/// # Errors
+/// Returns an error if `arg` has no `=` or an empty name.
+pub fn parse_define(arg: &str) -> Result<(String, String)> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(Error::BadDefine {
+            arg: arg.to_string(),
+        }),
+    }
+}
+
+/// Returns `true` if `text` contains a placeholder.
+pub fn has_placeholders(text: &str) -> bool {
+    PLACEHOLDER.is_match(text)
+}
+
+/// The values of the placeholders, and what was found while replacing them.
+struct Substitution<'a> {
+    defines: &'a Defines,
+    defaults: Map<String, Value>,
+    used: BTreeSet<String>,
+    unresolved: Vec<String>,
+}
+
+impl Substitution<'_> {
+    /// Returns the value of the placeholder `name`, typed when it is a
+    /// default or when a `--define` value is a number or a boolean.
+    fn value(&mut self, name: &str) -> Option<Value> {
+        self.used.insert(name.to_string());
+        if let Some(value) = self.defines.get(name) {
+            return match serde_json::from_str::<Value>(value) {
+                Ok(v @ (Value::Number(_) | Value::Bool(_))) => Some(v),
+                _ => Some(Value::String(value.clone())),
+            };
+        }
+        self.defaults.get(name).cloned()
+    }
+
+    /// Replaces the placeholders of `text`, recording the unresolved ones
+    /// with the `path` of the field holding them.
+    fn text(&mut self, text: &str, path: &str) -> String {
+        let mut unresolved = Vec::new();
+        let result = PLACEHOLDER.replace_all(text, |caps: &Captures| match self.value(&caps[1]) {
+            Some(Value::String(s)) => s,
+            Some(value) => value.to_string(),
+            None => {
+                unresolved.push(caps[0].to_string());
+                caps[0].to_string()
+            }
+        });
+        let result = result.into_owned();
+        for placeholder in unresolved {
+            self.unresolved.push(format!("{}: {}", path, placeholder));
+        }
+        result
+    }
+
+    fn replace(&mut self, value: &mut Value, path: &str) {
+        match value {
+            Value::String(s) => {
+                let exact = PLACEHOLDER
+                    .captures(s)
+                    .filter(|caps| caps[0].len() == s.len())
+                    .map(|caps| caps[1].to_string());
+                match exact.and_then(|name| self.value(&name)) {
+                    Some(v) => *value = v,
+                    None => *s = self.text(s, path),
+                }
+            }
+            Value::Array(values) => {
+                for (i, v) in values.iter_mut().enumerate() {
+                    self.replace(v, &format!("{}[{}]", path, i));
+                }
+            }
+            Value::Object(object) => {
+                let entries = std::mem::take(object);
+                for (key, mut v) in entries {
+                    let key = self.text(&key, path);
+                    let path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    self.replace(&mut v, &path);
+                    object.insert(key, v);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Replaces the placeholders of the definition `value` with the values of
+/// `defines`, or else with the defaults of its `defines` object, which is
+/// removed.
+///
+/// # Errors
+/// Returns an error listing the placeholders without value, or naming a
+/// `--define` matching no placeholder.
+pub fn substitute(value: &mut Value, defines: &Defines) -> Result<()> {
+    let defaults = match value.as_object_mut().and_then(|o| o.remove("defines")) {
+        None => Map::new(),
+        Some(Value::Object(defaults)) => defaults,
+        Some(_) => {
+            return Err(Error::InvalidJSON {
+                message: "defines: must map placeholder names to their default value".to_string(),
+            });
+        }
+    };
+    let mut substitution = Substitution {
+        defines,
+        defaults,
+        used: BTreeSet::new(),
+        unresolved: Vec::new(),
+    };
+    substitution.replace(value, "");
+    if !substitution.unresolved.is_empty() {
+        return Err(Error::UnresolvedPlaceholders {
+            list: substitution.unresolved.join(", "),
+        });
+    }
+    match defines
+        .keys()
+        .find(|name| !substitution.used.contains(*name))
+    {
+        Some(name) => Err(Error::UnusedDefine { name: name.clone() }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_substitute() {
+        use crate::definitions::placeholders::{Defines, substitute};
+        use serde_json::json;
+
+        let mut value = json!({
+            "defines": { "slot": 1, "scale": 1024 },
+            "collect": { "snmp": [ { "name": "temp", "oid": "1.3.6.${slot}.1", "query": "Get",
+                                     "labels": { ".${slot}": "x" } } ] },
+            "compute": { "metrics": [ { "name": "temp", "value": "{temp} * ${scale}", "max": "${scale}" } ] }
+        });
+        let defines = Defines::from([("slot".to_string(), "3".to_string())]);
+        substitute(&mut value, &defines).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "collect": { "snmp": [ { "name": "temp", "oid": "1.3.6.3.1", "query": "Get",
+                                         "labels": { ".3": "x" } } ] },
+                "compute": { "metrics": [ { "name": "temp", "value": "{temp} * 1024", "max": 1024 } ] }
+            })
+        );
+    }
+
+    #[test]
+    fn test_unresolved() {
+        use crate::definitions::placeholders::{Defines, parse_define, substitute};
+        use serde_json::json;
+
+        let mut value = json!({
+            "compute": { "metrics": [ { "name": "m", "value": "{a} * ${scale}", "max": "${max}" } ] }
+        });
+        let err = substitute(&mut value, &Defines::new()).unwrap_err();
+        assert!(err.to_string().starts_with(
+            "Unresolved placeholders: compute.metrics[0].value: ${scale}, compute.metrics[0].max: ${max}"
+        ));
+
+        let mut value = json!({ "defines": { "scale": 8 }, "output": { "ok": "${scale}" } });
+        let defines = Defines::from([parse_define("sacle=2").unwrap()]);
+        let err = substitute(&mut value, &defines).unwrap_err();
+        assert!(err.to_string().starts_with("--define sacle:"));
+        assert!(parse_define("scale").is_err());
+        assert!(parse_define("=2").is_err());
+    }
+}
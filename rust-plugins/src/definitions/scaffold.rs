@@ -0,0 +1,244 @@
+//! Generation of a starter definition from a walked table (`scaffold`).
+//!
+//! The columns of the table and their types are detected from the values of
+//! the walk. Every column becomes a label of a single `collect.snmp` entry,
+//! the first text column names the instances and every numeric column gets a
+//! metric, left to the author to rename and complete.
+
+use crate::generic::error::{Error, Result};
+use rasn_smi::v2::{ApplicationSyntax, ObjectSyntax, SimpleSyntax};
+use rasn_snmp::v2::{VarBind, VarBindValue};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+
+/// Name of the `collect.snmp` entry of the generated definition.
+const ENTRY: &str = "table";
+
+/// Type of a column, guessed from its values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    /// Printable text, used to name the instances.
+    Text,
+    /// Binary strings, OIDs, addresses and the index column, only collected.
+    Other,
+    /// An `INTEGER` whose values all look like the states of an enumeration.
+    Enum,
+    /// Any number, with its SNMP type.
+    Number(&'static str),
+}
+
+impl ColumnType {
+    /// Returns the type of a single value; empty strings are `Text`.
+    fn of(value: &ObjectSyntax) -> ColumnType {
+        match value {
+            ObjectSyntax::Simple(SimpleSyntax::Integer(_)) => ColumnType::Number("INTEGER"),
+            ObjectSyntax::Simple(SimpleSyntax::String(s)) => match std::str::from_utf8(s) {
+                Ok(text) if !text.chars().any(char::is_control) => ColumnType::Text,
+                _ => ColumnType::Other,
+            },
+            ObjectSyntax::Simple(SimpleSyntax::ObjectId(_)) => ColumnType::Other,
+            ObjectSyntax::ApplicationWide(value) => match value {
+                ApplicationSyntax::Address(_) => ColumnType::Other,
+                ApplicationSyntax::Counter(_) => ColumnType::Number("Counter32"),
+                ApplicationSyntax::Ticks(_) => ColumnType::Number("Timeticks"),
+                ApplicationSyntax::Arbitrary(_) => ColumnType::Other,
+                ApplicationSyntax::BigCounter(_) => ColumnType::Number("Counter64"),
+                ApplicationSyntax::Unsigned(_) => ColumnType::Number("Gauge32"),
+            },
+        }
+    }
+}
+
+/// Returns `true` if `value` is a non-empty string.
+fn non_empty(value: &ObjectSyntax) -> bool {
+    matches!(value, ObjectSyntax::Simple(SimpleSyntax::String(s)) if !s.is_empty())
+}
+
+/// Returns the type of a column from all its `values`: `Text` only if they
+/// are all printable and one at least is not empty, so that a binary column
+/// starting with an empty string, like `ifPhysAddress` of `lo`, is not taken
+/// for the names of the instances.
+fn column_type(values: &[(Vec<u32>, &ObjectSyntax)]) -> ColumnType {
+    match ColumnType::of(values[0].1) {
+        ColumnType::Text => {
+            let text = values
+                .iter()
+                .all(|(_, value)| ColumnType::of(value) == ColumnType::Text);
+            if text && values.iter().any(|(_, value)| non_empty(value)) {
+                ColumnType::Text
+            } else {
+                ColumnType::Other
+            }
+        }
+        ColumnType::Number("INTEGER") if looks_like_index(values) => ColumnType::Other,
+        ColumnType::Number("INTEGER") if looks_like_enum(values) => ColumnType::Enum,
+        t => t,
+    }
+}
+
+/// Returns the value of an `INTEGER`.
+fn integer(value: &ObjectSyntax) -> Option<i64> {
+    match value {
+        ObjectSyntax::Simple(SimpleSyntax::Integer(i)) => i64::try_from(i).ok(),
+        _ => None,
+    }
+}
+
+/// Returns `true` if each of the `values` is the index of its row, like
+/// `ifIndex`.
+fn looks_like_index(values: &[(Vec<u32>, &ObjectSyntax)]) -> bool {
+    values
+        .iter()
+        .all(|(index, value)| index.len() == 1 && integer(value) == Some(i64::from(index[0])))
+}
+
+/// Returns `true` if all the `values` are integers between 1 and 7, like
+/// the states of `ifOperStatus` or `RowStatus`.
+fn looks_like_enum(values: &[(Vec<u32>, &ObjectSyntax)]) -> bool {
+    values
+        .iter()
+        .all(|(_, value)| integer(value).is_some_and(|i| (1..=7).contains(&i)))
+}
+
+/// Builds a starter definition for the table whose entry is `oid` from the
+/// variable bindings of its walk.
+///
+/// # Errors
+/// Returns an error if no variable binding is below `oid`.
+pub fn scaffold(oid: &str, varbinds: &[VarBind]) -> Result<Value> {
+    let oid = oid.trim_start_matches('.');
+    let base: Vec<u32> = oid.split('.').filter_map(|arc| arc.parse().ok()).collect();
+    let mut columns: BTreeMap<u32, Vec<(Vec<u32>, &ObjectSyntax)>> = BTreeMap::new();
+    for var in varbinds {
+        let VarBindValue::Value(value) = &var.value else {
+            continue;
+        };
+        let arcs: Vec<u32> = var.name.iter().copied().collect();
+        // `<entry>.<column>.<index>`
+        if arcs.len() > base.len() + 1 && arcs.starts_with(&base) {
+            let index = arcs[base.len() + 1..].to_vec();
+            columns
+                .entry(arcs[base.len()])
+                .or_default()
+                .push((index, value));
+        }
+    }
+    if columns.is_empty() {
+        return Err(Error::EmptyTable {
+            oid: oid.to_string(),
+        });
+    }
+
+    let types: Vec<(u32, ColumnType)> = columns
+        .iter()
+        .map(|(column, values)| (*column, column_type(values)))
+        .collect();
+    let label = |column: u32| format!("col{}", column);
+    let labels: Map<String, Value> = types
+        .iter()
+        .map(|(column, _)| (format!(".{}", column), Value::from(label(*column))))
+        .collect();
+    let prefix = types
+        .iter()
+        .find(|(_, t)| *t == ColumnType::Text)
+        .map(|(column, _)| format!("{{{}.{}}}", ENTRY, label(*column)));
+
+    let mut metrics = Vec::new();
+    for (column, t) in types.iter() {
+        let mut metric = Map::new();
+        if let Some(prefix) = &prefix {
+            metric.insert("prefix".to_string(), Value::from(prefix.as_str()));
+        }
+        metric.insert(
+            "name".to_string(),
+            Value::from(format!("{}.{}", ENTRY, label(*column))),
+        );
+        let (description, extra) = match t {
+            ColumnType::Enum => (
+                format!("Column .{} (INTEGER enumeration)", column),
+                json!({ "type": "status" }),
+            ),
+            ColumnType::Number(kind @ ("Counter32" | "Counter64" | "Gauge32")) => (
+                format!("Column .{} ({})", column, kind),
+                json!({ "min": 0 }),
+            ),
+            ColumnType::Number(kind) => (format!("Column .{} ({})", column, kind), json!({})),
+            ColumnType::Text | ColumnType::Other => continue,
+        };
+        metric.insert("description".to_string(), Value::from(description));
+        if let Value::Object(extra) = extra {
+            metric.extend(extra);
+        }
+        metric.insert(
+            "value".to_string(),
+            Value::from(format!("{{{}.{}}}", ENTRY, label(*column))),
+        );
+        metric.insert("threshold-suffix".to_string(), Value::from(label(*column)));
+        metrics.push(Value::Object(metric));
+    }
+
+    Ok(json!({
+        "collect": {
+            "snmp": [
+                { "name": ENTRY, "oid": oid, "query": "Walk", "labels": labels }
+            ]
+        },
+        "compute": { "metrics": metrics }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_scaffold() {
+        use crate::definitions::scaffold::scaffold;
+        use crate::generic::Command;
+        use crate::snmp::snmpwalk::SnmpWalk;
+
+        let walk = SnmpWalk::parse(
+            r#".1.3.6.1.2.1.2.2.1.1.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.1.2 = INTEGER: 2
+.1.3.6.1.2.1.2.2.1.2.1 = STRING: ""
+.1.3.6.1.2.1.2.2.1.2.2 = Hex-STRING: 00 1A 2B 3C 4D 5E
+.1.3.6.1.2.1.2.2.1.3.1 = STRING: "lo"
+.1.3.6.1.2.1.2.2.1.3.2 = STRING: "eth0"
+.1.3.6.1.2.1.2.2.1.5.1 = Gauge32: 10000000
+.1.3.6.1.2.1.2.2.1.5.2 = Gauge32: 1000000000
+.1.3.6.1.2.1.2.2.1.6.1 = STRING: ""
+.1.3.6.1.2.1.2.2.1.6.2 = Hex-STRING: 00 1A 2B 3C 4D 5E
+.1.3.6.1.2.1.2.2.1.8.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.8.2 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.10.1 = Counter32: 1234
+.1.3.6.1.2.1.2.2.1.10.2 = Counter32: 5678
+.1.3.6.1.2.1.2.2.1.13.1 = INTEGER: 0
+.1.3.6.1.2.1.2.2.1.13.2 = INTEGER: 12"#,
+        )
+        .unwrap();
+        let result = walk.walk_varbinds("1.3.6.1.2.1.2.2.1").unwrap();
+        let definition = scaffold(".1.3.6.1.2.1.2.2.1", &result.varbinds).unwrap();
+
+        let snmp = &definition["collect"]["snmp"][0];
+        assert_eq!(snmp["oid"], "1.3.6.1.2.1.2.2.1");
+        assert_eq!(snmp["labels"].as_object().unwrap().len(), 8);
+        let metrics = definition["compute"]["metrics"].as_array().unwrap();
+        let names: Vec<&str> = metrics
+            .iter()
+            .map(|m| m["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["table.col5", "table.col8", "table.col10", "table.col13"]
+        );
+        // Column 2 starts with an empty string but holds binary data
+        assert_eq!(metrics[0]["prefix"], "{table.col3}");
+        assert_eq!(metrics[1]["type"], "status");
+        assert_eq!(metrics[2]["description"], "Column .10 (Counter32)");
+        assert_eq!(metrics[3]["description"], "Column .13 (INTEGER)");
+
+        let mut cmd = Command::from_value(definition).unwrap();
+        cmd.compile().unwrap();
+        assert!(cmd.check().is_empty(), "{:?}", cmd.check());
+
+        assert!(scaffold("1.3.6.1.2.1.99", &result.varbinds).is_err());
+    }
+}
@@ -0,0 +1,365 @@
+//! Listing of the instances of a definition for Centreon autodiscovery
+//! (`--list-instances`, `--disco-format` and `--disco-show`).
+//!
+//! Only the collect phase runs: each walked entry gives one instance per
+//! index, named by the `prefix` of the first metric built on that entry, and
+//! described by its label columns. The instances rejected by the `filter` of
+//! that metric are left out, as they would never be monitored.
+
+use super::{Command, QueryType, error::Result};
+use crate::compute::Parser;
+use crate::compute::ast::ExprResult;
+use crate::compute::symbols::Symbols;
+use crate::snmp::SnmpResult;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// Attributes given to every instance; label columns of the same name are
+/// not reported.
+const FIXED_ATTRIBUTES: [&str; 3] = ["entry", "name", "index"];
+
+/// An instance found by [`Command::discover`].
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Instance {
+    /// Name of the SNMP entry the instance comes from.
+    pub entry: String,
+    /// Instance name, as in the perfdata of the metrics.
+    pub name: String,
+    /// OID index of the instance in the walked table.
+    pub index: String,
+    /// Values of the label columns, after the value maps.
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Returns the value of `column` at row `i` as a string.
+fn cell(column: &ExprResult, i: usize) -> Option<String> {
+    match column {
+        ExprResult::Vector(v) => v.get(i).map(crate::output::float_string),
+        ExprResult::StrVector(v) => v.get(i).cloned(),
+        _ => None,
+    }
+}
+
+/// Returns the number of rows of `column`.
+fn rows(column: &ExprResult) -> usize {
+    match column {
+        ExprResult::Vector(v) => v.len(),
+        ExprResult::StrVector(v) => v.len(),
+        _ => 0,
+    }
+}
+
+/// Escapes `text` for an XML attribute or element.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Command {
+    /// Names of the columns of each walked entry: its labels, or the entry
+    /// itself if it has none.
+    fn walked_columns(&self) -> Vec<(&str, Vec<(&str, String)>)> {
+        self.collect
+            .snmp
+            .iter()
+            .filter(|s| matches!(s.query, QueryType::Walk))
+            .map(|s| {
+                let columns = match &s.labels {
+                    Some(labels) => {
+                        let names: BTreeSet<&str> = labels.values().map(String::as_str).collect();
+                        names
+                            .into_iter()
+                            .map(|column| (column, format!("{}.{}", s.name, column)))
+                            .collect()
+                    }
+                    None => vec![(s.name.as_str(), s.name.clone())],
+                };
+                (s.name.as_str(), columns)
+            })
+            .collect()
+    }
+
+    /// Returns the attributes reported by `--disco-show`, for
+    /// `--disco-format`.
+    pub fn disco_attributes(&self) -> Vec<String> {
+        let mut attributes: Vec<String> = FIXED_ATTRIBUTES.iter().map(|a| a.to_string()).collect();
+        for (_, columns) in self.walked_columns() {
+            for (column, _) in columns {
+                if !attributes.iter().any(|a| a == column) {
+                    attributes.push(column.to_string());
+                }
+            }
+        }
+        attributes
+    }
+
+    /// Runs the SNMP queries of the definition and lists the instances
+    /// whose name is kept by `filter_in` and `filter_out`.
+    pub fn discover(
+        &self,
+        target: &str,
+        version: &str,
+        community: &str,
+        filter_in: &[String],
+        filter_out: &[String],
+    ) -> Result<Vec<Instance>> {
+        let collect =
+            self.execute_snmp_collect(target, version, community, false, filter_in, filter_out)?;
+        self.instances(collect, filter_in, filter_out)
+    }
+
+    /// Lists the instances of already collected values.
+    ///
+    /// The command must have been compiled with [`Command::compile`].
+    pub fn instances(
+        &self,
+        collect: Vec<SnmpResult>,
+        filter_in: &[String],
+        filter_out: &[String],
+    ) -> Result<Vec<Instance>> {
+        let mut indexes: HashMap<String, Vec<String>> = HashMap::new();
+        for result in collect.iter() {
+            indexes.extend(result.indexes.clone());
+        }
+        let mut symbols = Symbols::from_results(collect)?;
+        self.apply_maps(&mut symbols)?;
+        let re_in = filter_in
+            .iter()
+            .map(|f| Regex::new(f))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let re_out = filter_out
+            .iter()
+            .map(|f| Regex::new(f))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let parser = Parser::new(&symbols, false);
+        let mut instances = Vec::new();
+        for (entry, columns) in self.walked_columns() {
+            let values: Vec<(&str, &String, &ExprResult)> = columns
+                .iter()
+                .filter_map(|(column, key)| symbols.get(key).map(|v| (*column, key, v)))
+                .collect();
+            let Some(&(_, longest, _)) = values.iter().max_by_key(|(_, _, v)| rows(v)) else {
+                continue;
+            };
+            let count = values.iter().map(|(_, _, v)| rows(v)).max().unwrap_or(0);
+
+            // The first metric whose prefix only refers to this entry names
+            // the instances.
+            let own = |name: &str| name == entry || name.starts_with(&format!("{}.", entry));
+            let mut prefix = ExprResult::Empty;
+            let mut filter = ExprResult::Number(1.0);
+            for metric in self.compute.metrics.iter() {
+                let Some(template) = &metric.compiled()?.prefix else {
+                    continue;
+                };
+                if template.macros().next().is_some() && template.macros().all(own) {
+                    prefix = parser
+                        .eval_template(template)
+                        .map_err(|e| super::field_error("Metric", metric, "prefix", e))?;
+                    filter = super::eval_filter(&parser, metric, "Metric")?;
+                    break;
+                }
+            }
+
+            for i in 0..count {
+                if !filter.is_true_at(i) {
+                    continue;
+                }
+                let index = indexes
+                    .get(longest)
+                    .and_then(|idx| idx.get(i))
+                    .cloned()
+                    .unwrap_or_else(|| (i + 1).to_string());
+                let name = match &prefix {
+                    ExprResult::StrVector(v) => v.get(i).cloned().unwrap_or(index.clone()),
+                    ExprResult::Str(s) => s.to_string(),
+                    _ => index.clone(),
+                };
+                if re_out.iter().any(|re| re.is_match(&name)) {
+                    continue;
+                }
+                if !re_in.is_empty() && !re_in.iter().any(|re| re.is_match(&name)) {
+                    continue;
+                }
+                let attributes = values
+                    .iter()
+                    .filter(|(column, _, _)| !FIXED_ATTRIBUTES.contains(column))
+                    .filter_map(|(column, _, v)| cell(v, i).map(|c| (column.to_string(), c)))
+                    .collect();
+                instances.push(Instance {
+                    entry: entry.to_string(),
+                    name,
+                    index,
+                    attributes,
+                });
+            }
+        }
+        Ok(instances)
+    }
+}
+
+/// Formats `attributes` as expected by Centreon for `--disco-format`.
+pub fn disco_format(attributes: &[String]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<data>\n");
+    for attribute in attributes {
+        let _ = writeln!(xml, "  <element>{}</element>", xml_escape(attribute));
+    }
+    xml.push_str("</data>\n");
+    xml
+}
+
+/// Formats `instances` as expected by Centreon for `--disco-show`.
+pub fn disco_show(instances: &[Instance]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<data>\n");
+    for instance in instances {
+        let _ = write!(
+            xml,
+            "  <label entry=\"{}\" name=\"{}\" index=\"{}\"",
+            xml_escape(&instance.entry),
+            xml_escape(&instance.name),
+            xml_escape(&instance.index)
+        );
+        for (column, value) in instance.attributes.iter() {
+            let _ = write!(xml, " {}=\"{}\"", column, xml_escape(value));
+        }
+        xml.push_str("/>\n");
+    }
+    xml.push_str("</data>\n");
+    xml
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_instances() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::Command;
+        use crate::generic::discovery::{disco_format, disco_show};
+        use crate::snmp::SnmpResult;
+        use std::collections::HashMap;
+
+        let mut cmd = Command::from_json(
+            r#"{
+  "collect": { "snmp": [
+    { "name": "disk", "oid": "1.3.6.1.2.1.25.2.3.1", "query": "Walk",
+      "labels": { ".3": "label", ".6": "used" } },
+    { "name": "uptime", "oid": "1.3.6.1.2.1.1.3.0", "query": "Get" } ] },
+  "compute": {
+    "metrics": [
+      { "name": "disk.used", "prefix": "{disk.label}", "value": "{disk.used}" },
+      { "name": "uptime", "value": "{uptime}" }
+    ]
+  }
+}"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        let collect = || {
+            let mut items = HashMap::new();
+            items.insert(
+                "disk.label".to_string(),
+                ExprResult::StrVector(vec!["/".to_string(), "/var & co".to_string()]),
+            );
+            items.insert(
+                "disk.used".to_string(),
+                ExprResult::Vector(vec![10.0, 20.5]),
+            );
+            let mut result = SnmpResult::new(items);
+            let indexes = vec!["31".to_string(), "36".to_string()];
+            result
+                .indexes
+                .insert("disk.label".to_string(), indexes.clone());
+            result.indexes.insert("disk.used".to_string(), indexes);
+            vec![result]
+        };
+
+        let instances = cmd.instances(collect(), &[], &[]).unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[1].name, "/var & co");
+        assert_eq!(instances[1].index, "36");
+        assert_eq!(instances[1].attributes["used"], "20.5");
+
+        let filtered = cmd.instances(collect(), &[], &["var".to_string()]).unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].name, "/");
+
+        assert_eq!(
+            disco_format(&cmd.disco_attributes()),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<data>
+  <element>entry</element>
+  <element>name</element>
+  <element>index</element>
+  <element>label</element>
+  <element>used</element>
+</data>
+"
+        );
+        assert_eq!(
+            disco_show(&filtered),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<data>
+  <label entry=\"disk\" name=\"/\" index=\"31\" label=\"/\" used=\"10\"/>
+</data>
+"
+        );
+    }
+
+    #[test]
+    fn test_instances_filter() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::Command;
+        use crate::snmp::SnmpResult;
+        use std::collections::HashMap;
+
+        let mut cmd = Command::from_json(
+            r#"{
+  "collect": { "snmp": [
+    { "name": "storage", "oid": "1.3.6.1.2.1.25.2.3.1", "query": "Walk",
+      "labels": { ".2": "type", ".3": "label", ".6": "used" } } ] },
+  "compute": {
+    "metrics": [
+      { "name": "storage.used", "prefix": "{storage.label}", "value": "{storage.used}",
+        "filter": "{storage.type} != 'ram'" }
+    ]
+  }
+}"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        let mut items = HashMap::new();
+        items.insert(
+            "storage.type".to_string(),
+            ExprResult::StrVector(vec!["ram".to_string(), "fixed".to_string()]),
+        );
+        items.insert(
+            "storage.label".to_string(),
+            ExprResult::StrVector(vec!["Physical memory".to_string(), "/".to_string()]),
+        );
+        items.insert(
+            "storage.used".to_string(),
+            ExprResult::Vector(vec![512.0, 10.0]),
+        );
+
+        // Physical memory is never monitored, so it is not discovered
+        let instances = cmd
+            .instances(vec![SnmpResult::new(items)], &[], &[])
+            .unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].name, "/");
+        assert_eq!(instances[0].index, "2");
+    }
+}
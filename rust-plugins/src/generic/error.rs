@@ -21,12 +21,79 @@ pub enum Error {
     #[snafu(display("Threshold: The threshold syntax must follow '[@]start:end'"))]
     BadThreshold,
 
+    #[snafu(display("Threshold: The entry '{entry}' must follow 'instance=threshold'"))]
+    BadInstanceThreshold { entry: String },
+
     #[snafu(display("Unknown argument: {arg}\nUse --help to display available options"))]
     UnknownArgument { arg: String },
 
+    #[snafu(display("Unknown threshold option: {arg}\nNo metric declares this threshold-suffix"))]
+    UnknownThresholdSuffix { arg: String },
+
+    #[snafu(display(
+        "Unknown definition '{name}': it is neither a file, nor in the search path ({searched}), nor a built-in mode\nUse --list-modes to display available modes"
+    ))]
+    UnknownDefinition { name: String, searched: String },
+
+    #[snafu(display("Definitions include each other: {cycle}"))]
+    IncludeCycle { cycle: String },
+
+    #[snafu(display("Unknown definition format '{format}': use json, yaml or toml"))]
+    UnknownFormat { format: String },
+
+    #[snafu(display("--define {arg}: the syntax must follow 'name=value'"))]
+    BadDefine { arg: String },
+
+    #[snafu(display("--define {name}: the definition has no placeholder ${{{name}}}"))]
+    UnusedDefine { name: String },
+
+    #[snafu(display(
+        "Unresolved placeholders: {list}\nDeclare their default in \"defines\" or use --define <name>=<value>"
+    ))]
+    UnresolvedPlaceholders { list: String },
+
     #[snafu(display("{message}"))]
     InvalidJSON { message: String },
 
+    #[snafu(display(
+        "Duplicate name '{name}': it is defined more than once by the collect or the metrics"
+    ))]
+    DuplicateName { name: String },
+
+    #[snafu(display(
+        "Nothing was found below {oid}: give the OID of a table entry, such as 1.3.6.1.2.1.2.2.1"
+    ))]
+    EmptyTable { oid: String },
+
+    #[snafu(display(
+        "SNMP entry \"{name}\": the agent has no value at {oid}\nSet its \"missing\" field to \"skip\" or {{ \"default\": <value> }} to go on without it"
+    ))]
+    MissingValue { name: String, oid: String },
+
+    #[snafu(display("The agent answered the request for {oid} with the error {status} ({name})"))]
+    AgentError {
+        status: u32,
+        name: String,
+        oid: String,
+    },
+
+    #[snafu(display("No response from the agent {target}"))]
+    NoResponse { target: String },
+
+    #[snafu(display("Invalid response from the agent {target}: {message}"))]
+    InvalidResponse { target: String, message: String },
+
+    #[snafu(display(
+        "The agent answered the request for {oid} with other variable bindings than requested"
+    ))]
+    MismatchedResponse { oid: String },
+
+    #[snafu(display("Line {line} of the snmpwalk: {message}"))]
+    InvalidSnmpWalk { line: usize, message: String },
+
+    #[snafu(display("Metric \"{name}\" is evaluated before the command is compiled"))]
+    NotCompiled { name: String },
+
     #[snafu(transparent)]
     Io { source: io::Error },
     #[snafu(transparent)]
@@ -0,0 +1,120 @@
+//! Help on the threshold options accepted by a definition (`--help`).
+
+use super::Command;
+use crate::compute::{Metric, MetricType};
+use std::fmt::Write;
+
+/// Returns the default `level` threshold of `metric` (`"warning"` or
+/// `"critical"`), combining its own value and its per-instance ones.
+fn default_threshold(metric: &Metric, level: &str) -> Option<String> {
+    let own = match level {
+        "warning" => metric.warning.clone(),
+        _ => metric.critical.clone(),
+    };
+    let instances = metric.thresholds.iter().flatten().filter_map(|(key, t)| {
+        let value = match level {
+            "warning" => t.warning.as_ref(),
+            _ => t.critical.as_ref(),
+        };
+        value.map(|v| format!("{}={}", key, v))
+    });
+    let all: Vec<String> = own.into_iter().chain(instances).collect();
+    if all.is_empty() {
+        None
+    } else {
+        Some(all.join(","))
+    }
+}
+
+impl Command {
+    /// Describes the `--warning-<suffix>` and `--critical-<suffix>` options
+    /// declared by the metrics and aggregations, in declaration order.
+    pub fn help(&self) -> String {
+        let aggregations = self.compute.aggregations.iter().flatten();
+        let metrics: Vec<&Metric> = self.compute.metrics.iter().chain(aggregations).collect();
+        let mut suffixes: Vec<&str> = Vec::new();
+        for metric in metrics.iter() {
+            if let Some(suffix) = metric.threshold_suffix.as_deref()
+                && !suffixes.contains(&suffix)
+            {
+                suffixes.push(suffix);
+            }
+        }
+        let mut help = String::new();
+        if suffixes.is_empty() {
+            help.push_str("This definition declares no threshold option.\n");
+            return help;
+        }
+        help.push_str("THRESHOLDS:\n");
+        for suffix in suffixes {
+            let _ = writeln!(
+                help,
+                "  --warning-{} <VALUE>, --critical-{} <VALUE>",
+                suffix, suffix
+            );
+            for metric in metrics
+                .iter()
+                .filter(|m| m.threshold_suffix.as_deref() == Some(suffix))
+            {
+                let unit = match metric.kind {
+                    MetricType::Status => " (status)".to_string(),
+                    MetricType::Number if metric.uom.is_empty() => "".to_string(),
+                    MetricType::Number => format!(" ({})", metric.uom),
+                };
+                let _ = write!(help, "      {}{}", metric.name, unit);
+                match &metric.description {
+                    Some(description) => {
+                        let _ = writeln!(help, ": {}", description);
+                    }
+                    None => help.push('\n'),
+                }
+                for level in ["warning", "critical"] {
+                    if let Some(value) = default_threshold(metric, level) {
+                        let _ = writeln!(help, "        default {}: {}", level, value);
+                    }
+                }
+            }
+        }
+        help
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_help() {
+        use crate::generic::Command;
+
+        let cmd = Command::from_json(
+            r#"{
+  "collect": { "snmp": [
+    { "name": "disk", "oid": "1.3.6.1.2.1.25.2.3.1", "query": "Walk",
+      "labels": { ".3": "label", ".5": "size", ".6": "used" } } ] },
+  "compute": {
+    "metrics": [
+      { "name": "disk.usage.percent", "prefix": "{disk.label}",
+        "value": "100 * {disk.used} / {disk.size}", "uom": "%",
+        "threshold-suffix": "usage", "description": "Used space of each disk",
+        "warning": "80", "thresholds": { "/var": { "critical": "95" } } },
+      { "name": "disk.used", "value": "{disk.used}" }
+    ],
+    "aggregations": [
+      { "name": "avg.disk.usage.percent", "value": "Average({metrics.disk.usage.percent})",
+        "uom": "%", "threshold-suffix": "usage" }
+    ]
+  }
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cmd.help(),
+            "THRESHOLDS:
+  --warning-usage <VALUE>, --critical-usage <VALUE>
+      disk.usage.percent (%): Used space of each disk
+        default warning: 80
+        default critical: /var=95
+      avg.disk.usage.percent (%)
+"
+        );
+    }
+}
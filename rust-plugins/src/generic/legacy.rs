@@ -0,0 +1,355 @@
+//! Conversion of the legacy `leaf`/`entries` definitions (`--convert-legacy`).
+//!
+//! The first definitions described a single leaf with a flat list of
+//! `Query` and `Agregation` entries:
+//!
+//! ```text
+//! { "leaf": { "name": "cpu",
+//!             "output": "{status}: {count} CPU(s) average usage is {total_cpu_avg} %",
+//!             "entries": [
+//!               { "Query": { "name": "cpu_{idx}", "oid": "1.3.6.1.2.1.25.3.3.1.2", "query": "Walk" } },
+//!               { "Agregation": { "name": "total_cpu_avg", "op": "Average" } } ],
+//!             "data": { "uom": "%", "min": 0, "max": 100 } } }
+//! ```
+//!
+//! Each query becomes a `collect.snmp` entry and a metric, each aggregation
+//! an entry of `compute.aggregations`, and the output templates are rewritten
+//! with the current macros. What has no equivalent is reported.
+
+use super::error::{Error, Result};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Legacy {
+    leaf: Leaf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Leaf {
+    name: String,
+    output: Option<LegacyOutput>,
+    entries: Vec<Entry>,
+    data: Option<Data>,
+}
+
+/// The three forms taken by the legacy `output` field.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyOutput {
+    /// A single template.
+    Text(String),
+    /// A header followed by the global line and the per-instance line.
+    Lines {
+        header: Option<String>,
+        text: Vec<String>,
+    },
+    /// The global line (`default`) and the per-instance line (`status`).
+    Status {
+        status: Option<String>,
+        default: String,
+    },
+}
+
+#[derive(Deserialize)]
+enum Entry {
+    Query {
+        name: String,
+        oid: String,
+        query: String,
+    },
+    Agregation {
+        name: String,
+        op: String,
+    },
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Data {
+    uom: Option<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+/// A legacy definition translated into the current format.
+pub struct Conversion {
+    /// The translated definition.
+    pub definition: Value,
+    /// What could not be translated, one sentence per problem.
+    pub problems: Vec<String>,
+}
+
+/// Removes the `{idx}` macro (and the separator before it) from a query name:
+/// `cpu_{idx}` becomes `cpu`.
+fn strip_idx(name: &str) -> String {
+    name.replace("_{idx}", "")
+        .replace(".{idx}", "")
+        .replace("{idx}", "")
+}
+
+/// Sets the `uom`, `min` and `max` shared by every legacy metric.
+fn add_data(metric: &mut Map<String, Value>, data: &Data) {
+    if let Some(uom) = &data.uom {
+        metric.insert("uom".to_string(), json!(uom));
+    }
+    if let Some(min) = data.min {
+        metric.insert("min".to_string(), json!(min));
+    }
+    if let Some(max) = data.max {
+        metric.insert("max".to_string(), json!(max));
+    }
+}
+
+/// Converts the legacy definition `json`.
+///
+/// # Errors
+/// Returns an error if `json` is not a legacy definition.
+pub fn convert(json: &str) -> Result<Conversion> {
+    let deserializer = &mut serde_json::Deserializer::from_str(json);
+    let legacy: Legacy =
+        serde_path_to_error::deserialize(deserializer).map_err(|e| Error::InvalidJSON {
+            message: e.to_string(),
+        })?;
+    let leaf = legacy.leaf;
+    let data = leaf.data.unwrap_or_default();
+    let mut problems = Vec::new();
+
+    let mut snmp = Vec::new();
+    let mut metrics = Vec::new();
+    let mut queries = Vec::new();
+    let mut walk = None;
+    for entry in leaf.entries.iter() {
+        if let Entry::Query { name, oid, query } = entry {
+            let name = strip_idx(name);
+            snmp.push(json!({ "name": name, "oid": oid, "query": query }));
+            let mut metric = Map::new();
+            metric.insert("name".to_string(), json!(name));
+            metric.insert("value".to_string(), json!(format!("{{{}}}", name)));
+            add_data(&mut metric, &data);
+            metric.insert("threshold-suffix".to_string(), json!(name));
+            metrics.push(Value::Object(metric));
+            if walk.is_none() && query == "Walk" {
+                walk = Some(name.clone());
+            }
+            queries.push(name);
+        }
+    }
+    // Aggregations and {count} apply to the values of the walked query
+    let target = walk.or_else(|| queries.first().cloned());
+    if queries.len() > 1
+        && let Some(target) = &target
+    {
+        problems.push(format!(
+            "Leaf \"{}\" has several queries: aggregations and {{count}} use \"{}\"",
+            leaf.name, target
+        ));
+    }
+
+    let mut aggregations = Vec::new();
+    let mut names = Vec::new();
+    for entry in leaf.entries.iter() {
+        if let Entry::Agregation { name, op } = entry {
+            let func = match op.as_str() {
+                "Average" | "Min" | "Max" => op.as_str(),
+                _ => {
+                    problems.push(format!(
+                        "Agregation \"{}\": the operation '{}' has no equivalent",
+                        name, op
+                    ));
+                    continue;
+                }
+            };
+            let Some(target) = &target else {
+                problems.push(format!(
+                    "Agregation \"{}\": there is no query to aggregate",
+                    name
+                ));
+                continue;
+            };
+            let mut metric = Map::new();
+            metric.insert("name".to_string(), json!(name));
+            metric.insert(
+                "value".to_string(),
+                json!(format!("{}({{metrics.{}}})", func, target)),
+            );
+            add_data(&mut metric, &data);
+            metric.insert("threshold-suffix".to_string(), json!(name));
+            aggregations.push(Value::Object(metric));
+            names.push(name.clone());
+        }
+    }
+
+    let (header, global, instance) = match leaf.output {
+        Some(LegacyOutput::Text(text)) => (None, Some(text), None),
+        Some(LegacyOutput::Lines { header, mut text }) => {
+            if text.len() > 2 {
+                problems.push(format!(
+                    "Output: only two lines of text are supported, {} are ignored",
+                    text.len() - 2
+                ));
+            }
+            let mut lines = text.drain(..);
+            (header, lines.next(), lines.next())
+        }
+        Some(LegacyOutput::Status { status, default }) => (None, Some(default), status),
+        None => (None, None, None),
+    };
+    if let Some(instance) = instance {
+        problems.push(format!(
+            "Output: the per-instance line '{}' has no equivalent, instances are detailed as '<instance>#<metric>'",
+            instance
+        ));
+    }
+
+    // Rewrites the legacy macros of the global line for the `ok` template
+    let re = Regex::new(r"\{([^{}]*)\}").unwrap();
+    let mut count = false;
+    let mut ok = None;
+    if let Some(global) = global {
+        let text = format!("{}{}", header.as_deref().unwrap_or(""), global);
+        ok = Some(
+            re.replace_all(&text, |caps: &Captures| match &caps[1] {
+                "status" => "OK".to_string(),
+                "count" if target.is_some() => {
+                    count = true;
+                    "{aggregations.count}".to_string()
+                }
+                name if names.iter().any(|n| n == name) => format!("{{aggregations.{}}}", name),
+                name if queries.iter().any(|q| q == name) => format!("{{metrics.{}}}", name),
+                name => {
+                    problems.push(format!("Output: the macro {{{}}} has no equivalent", name));
+                    caps[0].to_string()
+                }
+            })
+            .into_owned(),
+        );
+    }
+    if count && let Some(target) = &target {
+        aggregations.push(json!({
+            "name": "count",
+            "value": format!("Count({{metrics.{}}})", target),
+        }));
+    }
+
+    let mut output = Map::new();
+    if let Some(ok) = ok {
+        output.insert("ok".to_string(), json!(ok));
+    }
+    if let Some(header) = header {
+        for (field, status) in [
+            ("warning", "WARNING"),
+            ("critical", "CRITICAL"),
+            ("unknown", "UNKNOWN"),
+        ] {
+            output.insert(field.to_string(), json!(header.replace("{status}", status)));
+        }
+    }
+
+    let mut compute = Map::new();
+    compute.insert("metrics".to_string(), Value::Array(metrics));
+    if !aggregations.is_empty() {
+        compute.insert("aggregations".to_string(), Value::Array(aggregations));
+    }
+    let mut definition = Map::new();
+    definition.insert("collect".to_string(), json!({ "snmp": snmp }));
+    definition.insert("compute".to_string(), Value::Object(compute));
+    if !output.is_empty() {
+        definition.insert("output".to_string(), Value::Object(output));
+    }
+    Ok(Conversion {
+        definition: Value::Object(definition),
+        problems,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_convert_cpu() {
+        use crate::generic::Command;
+        use crate::generic::legacy::convert;
+
+        let conversion = convert(
+            r#"{
+  "leaf": {
+    "name": "cpu",
+    "output": {
+      "header": "{status}: ",
+      "text": [
+        "{count} CPU(s) average usage is {total_cpu_avg} %",
+        "CPU '{idx}' usage: {value} %"
+      ]
+    },
+    "entries": [
+      { "Agregation": { "name": "total_cpu_avg", "op": "Average" } },
+      { "Query": { "name": "cpu_{idx}", "oid": "1.3.6.1.2.1.25.3.3.1.2", "query": "Walk" } }
+    ],
+    "data": { "uom": "%", "min": 0, "max": 100 }
+  }
+}"#,
+        )
+        .unwrap();
+        let definition = &conversion.definition;
+        assert_eq!(definition["collect"]["snmp"][0]["name"], "cpu");
+        assert_eq!(
+            definition["compute"]["aggregations"][0]["value"],
+            "Average({metrics.cpu})"
+        );
+        assert_eq!(
+            definition["compute"]["aggregations"][1]["value"],
+            "Count({metrics.cpu})"
+        );
+        assert_eq!(
+            definition["output"]["ok"],
+            "OK: {aggregations.count} CPU(s) average usage is {aggregations.total_cpu_avg} %"
+        );
+        assert_eq!(definition["output"]["critical"], "CRITICAL: ");
+        assert_eq!(
+            conversion.problems,
+            vec![
+                "Output: the per-instance line 'CPU '{idx}' usage: {value} %' has no equivalent, instances are detailed as '<instance>#<metric>'"
+            ]
+        );
+        // The result is a valid definition
+        let cmd = Command::from_json(&definition.to_string()).unwrap();
+        assert!(cmd.check().is_empty());
+    }
+
+    #[test]
+    fn test_convert_reports_problems() {
+        use crate::generic::legacy::convert;
+
+        let conversion = convert(
+            r#"{
+  "leaf": {
+    "name": "mem",
+    "output": "{status}: {used} used, {unknown} {total}",
+    "entries": [
+      { "Query": { "name": "used", "oid": "1.3.6.1.4.1.2021.4.6.0", "query": "Get" } },
+      { "Query": { "name": "free", "oid": "1.3.6.1.4.1.2021.4.4.0", "query": "Get" } },
+      { "Agregation": { "name": "total", "op": "Sum" } }
+    ]
+  }
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            conversion.definition["output"]["ok"],
+            "OK: {metrics.used} used, {unknown} {total}"
+        );
+        assert_eq!(
+            conversion.problems,
+            vec![
+                "Leaf \"mem\" has several queries: aggregations and {count} use \"used\"",
+                "Agregation \"total\": the operation 'Sum' has no equivalent",
+                "Output: the macro {unknown} has no equivalent",
+                "Output: the macro {total} has no equivalent",
+            ]
+        );
+        assert!(convert(r#"{ "collect": { "snmp": [] } }"#).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Linting of command definitions (`--lint`).
+//!
+//! Unlike [`Command::check`], which rejects definitions that cannot be
+//! evaluated, the linter reports parts of a valid definition that are most
+//! likely mistakes: values collected but never used, thresholds shared by
+//! accident, and unknown macros in the output. Invalid OIDs are already
+//! rejected by [`Command::compile`].
+
+use super::{Command, QueryType};
+use std::collections::{BTreeMap, HashSet};
+
+impl Command {
+    /// Returns warnings about the parts of the compiled command that are
+    /// valid but most likely wrong.
+    pub fn lint(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let aggregations = self.compute.aggregations.iter().flatten();
+        let metrics: Vec<_> = self.compute.metrics.iter().chain(aggregations).collect();
+
+        let mut used: HashSet<&str> = self.output.ok_template().macros().collect();
+        for metric in metrics.iter() {
+            if let Ok(compiled) = metric.compiled() {
+                used.extend(compiled.macros());
+            }
+        }
+
+        let mut known: HashSet<String> = HashSet::new();
+        for s in self.collect.snmp.iter() {
+            let mut labels: Vec<(&String, &String)> = s.labels.iter().flatten().collect();
+            labels.sort();
+            let columns: Vec<String> = labels
+                .iter()
+                .map(|(_, label)| format!("{}.{}", s.name, label))
+                .collect();
+            if !used.contains(s.name.as_str()) && !columns.iter().any(|c| used.contains(c.as_str()))
+            {
+                let query = match s.query {
+                    QueryType::Get => "got",
+                    QueryType::Walk => "walked",
+                };
+                warnings.push(format!(
+                    "SNMP entry \"{}\" is {} but never used by any expression",
+                    s.name, query
+                ));
+            } else {
+                for ((_, label), column) in labels.iter().zip(columns.iter()) {
+                    if !used.contains(column.as_str()) {
+                        warnings.push(format!(
+                            "SNMP entry \"{}\", label \"{}\": the column {{{}}} is never used",
+                            s.name, label, column
+                        ));
+                    }
+                }
+            }
+            known.insert(s.name.clone());
+            known.extend(columns);
+        }
+
+        let mut suffixes: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for metric in metrics.iter() {
+            known.insert(format!("metrics.{}", metric.name));
+            if let Some(suffix) = &metric.threshold_suffix {
+                suffixes.entry(suffix).or_default().push(&metric.name);
+            }
+        }
+        for metric in self.compute.aggregations.iter().flatten() {
+            known.insert(format!("aggregations.{}", metric.name));
+        }
+        for (suffix, names) in suffixes.iter().filter(|(_, names)| names.len() > 1) {
+            warnings.push(format!(
+                "threshold-suffix \"{}\" is shared by \"{}\": --warning-{} and --critical-{} apply to all of them",
+                suffix,
+                names.join("\", \""),
+                suffix,
+                suffix
+            ));
+        }
+
+        for name in self.output.ok_template().macros() {
+            if !known.contains(name) {
+                warnings.push(format!("Output, field \"ok\": unknown macro {{{}}}", name));
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_lint() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::Command;
+        use crate::snmp::snmpwalk::SnmpWalk;
+
+        let mut cmd: Command = serde_json::from_str(
+            r#"{
+  "collect": {
+    "snmp": [
+      { "name": "tcpConnectionState", "oid": "1.3.6.1.2.1.6.19.1.7", "query": "Walk" },
+      { "name": "tcpListenerProcess", "oid": "1.3.6.1.2.1.6.20.1.4", "query": "Walk" },
+      { "name": "bad", "oid": "1.3.6.1.2.1.1.3.0", "query": "Get" },
+      { "name": "disk", "oid": ".1.3.6.1.2.1.25.2.3.1", "query": "Walk",
+        "labels": { ".3": "label", ".5": "size", ".6": "used" } }
+    ]
+  },
+  "compute": {
+    "metrics": [
+      { "name": "tcp.connections", "value": "Average({tcpConnectionState})",
+        "threshold-suffix": "usage" },
+      { "name": "disk.used", "value": "{disk.used} / {bad}", "prefix": "{disk.label}",
+        "threshold-suffix": "usage" }
+    ]
+  },
+  "output": { "ok": "{metrics.tcp.connections} connections, {disk.free} free" }
+}"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        assert_eq!(
+            cmd.lint(),
+            vec![
+                "SNMP entry \"tcpListenerProcess\" is walked but never used by any expression",
+                "SNMP entry \"disk\", label \"size\": the column {disk.size} is never used",
+                "threshold-suffix \"usage\" is shared by \"tcp.connections\", \"disk.used\": --warning-usage and --critical-usage apply to all of them",
+                "Output, field \"ok\": unknown macro {disk.free}",
+            ]
+        );
+
+        // The leading '.' of a table with labels is no mistake
+        let walk = SnmpWalk::parse(
+            r#".1.3.6.1.2.1.25.2.3.1.3.1 = STRING: "/"
+.1.3.6.1.2.1.25.2.3.1.5.1 = INTEGER: 100
+.1.3.6.1.2.1.25.2.3.1.6.1 = INTEGER: 42"#,
+        )
+        .unwrap();
+        let result = walk.collect(&cmd.queries(None)).unwrap();
+        assert!(matches!(&result.items["disk.label"], ExprResult::StrVector(v) if v == &["/"]));
+        assert!(matches!(&result.items["disk.used"], ExprResult::Vector(v) if v == &[42.0]));
+    }
+}
@@ -9,16 +9,28 @@ extern crate regex;
 extern crate serde;
 extern crate serde_json;
 
+pub mod discovery;
 pub mod error;
+mod help;
+pub mod legacy;
+mod lint;
 
 use self::error::Result;
-use crate::compute::{Compute, Parser, ast::ExprResult, threshold::Threshold};
+use crate::compute::check::{Checker, Kind, Shape, Type};
+use crate::compute::symbols::Symbols;
+use crate::compute::{
+    CompiledValue, Compute, Expression, Metric, Parser, ast::ExprResult, threshold::Threshold,
+};
 use crate::output::{Output, OutputFormatter};
-use crate::snmp::{snmp_bulk_get, snmp_bulk_walk, snmp_bulk_walk_with_labels};
+use crate::snmp::parse_oid;
+use crate::snmp::planner::{Keep, Query, snmp_collect};
+use crate::snmp::snmpwalk::SnmpWalk;
 use log::{debug, trace};
 use regex::Regex;
+use schemars::JsonSchema;
+use schemars::schema::RootSchema;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::snmp::SnmpResult;
 
@@ -30,6 +42,8 @@ use crate::snmp::SnmpResult;
 pub struct Perfdata<'p> {
     pub name: String,
     pub value: f64,
+    /// Value of a status metric, which is shown in messages but not in perfdata.
+    pub text: Option<String>,
     pub uom: &'p str,
     pub min: Option<f64>,
     pub max: Option<f64>,
@@ -101,30 +115,125 @@ fn worst(a: Status, b: Status) -> Status {
 }
 
 /// Type of SNMP query to perform for a given OID.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 enum QueryType {
-    /// Retrieve a single leaf OID value (`GetBulkRequest` with one OID).
+    /// Retrieve a single leaf OID value, packed with the other ones into
+    /// `GetRequest`s.
     Get,
     /// Walk a subtree using repeated `GetBulkRequest` calls.
     Walk,
 }
 
 /// Description of a single SNMP collection entry as read from the JSON config.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Snmp {
     /// Logical name used to reference collected values in compute expressions.
     name: String,
     /// The OID to query (may start with a leading `.`).
     oid: String,
     query: QueryType,
-    /// Optional label map used by [`snmp_bulk_walk_with_labels`] to split
-    /// a subtree walk into named sub-vectors.
+    /// Optional label map of column suffixes to names: only these columns
+    /// of the table are walked, each one into its own named sub-vector.
     labels: Option<HashMap<String, String>>,
+    /// Optional label of the column naming the instances. When instances
+    /// are filtered, only this column is walked and the other labels are
+    /// fetched with `GetRequest`s for the instances kept by the filters.
+    #[serde(rename = "filter-label")]
+    filter_label: Option<String>,
+    /// What to do when the agent has no value for a `Get` entry, `fail` by
+    /// default.
+    missing: Option<Missing>,
+    /// Optional value maps translating collected values into strings, keyed by
+    /// label name (or by `name` for entries without labels).
+    map: Option<HashMap<String, ValueMap>>,
+}
+
+/// A value map used by [`Snmp::map`]: either the name of a map declared in
+/// [`Command::maps`], or an inline map.
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(untagged)]
+enum ValueMap {
+    Named(String),
+    Inline(HashMap<String, String>),
+}
+
+/// What to do when the agent answers a `Get` with `noSuchObject` or
+/// `noSuchInstance`, given by [`Snmp::missing`].
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+enum Missing {
+    /// The execution fails with an UNKNOWN status.
+    #[default]
+    Fail,
+    /// The metrics and aggregations using the entry are left out.
+    Skip,
+    /// The entry takes this value, e.g. `{ "default": 0 }`.
+    Default(DefaultValue),
+}
+
+/// The value of a missing entry with the [`Missing::Default`] policy.
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(untagged)]
+enum DefaultValue {
+    Number(f64),
+    Text(String),
+}
+
+impl DefaultValue {
+    fn to_result(&self) -> ExprResult {
+        match self {
+            DefaultValue::Number(n) => ExprResult::Vector(vec![*n]),
+            DefaultValue::Text(s) => ExprResult::StrVector(vec![s.clone()]),
+        }
+    }
+}
+
+/// The `--filter-in` and `--filter-out` regexes selecting instances by name.
+struct InstanceFilter {
+    re_in: Vec<Regex>,
+    re_out: Vec<Regex>,
+}
+
+impl InstanceFilter {
+    fn new(filter_in: &[String], filter_out: &[String]) -> Result<InstanceFilter> {
+        let compile = |filters: &[String]| {
+            filters
+                .iter()
+                .map(|f| Regex::new(f))
+                .collect::<std::result::Result<Vec<_>, _>>()
+        };
+        Ok(InstanceFilter {
+            re_in: compile(filter_in)?,
+            re_out: compile(filter_out)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.re_in.is_empty() && self.re_out.is_empty()
+    }
+
+    /// Returns `true` if the instance `name` is kept.
+    fn keeps(&self, name: &str) -> bool {
+        !self.re_out.iter().any(|re| re.is_match(name))
+            && (self.re_in.is_empty() || self.re_in.iter().any(|re| re.is_match(name)))
+    }
+}
+
+/// Normalizes a collected value or a map key so that integers and OIDs
+/// written with or without leading/trailing dots match.
+fn map_key(value: &str) -> &str {
+    value.trim_matches('.')
 }
 
 /// Groups all SNMP queries that must be executed before computing metrics.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Collect {
+    /// Maximum number of entries collected at once, each through its own
+    /// socket (default: 1, all the entries sharing the same requests).
+    /// Overridden by `--snmp-concurrency`.
+    concurrency: Option<usize>,
     snmp: Vec<Snmp>,
 }
 
@@ -132,8 +241,22 @@ pub struct Collect {
 ///
 /// A `Command` ties together SNMP collection, metric computation, and output
 /// formatting.  Use [`Command::execute`] to run the full pipeline.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Command {
+    /// Reusable value maps (e.g. `ifOperStatus`: `1` → `up`), referenced by name
+    /// from [`Snmp::map`].
+    #[serde(default)]
+    maps: HashMap<String, HashMap<String, String>>,
+    /// Name of the definition this one is based on, merged by
+    /// [`SearchPath::load`](crate::definitions::SearchPath::load).
+    extends: Option<String>,
+    /// Names of the definitions merged into this one by
+    /// [`SearchPath::load`](crate::definitions::SearchPath::load).
+    include: Option<Vec<String>>,
+    /// Default values of the `${name}` placeholders, replaced by
+    /// [`SearchPath::load`](crate::definitions::SearchPath::load).
+    defines: Option<HashMap<String, serde_json::Value>>,
     collect: Collect,
     compute: Compute,
     #[serde(default = "default_output")]
@@ -153,89 +276,495 @@ pub struct CmdResult {
     pub output: String,
 }
 
-fn compute_status(value: &f64, warn: &Option<String>, crit: &Option<String>) -> Result<Status> {
-    if let Some(c) = crit {
-        let crit = Threshold::parse(c)?;
+fn compute_status(
+    value: &f64,
+    warn: Option<(&str, &Threshold)>,
+    crit: Option<(&str, &Threshold)>,
+) -> Status {
+    if let Some((_, crit)) = crit {
         if crit.in_alert(*value) {
-            return Ok(Status::Critical);
+            return Status::Critical;
         }
     }
-    if let Some(w) = warn {
-        let warn = Threshold::parse(w)?;
+    if let Some((_, warn)) = warn {
         if warn.in_alert(*value) {
-            return Ok(Status::Warning);
+            return Status::Warning;
         }
     }
-    Ok(Status::Ok)
+    Status::Ok
 }
 
-impl Command {
-    /// Sets the warning threshold for the metric identified by `name`
-    /// (matched against `threshold_suffix` in the compute config).
-    pub fn add_warning(&mut self, name: &str, value: String) {
-        if let Some(metric) =
-            self.compute
-                .metrics
-                .iter_mut()
-                .find(|metric| match &metric.threshold_suffix {
-                    Some(suffix) => suffix == name,
-                    None => false,
-                })
-        {
-            debug!("Adding warning to metric {}", metric.name);
-            metric.warning = Some(value);
-        } else if let Some(aggregations) = self.compute.aggregations.as_mut() {
-            if let Some(metric) =
-                aggregations
-                    .iter_mut()
-                    .find(|metric| match &metric.threshold_suffix {
-                        Some(suffix) => suffix == name,
-                        None => false,
-                    })
-            {
-                debug!("Adding warning to aggregation metric {}", metric.name);
-                metric.warning = Some(value);
+/// Threshold level set from the command line.
+#[derive(Debug, Copy, Clone)]
+enum Level {
+    Warning,
+    Critical,
+}
+
+/// Matches the `<suffix>` of `--warning-<suffix>`/`--critical-<suffix>`
+/// against the `threshold-suffix` declared by metrics.
+///
+/// A suffix containing `*` or `?` is a glob, a suffix starting with `re:` is
+/// a regular expression, anything else must match exactly.
+enum SuffixPattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl SuffixPattern {
+    fn parse(name: &str) -> Result<SuffixPattern> {
+        if let Some(re) = name.strip_prefix("re:") {
+            Ok(SuffixPattern::Regex(Regex::new(re)?))
+        } else if name.contains(['*', '?']) {
+            let mut re = String::from("^");
+            for c in name.chars() {
+                match c {
+                    '*' => re.push_str(".*"),
+                    '?' => re.push('.'),
+                    c => re.push_str(&regex::escape(&c.to_string())),
+                }
             }
+            re.push('$');
+            Ok(SuffixPattern::Regex(Regex::new(&re)?))
+        } else {
+            Ok(SuffixPattern::Exact(name.to_string()))
+        }
+    }
+
+    fn matches(&self, suffix: &Option<String>) -> bool {
+        match (self, suffix) {
+            (SuffixPattern::Exact(name), Some(suffix)) => name == suffix,
+            (SuffixPattern::Regex(re), Some(suffix)) => re.is_match(suffix),
+            (_, None) => false,
+        }
+    }
+}
+
+/// Builds the error reported when `field` of `metric` cannot be evaluated.
+fn field_error(kind: &str, metric: &Metric, field: &str, e: String) -> error::Error {
+    error::Error::InvalidJSON {
+        message: format!("{} \"{}\", field \"{}\": {}", kind, metric.name, field, e),
+    }
+}
+
+/// Returns the name of the instance `i` of the `len` values of a metric,
+/// from its evaluated `prefix`, or the next number `idx` if it has none.
+fn instance_name(
+    prefix: &ExprResult,
+    i: usize,
+    len: usize,
+    idx: &mut u32,
+    kind: &str,
+    metric: &Metric,
+) -> Result<String> {
+    match prefix {
+        ExprResult::StrVector(names) => match names.get(i) {
+            Some(name) => Ok(name.to_string()),
+            None => {
+                let e = format!("{} names for {} values", names.len(), len);
+                Err(field_error(kind, metric, "prefix", e))
+            }
+        },
+        ExprResult::Str(s) => Ok(s.to_string()),
+        ExprResult::Empty => {
+            let res = idx.to_string();
+            *idx += 1;
+            Ok(res)
+        }
+        _ => {
+            let e = "the names of the instances must be strings".to_string();
+            Err(field_error(kind, metric, "prefix", e))
         }
     }
+}
 
-    /// Sets the critical threshold for the metric identified by `name`
-    /// (matched against `threshold_suffix` in the compute config).
-    pub fn add_critical(&mut self, name: &str, value: String) {
-        if let Some(metric) =
-            self.compute
-                .metrics
-                .iter_mut()
-                .find(|metric| match &metric.threshold_suffix {
-                    Some(suffix) => suffix == name,
-                    None => false,
-                })
+/// Returns the single number `res` a `min_expr` or `max_expr` of an
+/// aggregation evaluates to.
+fn single_number(res: ExprResult, metric: &Metric, field: &str) -> Result<f64> {
+    match res {
+        ExprResult::Number(v) => Ok(v),
+        ExprResult::Vector(v) if v.len() == 1 => Ok(v[0]),
+        _ => {
+            let e = "it must be a single number".to_string();
+            Err(field_error("Aggregation", metric, field, e))
+        }
+    }
+}
+
+/// Evaluates the `filter` expression of a metric. Without filter, every
+/// instance is kept.
+fn eval_filter(parser: &Parser, metric: &Metric, kind: &str) -> Result<ExprResult> {
+    match &metric.compiled()?.filter {
+        Some(filter) => parser
+            .eval_expr(filter)
+            .map_err(|e| field_error(kind, metric, "filter", e)),
+        None => Ok(ExprResult::Number(1.0)),
+    }
+}
+
+/// Evaluates a [`MetricType::Status`](crate::compute::MetricType::Status) metric: its textual
+/// value per instance and the `warning`/`critical` boolean expressions deciding its status.
+///
+/// Pushes one [`Perfdata`] per instance kept by the filters, and returns the
+/// textual values of the instances kept by the `filter` expression so that
+/// they can be referenced as `{metrics.<name>}`.
+fn compute_status_metric<'m>(
+    parser: &Parser,
+    metric: &'m Metric,
+    idx: &mut u32,
+    re_in: &[Regex],
+    re_out: &[Regex],
+    status: &mut Status,
+    metrics: &mut Vec<Perfdata<'m>>,
+) -> Result<ExprResult> {
+    let compiled = metric.compiled()?;
+    let CompiledValue::Status {
+        value,
+        warning,
+        critical,
+    } = &compiled.value
+    else {
+        return Ok(ExprResult::Empty);
+    };
+    let eval = |field: &str, expr: &Option<Expression>| match expr {
+        Some(expr) => parser
+            .eval_expr(expr)
+            .map_err(|e| field_error("Metric", metric, field, e)),
+        None => Ok(ExprResult::Empty),
+    };
+    let filter = eval_filter(parser, metric, "Metric")?;
+    let texts = match parser
+        .eval_template(value)
+        .map_err(|e| field_error("Metric", metric, "value", e))?
+    {
+        ExprResult::Str(s) => vec![s],
+        ExprResult::StrVector(v) => v,
+        _ => vec![],
+    };
+    let prefix = match &compiled.prefix {
+        Some(prefix) => parser
+            .eval_template(prefix)
+            .map_err(|e| field_error("Metric", metric, "prefix", e))?,
+        None => ExprResult::Empty,
+    };
+    let warning = eval("warning", warning)?;
+    let critical = eval("critical", critical)?;
+    let mut kept = Vec::with_capacity(texts.len());
+    for (i, text) in texts.iter().enumerate() {
+        if !filter.is_true_at(i) {
+            continue;
+        }
+        kept.push(text.clone());
+        let instance_name = match &prefix {
+            ExprResult::StrVector(v) => v.get(i).cloned().unwrap_or_default(),
+            ExprResult::Str(s) => s.to_string(),
+            _ => {
+                let res = idx.to_string();
+                *idx += 1;
+                res
+            }
+        };
+        if re_out.iter().any(|re| re.is_match(&instance_name))
+            || (!re_in.is_empty() && !re_in.iter().any(|re| re.is_match(&instance_name)))
         {
-            metric.critical = Some(value);
-            debug!("Adding critical to metric {}", metric.name);
-        } else if let Some(aggregations) = self.compute.aggregations.as_mut() {
-            if let Some(metric) =
-                aggregations
-                    .iter_mut()
-                    .find(|metric| match &metric.threshold_suffix {
-                        Some(suffix) => suffix == name,
-                        None => false,
-                    })
+            continue;
+        }
+        let current_status = if critical.is_true_at(i) {
+            Status::Critical
+        } else if warning.is_true_at(i) {
+            Status::Warning
+        } else {
+            Status::Ok
+        };
+        *status = worst(*status, current_status);
+        let m = Perfdata {
+            name: format!("'{}#{}'", instance_name, metric.name),
+            value: f64::NAN,
+            text: Some(text.clone()),
+            uom: &metric.uom,
+            min: None,
+            max: None,
+            warning: None,
+            critical: None,
+            status: Some(current_status),
+        };
+        trace!("New status '{}' with value {:?}", m.name, m.text);
+        metrics.push(m);
+    }
+    Ok(ExprResult::StrVector(kept))
+}
+
+impl Command {
+    /// Deserializes a command from its JSON definition and compiles it.
+    ///
+    /// # Errors
+    /// Returns an error locating the offending field (e.g.
+    /// ``compute.metrics[0]: unknown field `treshold-suffix` ``) if the
+    /// definition does not follow the schema, or a compilation error.
+    pub fn from_json(json: &str) -> Result<Command> {
+        let deserializer = &mut serde_json::Deserializer::from_str(json);
+        Command::deserialize_from(deserializer)
+    }
+
+    /// Deserializes a command from an already parsed JSON definition, such
+    /// as the result of merging several definitions, and compiles it.
+    ///
+    /// # Errors
+    /// Same as [`Command::from_json`].
+    pub fn from_value(json: serde_json::Value) -> Result<Command> {
+        Command::deserialize_from(json)
+    }
+
+    fn deserialize_from<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Command>
+    where
+        D::Error: std::fmt::Display,
+    {
+        let mut command: Command = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            error::Error::InvalidJSON {
+                message: e.to_string(),
+            }
+        })?;
+        if command.extends.is_some() || command.include.is_some() || command.defines.is_some() {
+            return Err(error::Error::InvalidJSON {
+                message:
+                    "extends, include, defines: the definition must be loaded from the search path"
+                        .to_string(),
+            });
+        }
+        command.compile()?;
+        Ok(command)
+    }
+
+    /// Returns the JSON Schema of command definitions, as printed by
+    /// `--print-schema`.
+    pub fn schema() -> RootSchema {
+        schemars::schema_for!(Command)
+    }
+
+    /// Parses every expression and template of the command once, so that it
+    /// can be evaluated repeatedly without reparsing.
+    ///
+    /// # Errors
+    /// Returns an error naming the metric and field holding an invalid
+    /// expression, or the name defined twice by the collect or the metrics.
+    pub fn compile(&mut self) -> Result<()> {
+        self.check_names()?;
+        self.check_entries()?;
+        for metric in self.compute.metrics.iter_mut() {
+            metric.compile("Metric")?;
+        }
+        for metric in self.compute.aggregations.iter_mut().flatten() {
+            metric.compile("Aggregation")?;
+        }
+        self.output.compile();
+        Ok(())
+    }
+
+    /// Infers the type and shape of every expression and template of the
+    /// compiled command, and returns all the problems found.
+    pub fn check(&self) -> Vec<String> {
+        let mut checker = Checker::new();
+        for s in self.collect.snmp.iter() {
+            let shape = match s.query {
+                QueryType::Get => Shape::Scalar,
+                QueryType::Walk => Shape::Table(s.name.clone()),
+            };
+            // Mapped values are translated into strings
+            let kind = |column: &str| match &s.map {
+                Some(map) if map.contains_key(column) => Kind::Str,
+                _ => Kind::Unknown,
+            };
+            checker.declare(s.name.clone(), Type::new(kind(&s.name), shape.clone()));
+            for label in s.labels.iter().flat_map(|labels| labels.values()) {
+                let t = Type::new(kind(label), shape.clone());
+                checker.declare(format!("{}.{}", s.name, label), t);
+            }
+        }
+        for metric in self.compute.metrics.iter() {
+            checker.metric("Metric", metric);
+        }
+        for metric in self.compute.aggregations.iter().flatten() {
+            checker.metric("Aggregation", metric);
+        }
+        checker.template("Output, field \"ok\"", self.output.ok_template());
+        checker.problems()
+    }
+
+    /// Checks that the names given to collected values, metrics and
+    /// aggregations are unique, as they all share the same namespace.
+    fn check_names(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for s in self.collect.snmp.iter() {
+            let labels = s.labels.iter().flatten();
+            let labels = labels.map(|(_, label)| format!("{}.{}", s.name, label));
+            for name in std::iter::once(s.name.clone()).chain(labels) {
+                if !names.insert(name.clone()) {
+                    return Err(error::Error::DuplicateName { name });
+                }
+            }
+        }
+        let metrics = self.compute.metrics.iter().map(|m| ("metrics", m));
+        let aggregations = self.compute.aggregations.iter().flatten();
+        let aggregations = aggregations.map(|m| ("aggregations", m));
+        for (namespace, metric) in metrics.chain(aggregations) {
+            let name = format!("{}.{}", namespace, metric.name);
+            if !names.insert(name.clone()) {
+                return Err(error::Error::DuplicateName { name });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the OIDs of the entries and of their labels are valid,
+    /// that each `filter-label` names a label of a walked entry, and that
+    /// only `Get` entries have a `missing` policy.
+    fn check_entries(&self) -> Result<()> {
+        if self.collect.concurrency == Some(0) {
+            return Err(error::Error::InvalidJSON {
+                message: "collect, field \"concurrency\": must be at least 1".to_string(),
+            });
+        }
+        for s in self.collect.snmp.iter() {
+            if parse_oid(&s.oid).is_none() {
+                return Err(error::Error::InvalidJSON {
+                    message: format!(
+                        "SNMP entry \"{}\", field \"oid\": '{}' is not a valid OID",
+                        s.name, s.oid
+                    ),
+                });
+            }
+            let mut labels: Vec<(&String, &String)> = s.labels.iter().flatten().collect();
+            labels.sort();
+            if let Some((suffix, label)) = labels
+                .iter()
+                .find(|(suffix, _)| parse_oid(suffix).is_none())
             {
-                debug!("Adding critical to aggregation metric {}", metric.name);
-                metric.critical = Some(value);
+                return Err(error::Error::InvalidJSON {
+                    message: format!(
+                        "SNMP entry \"{}\", label \"{}\": '{}' is not a valid OID suffix",
+                        s.name, label, suffix
+                    ),
+                });
             }
+            if s.missing.is_some() && matches!(s.query, QueryType::Walk) {
+                return Err(error::Error::InvalidJSON {
+                    message: format!(
+                        "SNMP entry \"{}\", field \"missing\": only a Get can be missing",
+                        s.name
+                    ),
+                });
+            }
+            let Some(filter_label) = &s.filter_label else {
+                continue;
+            };
+            let problem = match (&s.query, &s.labels) {
+                (QueryType::Get, _) => "only a Walk can be filtered",
+                (QueryType::Walk, None) => "the entry has no labels",
+                (QueryType::Walk, Some(labels)) if !labels.values().any(|l| l == filter_label) => {
+                    "unknown label"
+                }
+                _ => continue,
+            };
+            return Err(error::Error::InvalidJSON {
+                message: format!(
+                    "SNMP entry \"{}\", field \"filter-label\": {} '{}'",
+                    s.name, problem, filter_label
+                ),
+            });
         }
+        Ok(())
+    }
+
+    /// Sets the warning threshold of every metric and aggregation whose
+    /// `threshold_suffix` matches `name` (exactly, as a glob or as `re:<regex>`).
+    ///
+    /// # Errors
+    /// Returns an error if no metric declares a matching suffix.
+    pub fn add_warning(&mut self, name: &str, value: String) -> Result<()> {
+        self.add_threshold(name, value, Level::Warning)
+    }
+
+    /// Sets the critical threshold of every metric and aggregation whose
+    /// `threshold_suffix` matches `name` (exactly, as a glob or as `re:<regex>`).
+    ///
+    /// # Errors
+    /// Returns an error if no metric declares a matching suffix.
+    pub fn add_critical(&mut self, name: &str, value: String) -> Result<()> {
+        self.add_threshold(name, value, Level::Critical)
+    }
+
+    fn add_threshold(&mut self, name: &str, value: String, level: Level) -> Result<()> {
+        let pattern = SuffixPattern::parse(name)?;
+        let metrics = self.compute.metrics.iter_mut().map(|m| ("Metric", m));
+        let aggregations = self.compute.aggregations.iter_mut().flatten();
+        let aggregations = aggregations.map(|m| ("Aggregation", m));
+        let mut found = false;
+        for (kind, metric) in metrics.chain(aggregations) {
+            if !pattern.matches(&metric.threshold_suffix) {
+                continue;
+            }
+            debug!("Adding {:?} '{}' to metric {}", level, value, metric.name);
+            match level {
+                Level::Warning => metric.warning = Some(value.clone()),
+                Level::Critical => metric.critical = Some(value.clone()),
+            }
+            // Only the thresholds of this metric changed
+            metric.compile(kind)?;
+            found = true;
+        }
+        if found {
+            Ok(())
+        } else {
+            let option = match level {
+                Level::Warning => "warning",
+                Level::Critical => "critical",
+            };
+            Err(error::Error::UnknownThresholdSuffix {
+                arg: format!("--{}-{}", option, name),
+            })
+        }
+    }
+
+    /// Sets the maximum number of entries collected at once, overriding the
+    /// `concurrency` of the definition.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.collect.concurrency = Some(concurrency);
+    }
+
+    /// Returns the queries of the collect phase. With `keep`, the entries
+    /// with a `filter-label` only fetch the instances it keeps.
+    fn queries<'a>(&'a self, keep: Option<Keep<'a>>) -> Vec<Query<'a>> {
+        self.collect
+            .snmp
+            .iter()
+            .map(|s| match s.query {
+                QueryType::Get => Query::Get {
+                    oid: &s.oid,
+                    name: &s.name,
+                },
+                QueryType::Walk => Query::Walk {
+                    oid: &s.oid,
+                    name: &s.name,
+                    labels: s.labels.as_ref(),
+                    filter: s.filter_label.as_deref().zip(keep),
+                },
+            })
+            .collect()
     }
 
     /// Executes all configured SNMP queries (Get and Walk operations) and returns the results.
+    ///
+    /// The entries with a `filter-label` only fetch the instances kept by
+    /// `filter_in` and `filter_out`.
     fn execute_snmp_collect(
         &self,
         target: &str,
         version: &str,
         community: &str,
         check_format: bool,
-    ) -> Vec<SnmpResult> {
+        filter_in: &[String],
+        filter_out: &[String],
+    ) -> Result<Vec<SnmpResult>> {
         let mut collect: Vec<SnmpResult> = Vec::new();
 
         if check_format {
@@ -257,35 +786,114 @@ impl Command {
                 collect.push(SnmpResult::new(items));
             }
         } else {
-            let mut to_get = Vec::new();
-            let mut get_name = Vec::new();
-            for s in self.collect.snmp.iter() {
-                match s.query {
-                    QueryType::Walk => {
-                        if let Some(lab) = &s.labels {
-                            let r = snmp_bulk_walk_with_labels(
-                                target, version, community, &s.oid, &s.name, &lab,
-                            );
-                            collect.push(r);
-                        } else {
-                            let r = snmp_bulk_walk(target, version, community, &s.oid, &s.name);
-                            collect.push(r);
-                        }
-                    }
-                    QueryType::Get => {
-                        to_get.push(s.oid.as_str());
-                        get_name.push(s.name.as_str());
-                    }
+            let filter = InstanceFilter::new(filter_in, filter_out)?;
+            let keep = |name: &str| filter.keeps(name);
+            let queries = self.queries((!filter.is_empty()).then_some(&keep));
+            let concurrency = self.collect.concurrency.unwrap_or(1);
+            collect.push(snmp_collect(
+                target,
+                version,
+                community,
+                &queries,
+                concurrency,
+            )?);
+        }
+
+        Ok(collect)
+    }
+
+    /// Answers the SNMP queries from a saved `.snmpwalk` file instead of an
+    /// agent, for [`Command::evaluate`] or [`Command::instances`].
+    ///
+    /// # Errors
+    /// Returns an error if a regex of `filter_in` or `filter_out` is invalid.
+    pub fn replay_collect(
+        &self,
+        walk: &SnmpWalk,
+        filter_in: &[String],
+        filter_out: &[String],
+    ) -> Result<Vec<SnmpResult>> {
+        let filter = InstanceFilter::new(filter_in, filter_out)?;
+        let keep = |name: &str| filter.keeps(name);
+        let queries = self.queries((!filter.is_empty()).then_some(&keep));
+        Ok(vec![walk.collect(&queries)?])
+    }
+
+    /// Applies the `missing` policy of the `Get` entries the agent had no
+    /// value for, and returns the names of the entries skipped.
+    ///
+    /// # Errors
+    /// Returns an error for the first missing entry whose policy is `fail`.
+    fn apply_missing(&self, symbols: &mut Symbols) -> Result<HashSet<String>> {
+        let mut skipped = HashSet::new();
+        for s in self.collect.snmp.iter() {
+            if !matches!(s.query, QueryType::Get) || symbols.get(&s.name).is_some() {
+                continue;
+            }
+            match s.missing.as_ref().unwrap_or(&Missing::Fail) {
+                Missing::Fail => {
+                    return Err(error::Error::MissingValue {
+                        name: s.name.clone(),
+                        oid: s.oid.clone(),
+                    });
                 }
+                Missing::Skip => {
+                    debug!("SNMP entry '{}' is missing and skipped", s.name);
+                    skipped.insert(s.name.clone());
+                }
+                Missing::Default(value) => symbols.insert(s.name.clone(), value.to_result())?,
             }
+        }
+        Ok(skipped)
+    }
 
-            if !to_get.is_empty() {
-                let r = snmp_bulk_get(target, version, community, 1, 1, &to_get, &get_name);
-                collect.push(r);
+    /// Translates collected values through the value maps declared by each
+    /// SNMP entry. Mapped values become strings; values without a
+    /// translation are kept as they are.
+    fn apply_maps(&self, symbols: &mut Symbols) -> Result<()> {
+        for s in self.collect.snmp.iter() {
+            let Some(maps) = &s.map else { continue };
+            for (column, value_map) in maps {
+                let map = match value_map {
+                    ValueMap::Inline(map) => map,
+                    ValueMap::Named(name) => {
+                        self.maps
+                            .get(name)
+                            .ok_or_else(|| error::Error::InvalidJSON {
+                                message: format!(
+                                    "SNMP entry \"{}\", field \"map\": unknown map '{}'",
+                                    s.name, name
+                                ),
+                            })?
+                    }
+                };
+                let map: HashMap<&str, &String> =
+                    map.iter().map(|(k, v)| (map_key(k), v)).collect();
+                let key = if *column == s.name {
+                    s.name.clone()
+                } else {
+                    format!("{}.{}", s.name, column)
+                };
+                let Some(item) = symbols.get_mut(&key) else {
+                    continue;
+                };
+                let values = match item {
+                    ExprResult::Vector(v) => v.iter().map(crate::output::float_string).collect(),
+                    ExprResult::StrVector(v) => std::mem::take(v),
+                    _ => continue,
+                };
+                let mapped = values
+                    .into_iter()
+                    .map(|v| match map.get(map_key(&v)) {
+                        Some(label) => label.to_string(),
+                        None => v,
+                    })
+                    .collect();
+                trace!("Mapped '{}' to {:?}", key, mapped);
+                *item = ExprResult::StrVector(mapped);
             }
         }
-
-        collect
+        Ok(())
     }
 
     /// Executes the complete plugin pipeline: SNMP collection, metric computation, filtering, and output formatting.
@@ -309,11 +917,34 @@ impl Command {
         filter_out: &Vec<String>,
         check_format: bool,
     ) -> Result<CmdResult> {
-        let mut collect = self.execute_snmp_collect(target, version, community, check_format);
+        let collect = self.execute_snmp_collect(
+            target,
+            version,
+            community,
+            check_format,
+            filter_in,
+            filter_out,
+        )?;
+        self.evaluate(collect, filter_in, filter_out, check_format)
+    }
+
+    /// Computes metrics and aggregations from already collected values,
+    /// evaluates their status and formats the output.
+    ///
+    /// The command must have been compiled with [`Command::compile`].
+    pub fn evaluate(
+        &self,
+        collect: Vec<SnmpResult>,
+        filter_in: &[String],
+        filter_out: &[String],
+        check_format: bool,
+    ) -> Result<CmdResult> {
+        let mut symbols = Symbols::from_results(collect)?;
+        let mut skipped = self.apply_missing(&mut symbols)?;
+        self.apply_maps(&mut symbols)?;
 
         let mut idx: u32 = 0;
         let mut metrics = vec![];
-        let mut my_res = SnmpResult::new(HashMap::new());
         let mut status = Status::Ok;
 
         // Prepare filters
@@ -330,28 +961,50 @@ impl Command {
         }
 
         for metric in self.compute.metrics.iter() {
-            let value = &metric.value;
-            let parser = Parser::new(&collect, check_format);
-            let value = parser.eval(value).map_err(|e| error::Error::InvalidJSON {
-                message: format!("Metric \"{}\", field \"value\": {}", metric.name, e),
-            })?;
-            let min = if let Some(min_expr) = metric.min_expr.as_ref() {
+            let compiled = metric.compiled()?;
+            if compiled.macros().iter().any(|name| skipped.contains(*name)) {
+                debug!("Metric '{}' skipped: it uses a missing value", metric.name);
+                skipped.insert(format!("metrics.{}", metric.name));
+                continue;
+            }
+            let parser = Parser::new(&symbols, check_format);
+            let CompiledValue::Number {
+                value,
+                warning: warnings,
+                critical: criticals,
+            } = &compiled.value
+            else {
+                let texts = compute_status_metric(
+                    &parser,
+                    metric,
+                    &mut idx,
+                    &re_in,
+                    &re_out,
+                    &mut status,
+                    &mut metrics,
+                )?;
+                let key = format!("metrics.{}", metric.name);
+                debug!("New ID '{}' with content: {:?}", key, texts);
+                symbols.insert(key, texts)?;
+                continue;
+            };
+            let filter = eval_filter(&parser, metric, "Metric")?;
+            let value = parser
+                .eval_expr(value)
+                .map_err(|e| field_error("Metric", metric, "value", e))?;
+            let min = if let Some(min_expr) = compiled.min.as_ref() {
                 parser
-                    .eval(&min_expr)
-                    .map_err(|e| error::Error::InvalidJSON {
-                        message: format!("Metric \"{}\", field \"min_expr\": {}", metric.name, e),
-                    })?
+                    .eval_expr(min_expr)
+                    .map_err(|e| field_error("Metric", metric, "min_expr", e))?
             } else if let Some(min_value) = metric.min {
                 ExprResult::Number(min_value)
             } else {
                 ExprResult::Empty
             };
-            let max = if let Some(max_expr) = metric.max_expr.as_ref() {
+            let max = if let Some(max_expr) = compiled.max.as_ref() {
                 parser
-                    .eval(&max_expr)
-                    .map_err(|e| error::Error::InvalidJSON {
-                        message: format!("Metric \"{}\", field \"max_expr\": {}", metric.name, e),
-                    })?
+                    .eval_expr(max_expr)
+                    .map_err(|e| field_error("Metric", metric, "max_expr", e))?
             } else if let Some(max_value) = metric.max {
                 ExprResult::Number(max_value)
             } else {
@@ -360,38 +1013,27 @@ impl Command {
 
             let compute_threshold = |idx: usize, expr: &ExprResult| match &expr {
                 ExprResult::Number(value) => Some(*value),
-                ExprResult::Vector(v) => Some(v[idx]),
+                ExprResult::Vector(v) => v.get(idx).copied(),
                 _ => None,
             };
-            match &value {
+            let kept = match &value {
                 ExprResult::Vector(v) => {
-                    let prefix_str = match &metric.prefix {
-                        Some(prefix) => {
-                            parser
-                                .eval_str(prefix)
-                                .map_err(|e| error::Error::InvalidJSON {
-                                    message: format!(
-                                        "Metric \"{}\", field \"prefix\": {}",
-                                        metric.name, e
-                                    ),
-                                })?
-                        }
+                    let mut kept = Vec::with_capacity(v.len());
+                    let prefix_str = match &compiled.prefix {
+                        Some(prefix) => parser
+                            .eval_template(prefix)
+                            .map_err(|e| field_error("Metric", metric, "prefix", e))?,
                         None => ExprResult::Empty,
                     };
                     for (i, item) in v.iter().enumerate() {
-                        // first, compose the instance name
-                        let instance_name = match &prefix_str {
-                            ExprResult::StrVector(v) => v[i].to_string(),
-                            ExprResult::Str(s) => s.to_string(),
-                            ExprResult::Empty => {
-                                let res = idx.to_string();
-                                idx += 1;
-                                res
-                            }
-                            _ => {
-                                panic!("A label must be a string");
-                            }
-                        };
+                        // first, drop instances rejected by the metric filter
+                        if !filter.is_true_at(i) {
+                            continue;
+                        }
+                        kept.push(*item);
+                        // then compose the instance name
+                        let instance_name =
+                            instance_name(&prefix_str, i, v.len(), &mut idx, "Metric", metric)?;
                         // then apply filters exclusion and inclusion filters
                         if !re_out.is_empty() && re_out.iter().any(|re| re.is_match(&instance_name))
                         {
@@ -404,42 +1046,46 @@ impl Command {
                         }
                         // and now concatenate to form the full perfdata
                         let name = format!("'{}#{}'", instance_name, metric.name);
-                        let current_status =
-                            compute_status(item, &metric.warning, &metric.critical)?;
+                        let w = warnings.resolve(&instance_name);
+                        let c = criticals.resolve(&instance_name);
+                        let current_status = compute_status(item, w, c);
                         status = worst(status, current_status);
-                        let w = match metric.warning {
-                            Some(ref w) => Some(w.as_str()),
-                            None => None,
-                        };
-                        let c = match metric.critical {
-                            Some(ref c) => Some(c.as_str()),
-                            None => None,
-                        };
                         let m = Perfdata {
                             name,
                             value: *item,
+                            text: None,
                             uom: &metric.uom,
                             min: compute_threshold(i, &min),
                             max: compute_threshold(i, &max),
-                            warning: w,
-                            critical: c,
+                            warning: w.map(|t| t.0),
+                            critical: c.map(|t| t.0),
                             status: Some(current_status),
                         };
                         trace!("New metric '{}' with value {:?}", m.name, m.value);
                         metrics.push(m);
                     }
+                    ExprResult::Vector(kept)
                 }
+                ExprResult::Number(_) if !filter.is_true_at(0) => ExprResult::Vector(vec![]),
                 ExprResult::Number(s) => {
-                    let name = match &metric.prefix {
-                        Some(prefix) => {
-                            format!("{}#{}", prefix, metric.name)
-                        }
+                    // A table reduced to one row by the filters is a scalar
+                    // too, named by its evaluated prefix
+                    let instance_name = match &compiled.prefix {
+                        Some(prefix) => match parser
+                            .eval_template(prefix)
+                            .map_err(|e| field_error("Metric", metric, "prefix", e))?
+                        {
+                            ExprResult::Str(name) => name,
+                            ExprResult::StrVector(mut v) if v.len() == 1 => v.remove(0),
+                            _ => metric.prefix.clone().unwrap_or_default(),
+                        },
                         None => {
-                            let res = format!("{}#{}", idx, metric.name);
+                            let res = idx.to_string();
                             idx += 1;
                             res
                         }
                     };
+                    let name = format!("{}#{}", instance_name, metric.name);
                     if !re_in.is_empty() {
                         // If one filter is matched, we keep the metric
                         if !re_in.iter().any(|re| re.is_match(&name)) {
@@ -451,164 +1097,713 @@ impl Command {
                             continue;
                         }
                     }
-                    let current_status = compute_status(s, &metric.warning, &metric.critical)?;
+                    let w = warnings.resolve(&instance_name);
+                    let c = criticals.resolve(&instance_name);
+                    let current_status = compute_status(s, w, c);
                     status = worst(status, current_status);
-                    let w = match metric.warning {
-                        Some(ref w) => Some(w.as_str()),
-                        None => None,
-                    };
-                    let c = match metric.critical {
-                        Some(ref c) => Some(c.as_str()),
-                        None => None,
-                    };
                     let m = Perfdata {
                         name,
                         value: *s,
+                        text: None,
                         uom: &metric.uom,
                         min: compute_threshold(0, &min),
                         max: compute_threshold(0, &max),
-                        warning: w,
-                        critical: c,
+                        warning: w.map(|t| t.0),
+                        critical: c.map(|t| t.0),
                         status: Some(current_status),
                     };
                     trace!("New metric '{}' with value {:?}", m.name, m.value);
                     metrics.push(m);
+                    ExprResult::Number(*s)
                 }
-                _ => panic!("Aggregation must be applied to a vector"),
-            }
+                _ => {
+                    let e = "it must be a number or a vector of numbers".to_string();
+                    return Err(field_error("Metric", metric, "value", e));
+                }
+            };
             let key = format!("metrics.{}", metric.name);
-            debug!("New ID '{}' with content: {:?}", key, value);
-            my_res.items.insert(key, value);
+            debug!("New ID '{}' with content: {:?}", key, kept);
+            symbols.insert(key, kept)?;
         }
-        collect.push(my_res);
         if let Some(aggregations) = self.compute.aggregations.as_ref() {
-            let mut my_res = SnmpResult::new(HashMap::new());
             for metric in aggregations {
-                let value = &metric.value;
-                let parser = Parser::new(&collect, check_format);
-                let max = if let Some(max_expr) = metric.max_expr.as_ref() {
+                let compiled = metric.compiled()?;
+                if compiled.macros().iter().any(|name| skipped.contains(*name)) {
+                    debug!(
+                        "Aggregation '{}' skipped: it uses a missing value",
+                        metric.name
+                    );
+                    continue;
+                }
+                let CompiledValue::Number {
+                    value,
+                    warning: warnings,
+                    critical: criticals,
+                } = &compiled.value
+                else {
+                    return Err(error::Error::InvalidJSON {
+                        message: format!("Aggregation \"{}\" cannot be a status", metric.name),
+                    });
+                };
+                let parser = Parser::new(&symbols, check_format);
+                let max = if let Some(max_expr) = compiled.max.as_ref() {
                     let res = parser
-                        .eval(&max_expr)
-                        .map_err(|e| error::Error::InvalidJSON {
-                            message: format!(
-                                "Aggregation \"{}\", field \"max_expr\": {}",
-                                metric.name, e
-                            ),
-                        })?;
-                    Some(match res {
-                        ExprResult::Number(v) => v,
-                        ExprResult::Vector(v) => {
-                            assert!(v.len() == 1);
-                            v[0]
-                        }
-                        _ => panic!("Aggregation must be applied to a vector"),
-                    })
+                        .eval_expr(max_expr)
+                        .map_err(|e| field_error("Aggregation", metric, "max_expr", e))?;
+                    Some(single_number(res, metric, "max_expr")?)
                 } else if let Some(max_value) = metric.max {
                     Some(max_value)
                 } else {
                     None
                 };
-                let min = if let Some(min_expr) = metric.min_expr.as_ref() {
+                let min = if let Some(min_expr) = compiled.min.as_ref() {
                     let res = parser
-                        .eval(&min_expr)
-                        .map_err(|e| error::Error::InvalidJSON {
-                            message: format!(
-                                "Aggregation \"{}\", field \"min_expr\": {}",
-                                metric.name, e
-                            ),
-                        })?;
-                    Some(match res {
-                        ExprResult::Number(v) => v,
-                        ExprResult::Vector(v) => {
-                            assert!(v.len() == 1);
-                            v[0]
-                        }
-                        _ => panic!("Aggregation must be applied to a vector"),
-                    })
+                        .eval_expr(min_expr)
+                        .map_err(|e| field_error("Aggregation", metric, "min_expr", e))?;
+                    Some(single_number(res, metric, "min_expr")?)
                 } else if let Some(min_value) = metric.min {
                     Some(min_value)
                 } else {
                     None
                 };
-                let value = parser.eval(value).map_err(|e| error::Error::InvalidJSON {
-                    message: format!("Aggregation \"{}\", field \"value\": {}", metric.name, e),
-                })?;
-                match &value {
+                let filter = eval_filter(&parser, metric, "Aggregation")?;
+                let value = parser
+                    .eval_expr(value)
+                    .map_err(|e| field_error("Aggregation", metric, "value", e))?;
+                let kept = match value {
                     ExprResult::Vector(v) => {
-                        for item in v {
-                            let name = match &metric.prefix {
-                                Some(prefix) => {
-                                    format!("{:?}#{}", prefix, metric.name)
-                                }
-                                None => {
-                                    let res = format!("{}#{}", idx, metric.name);
-                                    idx += 1;
-                                    res
-                                }
-                            };
-                            let current_status =
-                                compute_status(item, &metric.warning, &metric.critical)?;
+                        let mut kept = Vec::with_capacity(v.len());
+                        let prefix_str = match &compiled.prefix {
+                            Some(prefix) => parser
+                                .eval_template(prefix)
+                                .map_err(|e| field_error("Aggregation", metric, "prefix", e))?,
+                            None => ExprResult::Empty,
+                        };
+                        for (i, item) in v.iter().enumerate() {
+                            if !filter.is_true_at(i) {
+                                continue;
+                            }
+                            kept.push(*item);
+                            let instance_name = instance_name(
+                                &prefix_str,
+                                i,
+                                v.len(),
+                                &mut idx,
+                                "Aggregation",
+                                metric,
+                            )?;
+                            let name = format!("{}#{}", instance_name, metric.name);
+                            let w = warnings.resolve(&instance_name);
+                            let c = criticals.resolve(&instance_name);
+                            let current_status = compute_status(item, w, c);
                             status = worst(status, current_status);
-                            let w = match metric.warning {
-                                Some(ref w) => Some(w.as_str()),
-                                None => None,
-                            };
-                            let c = match metric.critical {
-                                Some(ref c) => Some(c.as_str()),
-                                None => None,
-                            };
                             let m = Perfdata {
                                 name,
                                 value: *item,
+                                text: None,
                                 uom: &metric.uom,
                                 min,
                                 max,
-                                warning: w,
-                                critical: c,
+                                warning: w.map(|t| t.0),
+                                critical: c.map(|t| t.0),
                                 status: Some(current_status),
                             };
                             trace!("New metric '{}' with value {:?}", m.name, m.value);
                             metrics.push(m);
                         }
+                        ExprResult::Vector(kept)
                     }
+                    ExprResult::Number(_) if !filter.is_true_at(0) => ExprResult::Vector(vec![]),
                     ExprResult::Number(s) => {
                         let name = &metric.name;
-                        let current_status = compute_status(s, &metric.warning, &metric.critical)?;
+                        let w = warnings.resolve(name);
+                        let c = criticals.resolve(name);
+                        let current_status = compute_status(&s, w, c);
                         status = worst(status, current_status);
-                        let w = match metric.warning {
-                            Some(ref w) => Some(w.as_str()),
-                            None => None,
-                        };
-                        let c = match metric.critical {
-                            Some(ref c) => Some(c.as_str()),
-                            None => None,
-                        };
                         let m = Perfdata {
                             name: name.to_string(),
-                            value: *s,
+                            value: s,
+                            text: None,
                             uom: &metric.uom,
                             min,
                             max,
-                            warning: w,
-                            critical: c,
+                            warning: w.map(|t| t.0),
+                            critical: c.map(|t| t.0),
                             status: Some(current_status),
                         };
                         trace!("New metric '{}' with value {:?}", m.name, m.value);
                         metrics.push(m);
+                        ExprResult::Number(s)
                     }
-                    _ => panic!("Aggregation must be applied to a vector"),
-                }
+                    _ => {
+                        let e = "it must be a number or a vector of numbers".to_string();
+                        return Err(field_error("Aggregation", metric, "value", e));
+                    }
+                };
                 let key = format!("aggregations.{}", metric.name);
-                debug!("New ID '{}' with content: {:?}", key, value);
-                my_res.items.insert(key, value);
+                debug!("New ID '{}' with content: {:?}", key, kept);
+                symbols.insert(key, kept)?;
             }
-            collect.push(my_res);
         }
 
-        debug!("collect: {:#?}", collect);
+        debug!("symbols: {:#?}", symbols);
         trace!("metrics: {:#?}", metrics);
-        let output_formatter = OutputFormatter::new(status, &collect, &metrics, &self.output);
+        let output_formatter = OutputFormatter::new(status, &symbols, &metrics, &self.output);
         let output = output_formatter.to_string();
         Ok(CmdResult { status, output })
     }
 }
+
+#[cfg(test)]
+mod test {
+    fn command() -> super::Command {
+        let mut cmd: super::Command = serde_json::from_str(
+            r#"{
+              "collect": { "snmp": [] },
+              "compute": {
+                "metrics": [
+                  { "name": "cpu.core", "value": "1", "threshold-suffix": "cpu" },
+                  { "name": "cpu.steal", "value": "1", "threshold-suffix": "cpu-steal" },
+                  { "name": "mem", "value": "1", "threshold-suffix": "memory" }
+                ],
+                "aggregations": [
+                  { "name": "cpu.avg", "value": "1", "threshold-suffix": "cpu" }
+                ]
+              }
+            }"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        cmd
+    }
+
+    #[test]
+    fn test_threshold_on_every_matching_metric() {
+        use crate::compute::CompiledValue;
+
+        let mut cmd = command();
+        cmd.add_warning("cpu", "80".to_string()).unwrap();
+        let metrics = &cmd.compute.metrics;
+        let aggregations = cmd.compute.aggregations.as_ref().unwrap();
+        assert_eq!(metrics[0].warning.as_deref(), Some("80"));
+        assert_eq!(metrics[1].warning, None);
+        assert_eq!(aggregations[0].warning.as_deref(), Some("80"));
+        // The matched metrics are compiled again with their new threshold
+        let warning = |metric: &crate::compute::Metric| match &metric.compiled().unwrap().value {
+            CompiledValue::Number { warning, .. } => warning.resolve("").map(|w| w.0.to_string()),
+            _ => None,
+        };
+        assert_eq!(warning(&aggregations[0]).as_deref(), Some("80"));
+        assert_eq!(warning(&metrics[1]), None);
+    }
+
+    #[test]
+    fn test_threshold_glob_and_regex_suffix() {
+        let mut cmd = command();
+        cmd.add_critical("cpu*", "90".to_string()).unwrap();
+        cmd.add_warning("re:^mem", "70".to_string()).unwrap();
+        let metrics = &cmd.compute.metrics;
+        assert_eq!(metrics[0].critical.as_deref(), Some("90"));
+        assert_eq!(metrics[1].critical.as_deref(), Some("90"));
+        assert_eq!(metrics[2].critical, None);
+        assert_eq!(metrics[2].warning.as_deref(), Some("70"));
+    }
+
+    #[test]
+    fn test_value_maps() {
+        use crate::compute::ast::ExprResult;
+        use crate::compute::symbols::Symbols;
+        use std::collections::HashMap;
+
+        let mut cmd: super::Command = serde_json::from_str(
+            r#"{
+              "maps": { "ifOperStatus": { "1": "up", "2": "down" } },
+              "collect": { "snmp": [
+                { "name": "if", "oid": "1.3.6.1.2.1.2.2.1", "query": "Walk",
+                  "labels": { ".8": "status", ".3": "type" },
+                  "map": {
+                    "status": "ifOperStatus",
+                    "type": { ".1.3.6.1.2.1.25.2.1.4": "fixedDisk" }
+                  }
+                }
+              ] },
+              "compute": { "metrics": [] }
+            }"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        let items = HashMap::from([
+            (
+                "if.status".to_string(),
+                ExprResult::Vector(vec![1.0, 2.0, 7.0]),
+            ),
+            (
+                "if.type".to_string(),
+                ExprResult::StrVector(vec!["1.3.6.1.2.1.25.2.1.4.".to_string()]),
+            ),
+        ]);
+        let mut symbols = Symbols::from(items);
+        cmd.apply_maps(&mut symbols).unwrap();
+        match symbols.get("if.status").unwrap() {
+            ExprResult::StrVector(v) => assert_eq!(v, &vec!["up", "down", "7"]),
+            _ => panic!("Expected a string vector"),
+        }
+        match symbols.get("if.type").unwrap() {
+            ExprResult::StrVector(v) => assert_eq!(v, &vec!["fixedDisk"]),
+            _ => panic!("Expected a string vector"),
+        }
+    }
+
+    #[test]
+    fn test_metric_filter() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::Status;
+        use crate::snmp::SnmpResult;
+        use std::collections::HashMap;
+
+        let mut cmd: super::Command = serde_json::from_str(
+            r#"{
+              "collect": { "snmp": [] },
+              "compute": {
+                "metrics": [
+                  { "name": "used", "prefix": "{disk.label}", "value": "{disk.used}",
+                    "filter": "{disk.type} == 'fixed'", "critical": "50" }
+                ],
+                "aggregations": [
+                  { "name": "avg", "value": "Average({metrics.used})" }
+                ]
+              }
+            }"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        let items = HashMap::from([
+            (
+                "disk.label".to_string(),
+                ExprResult::StrVector(vec!["/".to_string(), "ram".to_string(), "/var".to_string()]),
+            ),
+            (
+                "disk.type".to_string(),
+                ExprResult::StrVector(vec![
+                    "fixed".to_string(),
+                    "ram".to_string(),
+                    "fixed".to_string(),
+                ]),
+            ),
+            (
+                "disk.used".to_string(),
+                ExprResult::Vector(vec![10.0, 99.0, 20.0]),
+            ),
+        ]);
+        let res = cmd
+            .evaluate(vec![SnmpResult::new(items)], &[], &[], false)
+            .unwrap();
+        assert_eq!(res.status, Status::Ok);
+        assert_eq!(
+            res.output,
+            "OK: Everything is ok  | '/#used'=10;;50;; '/var#used'=20;;50;; avg=15;;;;"
+        );
+    }
+
+    #[test]
+    fn test_prefix_errors() {
+        use crate::compute::ast::ExprResult;
+        use crate::snmp::SnmpResult;
+        use std::collections::HashMap;
+
+        let evaluate = |prefix: &str| {
+            let mut cmd: super::Command = serde_json::from_str(&format!(
+                r#"{{
+                  "collect": {{ "snmp": [] }},
+                  "compute": {{
+                    "metrics": [ {{ "name": "used", "prefix": "{}", "value": "{{disk.used}}" }} ]
+                  }}
+                }}"#,
+                prefix
+            ))
+            .unwrap();
+            cmd.compile().unwrap();
+            let items = HashMap::from([
+                (
+                    "disk.label".to_string(),
+                    ExprResult::StrVector(vec!["/".to_string(), "/var".to_string()]),
+                ),
+                (
+                    "disk.used".to_string(),
+                    ExprResult::Vector(vec![10.0, 20.0, 30.0]),
+                ),
+            ]);
+            cmd.evaluate(vec![SnmpResult::new(items)], &[], &[], false)
+                .map(|res| res.output)
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            evaluate("{disk.label}").unwrap_err(),
+            "Metric \"used\", field \"prefix\": 2 names for 3 values"
+        );
+        assert!(evaluate("{disk.used}").unwrap().contains("'30#used'=30"));
+    }
+
+    #[test]
+    fn test_aggregation_instance_thresholds() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::Status;
+        use crate::snmp::SnmpResult;
+        use std::collections::HashMap;
+
+        let mut cmd: super::Command = serde_json::from_str(
+            r#"{
+              "collect": { "snmp": [] },
+              "compute": {
+                "metrics": [],
+                "aggregations": [
+                  { "name": "double", "prefix": "{disk.label}", "value": "{disk.used} * 2",
+                    "critical": "/var=30,re:^/h=5" }
+                ]
+              }
+            }"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        let items = HashMap::from([
+            (
+                "disk.label".to_string(),
+                ExprResult::StrVector(vec![
+                    "/".to_string(),
+                    "/var".to_string(),
+                    "/home".to_string(),
+                ]),
+            ),
+            (
+                "disk.used".to_string(),
+                ExprResult::Vector(vec![10.0, 20.0, 5.0]),
+            ),
+        ]);
+        let res = cmd
+            .evaluate(vec![SnmpResult::new(items)], &[], &[], false)
+            .unwrap();
+        assert_eq!(res.status, Status::Critical);
+        assert!(
+            res.output
+                .ends_with("| /#double=20;;;; /var#double=40;;30;; /home#double=10;;5;;"),
+            "{}",
+            res.output
+        );
+    }
+
+    #[test]
+    fn test_aggregation_errors() {
+        use crate::compute::ast::ExprResult;
+        use crate::snmp::SnmpResult;
+        use std::collections::HashMap;
+
+        let evaluate = |aggregation: &str| {
+            let mut cmd: super::Command = serde_json::from_str(&format!(
+                r#"{{
+                  "collect": {{ "snmp": [] }},
+                  "compute": {{ "metrics": [], "aggregations": [ {} ] }}
+                }}"#,
+                aggregation
+            ))
+            .unwrap();
+            cmd.compile().unwrap();
+            let items = HashMap::from([
+                (
+                    "disk.label".to_string(),
+                    ExprResult::StrVector(vec!["/".to_string(), "/var".to_string()]),
+                ),
+                (
+                    "disk.used".to_string(),
+                    ExprResult::Vector(vec![10.0, 20.0]),
+                ),
+            ]);
+            cmd.evaluate(vec![SnmpResult::new(items)], &[], &[], false)
+                .map(|res| res.output)
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            evaluate(
+                r#"{ "name": "total", "value": "Sum({disk.used})", "max_expr": "{disk.used}" }"#
+            )
+            .unwrap_err(),
+            "Aggregation \"total\", field \"max_expr\": it must be a single number"
+        );
+        assert_eq!(
+            evaluate(
+                r#"{ "name": "total", "value": "Sum({disk.used})", "min_expr": "{disk.label}" }"#
+            )
+            .unwrap_err(),
+            "Aggregation \"total\", field \"min_expr\": it must be a single number"
+        );
+        assert_eq!(
+            evaluate(r#"{ "name": "label", "value": "{disk.label}" }"#).unwrap_err(),
+            "Aggregation \"label\", field \"value\": it must be a number or a vector of numbers"
+        );
+    }
+
+    #[test]
+    fn test_threshold_unknown_suffix() {
+        let mut cmd = command();
+        match cmd.add_warning("foo", "80".to_string()) {
+            Ok(_) => panic!("The suffix 'foo' should be rejected"),
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "Unknown threshold option: --warning-foo\nNo metric declares this threshold-suffix"
+            ),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let compile = |json: &str| {
+            let mut cmd: super::Command = serde_json::from_str(json).unwrap();
+            cmd.compile().map_err(|e| e.to_string())
+        };
+        let err = compile(
+            r#"{
+              "collect": { "snmp": [
+                { "name": "cpu", "oid": "1.3.6.1.2.1.25.3.3.1.2", "query": "Walk" },
+                { "name": "cpu", "oid": "1.3.6.1.4.1.2021.11.9.0", "query": "Get" }
+              ] },
+              "compute": { "metrics": [] }
+            }"#,
+        );
+        assert!(err.unwrap_err().starts_with("Duplicate name 'cpu'"));
+        let err = compile(
+            r#"{
+              "collect": { "snmp": [] },
+              "compute": {
+                "metrics": [ { "name": "used", "value": "1" }, { "name": "used", "value": "2" } ]
+              }
+            }"#,
+        );
+        assert!(
+            err.unwrap_err()
+                .starts_with("Duplicate name 'metrics.used'")
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let from_json = |json: &str| super::Command::from_json(json).map_err(|e| e.to_string());
+        let err = from_json(
+            r#"{
+              "collect": { "snmp": [
+                { "name": "cpu", "oid": "1.3.6.1.2.1.25.3.3.1.2", "query": "Walk" }
+              ] },
+              "compute": {
+                "metrics": [ { "name": "cpu", "value": "{cpu}", "treshold-suffix": "cpu" } ]
+              }
+            }"#,
+        );
+        assert!(
+            err.unwrap_err()
+                .starts_with("compute.metrics[0].treshold-suffix: unknown field `treshold-suffix`")
+        );
+        let err = from_json(
+            r#"{
+              "collect": { "snmp": [
+                { "name": "cpu", "oid": "1.3.6.1.2.1.25.3.3.1.2", "query": "Walk", "label": {} }
+              ] },
+              "compute": { "metrics": [] }
+            }"#,
+        );
+        assert!(
+            err.unwrap_err()
+                .starts_with("collect.snmp[0].label: unknown field `label`")
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = serde_json::to_value(super::Command::schema()).unwrap();
+        let metric = &schema["definitions"]["Metric"];
+        assert_eq!(metric["additionalProperties"], false);
+        assert!(metric["properties"]["threshold-suffix"].is_object());
+        assert!(metric["properties"].get("compiled").is_none());
+    }
+
+    #[test]
+    fn test_check_reports_every_problem() {
+        let mut cmd: super::Command = serde_json::from_str(
+            r#"{
+              "collect": { "snmp": [
+                { "name": "if", "oid": "1.3.6.1.2.1.2.2.1", "query": "Walk",
+                  "labels": { ".2": "descr", ".8": "status" }, "map": { "status": { "1": "up" } } },
+                { "name": "disk", "oid": "1.3.6.1.2.1.25.2.3.1", "query": "Walk",
+                  "labels": { ".6": "used" } }
+              ] },
+              "compute": {
+                "metrics": [
+                  { "name": "in", "prefix": "{if.descr}", "value": "{if.status} * 8" },
+                  { "name": "used", "prefix": "{if.descr}", "value": "{disk.used}" }
+                ],
+                "aggregations": [ { "name": "total", "value": "{metrics.used} * 2" } ]
+              }
+            }"#,
+        )
+        .unwrap();
+        cmd.compile().unwrap();
+        assert_eq!(
+            cmd.check(),
+            vec![
+                "Metric \"in\", field \"value\": operator '*' expects a number, got a string",
+                "Metric \"used\", field \"prefix\": has one value per instance of 'if' but the metric has one per instance of 'disk'",
+                "Aggregation \"total\", field \"value\": has one value per instance of 'disk', use Average(), Min() or Max()",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_oids() {
+        use crate::snmp::parse_oid;
+
+        assert_eq!(parse_oid("1.3.6.1.2.1.1.3.0").unwrap().len(), 9);
+        assert_eq!(parse_oid(".1.3.6.1.2.1.1.3.0").unwrap().len(), 9);
+        assert_eq!(parse_oid(".2"), Some(vec![2]));
+        assert!(parse_oid("").is_none());
+        assert!(parse_oid("1.3.6..1").is_none());
+        assert!(parse_oid("1.3.6.1.2.1.1.3.0.").is_none());
+        assert!(parse_oid("iso.3.6.1").is_none());
+
+        let compile = |oid: &str, suffix: &str| {
+            let json = format!(
+                r#"{{
+                  "collect": {{ "snmp": [
+                    {{ "name": "disk", "oid": "{}", "query": "Walk", "labels": {{ "{}": "used" }} }}
+                  ] }},
+                  "compute": {{ "metrics": [ {{ "name": "used", "value": "{{disk.used}}" }} ] }}
+                }}"#,
+                oid, suffix
+            );
+            let mut cmd: super::Command = serde_json::from_str(&json).unwrap();
+            cmd.compile().map_err(|e| e.to_string())
+        };
+        assert!(compile("1.3.6.1.2.1.25.2.3.1", ".6").is_ok());
+        let err = compile("1.3.6.x", ".6").unwrap_err();
+        assert!(
+            err.contains("SNMP entry \"disk\", field \"oid\": '1.3.6.x' is not a valid OID"),
+            "{}",
+            err
+        );
+        let err = compile("1.3.6.1.2.1.25.2.3.1", ".six").unwrap_err();
+        assert!(
+            err.contains("SNMP entry \"disk\", label \"used\": '.six' is not a valid OID suffix"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_filter_label() {
+        use crate::snmp::snmpwalk::SnmpWalk;
+
+        let compile = |filter_label: &str| {
+            let json = format!(
+                r#"{{
+                  "collect": {{ "snmp": [
+                    {{ "name": "if", "oid": "1.3.6.1.2.1.2.2.1", "query": "Walk",
+                      "labels": {{ ".2": "descr", ".8": "status" }}, "filter-label": "{}" }}
+                  ] }},
+                  "compute": {{
+                    "metrics": [ {{ "name": "status", "prefix": "{{if.descr}}", "value": "{{if.status}}" }} ]
+                  }}
+                }}"#,
+                filter_label
+            );
+            let mut cmd: super::Command = serde_json::from_str(&json).unwrap();
+            cmd.compile().map(|_| cmd).map_err(|e| e.to_string())
+        };
+        let err = compile("name").err().unwrap();
+        assert!(err.contains("SNMP entry \"if\", field \"filter-label\": unknown label 'name'"));
+
+        let cmd = compile("descr").unwrap();
+        let walk = SnmpWalk::parse(
+            r#".1.3.6.1.2.1.2.2.1.2.1 = STRING: "lo"
+.1.3.6.1.2.1.2.2.1.2.2 = STRING: "eth0"
+.1.3.6.1.2.1.2.2.1.2.3 = STRING: "eth1"
+.1.3.6.1.2.1.2.2.1.8.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.8.2 = INTEGER: 2
+.1.3.6.1.2.1.2.2.1.8.3 = INTEGER: 1"#,
+        )
+        .unwrap();
+        let filter_in = vec!["^eth".to_string()];
+        let filter_out = vec!["1$".to_string()];
+        let collect = cmd.replay_collect(&walk, &filter_in, &filter_out).unwrap();
+        // Only the status of eth0 is fetched
+        assert_eq!(collect[0].varbinds.len(), 2);
+        assert_eq!(collect[0].indexes["if.status"], vec!["2"]);
+        let result = cmd
+            .evaluate(collect, &filter_in, &filter_out, false)
+            .unwrap();
+        assert_eq!(result.output, "OK: Everything is ok  | eth0#status=2;;;;");
+
+        // Without filters, the whole table is walked
+        let collect = cmd.replay_collect(&walk, &[], &[]).unwrap();
+        assert_eq!(collect[0].varbinds.len(), 6);
+    }
+
+    #[test]
+    fn test_missing() {
+        use crate::snmp::snmpwalk::SnmpWalk;
+
+        let command = |missing: &str| {
+            let json = format!(
+                r#"{{
+                  "collect": {{ "snmp": [
+                    {{ "name": "total", "oid": "1.3.6.1.4.1.2021.4.5.0", "query": "Get" }},
+                    {{ "name": "cached", "oid": "1.3.6.1.4.1.2021.4.15.0", "query": "Get" {} }}
+                  ] }},
+                  "compute": {{
+                    "metrics": [
+                      {{ "name": "total", "value": "{{total}}" }},
+                      {{ "name": "cached", "value": "{{cached}}" }},
+                      {{ "name": "free", "value": "{{total}} - {{metrics.cached}}" }}
+                    ]
+                  }}
+                }}"#,
+                missing
+            );
+            let mut cmd: super::Command = serde_json::from_str(&json).unwrap();
+            cmd.compile().map(|_| cmd).map_err(|e| e.to_string())
+        };
+        let walk = SnmpWalk::parse(".1.3.6.1.4.1.2021.4.5.0 = INTEGER: 8000").unwrap();
+        let output = |cmd: super::Command| {
+            let collect = cmd.replay_collect(&walk, &[], &[]).unwrap();
+            cmd.evaluate(collect, &[], &[], false)
+                .map(|result| result.output)
+                .map_err(|e| e.to_string())
+        };
+
+        let err = output(command("").unwrap()).unwrap_err();
+        assert!(err.starts_with(
+            "SNMP entry \"cached\": the agent has no value at 1.3.6.1.4.1.2021.4.15.0"
+        ));
+        assert_eq!(
+            output(command(r#", "missing": "fail""#).unwrap()).unwrap_err(),
+            err
+        );
+        assert_eq!(
+            output(command(r#", "missing": "skip""#).unwrap()).unwrap(),
+            "OK: Everything is ok  | 0#total=8000;;;;"
+        );
+        assert_eq!(
+            output(command(r#", "missing": { "default": 500 }"#).unwrap()).unwrap(),
+            "OK: Everything is ok  | 0#total=8000;;;; 1#cached=500;;;; 2#free=7500;;;;"
+        );
+
+        let mut cmd: super::Command = serde_json::from_str(
+            r#"{
+              "collect": { "snmp": [
+                { "name": "cpu", "oid": "1.3.6.1.2.1.25.3.3.1.2", "query": "Walk", "missing": "skip" }
+              ] },
+              "compute": { "metrics": [] }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cmd.compile().unwrap_err().to_string(),
+            "SNMP entry \"cpu\", field \"missing\": only a Get can be missing"
+        );
+    }
+}
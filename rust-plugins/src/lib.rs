@@ -0,0 +1,24 @@
+//! Generic SNMP plugin for Centreon.
+//!
+//! A [`generic::Command`] loaded from a JSON definition describes what to
+//! collect via SNMP ([`snmp`]), how to compute metrics from it ([`compute`])
+//! and how to format the Nagios-compatible result ([`output`]). Standard
+//! definitions are built in ([`modes`]), others are looked up by name
+//! ([`definitions`]).
+
+extern crate lalrpop_util;
+extern crate log;
+extern crate rasn;
+extern crate rasn_smi;
+extern crate rasn_snmp;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate snafu;
+
+pub mod compute;
+pub mod definitions;
+pub mod generic;
+pub mod modes;
+pub mod output;
+pub mod snmp;
@@ -1,12 +1,13 @@
 //! Entry point for the Centreon SNMP plugin.
 //!
 //! Parses CLI arguments (hostname, port, SNMP credentials, filters, thresholds),
-//! loads a JSON command definition, runs the SNMP collection and metric computation,
+//! loads a JSON, YAML or TOML command definition, runs the SNMP collection and metric computation,
 //! and prints Nagios-compatible output to stdout.
 //!
 //! # Usage
 //! ```text
 //! plugin -H <host> -p <port> -j <config.json> [--warning-<metric> <value>] [--critical-<metric> <value>]
+//! plugin -H <host> -p <port> --mode <mode> [--warning-<metric> <value>] [--critical-<metric> <value>]
 //! ```
 
 extern crate env_logger;
@@ -21,30 +22,218 @@ extern crate serde;
 extern crate serde_json;
 extern crate snafu;
 
-mod compute;
-mod generic;
-mod output;
-mod snmp;
-
+use centreon_generic_snmp::definitions::SearchPath;
+use centreon_generic_snmp::definitions::format::{self, Format};
+use centreon_generic_snmp::definitions::placeholders::{self, Defines};
+use centreon_generic_snmp::definitions::scaffold;
+use centreon_generic_snmp::generic::Command;
+use centreon_generic_snmp::generic::discovery;
+use centreon_generic_snmp::generic::error::*;
+use centreon_generic_snmp::generic::legacy;
+use centreon_generic_snmp::modes;
+use centreon_generic_snmp::snmp::snmpwalk::{self, SnmpWalk};
+use centreon_generic_snmp::snmp::planner::{Query, snmp_collect};
+use centreon_generic_snmp::snmp::{parse_oid, snmp_walk_varbinds};
 use env_logger::Env;
-use generic::Command;
-use generic::error::*;
-use lalrpop_util::lalrpop_mod;
 use lexopt::Arg;
 use log::trace;
 use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+/// Returns the search path of definitions, starting with the directory
+/// given with `-j` if any.
+fn search_path(json: Option<&str>) -> SearchPath {
+    let mut path = SearchPath::from_env();
+    if let Some(dir) = json.filter(|dir| Path::new(dir).is_dir()) {
+        path.prepend(PathBuf::from(dir));
+    }
+    path
+}
+
+/// Loads the definition to run: the mode if one is given, otherwise the
+/// definition named by `-j`, either a file or a name looked up in the search
+/// path. Its `extends` and `include` entries are resolved, its placeholders
+/// replaced with `defines` and its expressions compiled.
+///
+/// # Errors
+/// Returns an error if a definition cannot be found or read, if the JSON is
+/// malformed or if an expression is invalid.
+fn json_to_command(
+    json: Option<&str>,
+    mode: Option<&str>,
+    defines: &Defines,
+) -> Result<Command, Error> {
+    let path = search_path(json);
+    match (json, mode) {
+        (_, Some(mode)) => path.load(mode, defines),
+        (Some(json), None) => path.load(json, defines),
+        (None, None) => Err(Error::InvalidJSON {
+            message: "JSON file is required (use -j or --json argument, or --mode)".to_string(),
+        }),
+    }
+}
+
+/// Prints the built-in modes, and the definitions of the search path, which
+/// starts with the directory given with `-j`.
+fn list_modes(json: Option<&str>) -> Result<(), Error> {
+    let mut local: Vec<(String, String)> = Vec::new();
+    for dir in search_path(json).directories().iter().filter(|dir| dir.is_dir()) {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| format::EXTENSIONS.contains(&ext))
+                && let Some(name) = path.file_stem().and_then(|name| name.to_str())
+                && !local.iter().any(|(n, _)| n == name)
+            {
+                local.push((name.to_string(), path.display().to_string()));
+            }
+        }
+    }
+    local.sort();
+    println!("Available modes:");
+    for mode in modes::MODES {
+        match local.iter().find(|(name, _)| name == mode.name) {
+            Some((_, path)) => println!("  {:<20} (overridden by {})", mode.name, path),
+            None => println!("  {:<20} {}", mode.name, mode.description),
+        }
+    }
+    for (name, path) in local.iter() {
+        if modes::find(name).is_none() {
+            println!("  {:<20} (local: {})", name, path);
+        }
+    }
+    Ok(())
+}
 
-lalrpop_mod!(grammar);
+/// Prints the definition `file_name` translated into the format `to`.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or parsed, or if the format
+/// is unknown.
+fn convert_definition(file_name: &str, to: &str) -> Result<(), Error> {
+    let to = Format::parse(to)?;
+    let text = fs::read_to_string(file_name)?;
+    let value = Format::detect(Some(Path::new(file_name)), &text).read(&text)?;
+    print!("{}", to.write(&value)?);
+    if to == Format::Json {
+        println!();
+    }
+    Ok(())
+}
 
-/// Reads a JSON file and deserializes it into a [`Command`].
+/// Prints the translation of the legacy definition `file_name` and exits,
+/// with status 1 if parts of it could not be translated.
 ///
 /// # Errors
-/// Returns an error if the file cannot be read or if the JSON is malformed.
-fn json_to_command(file_name: &str) -> Result<Command, Error> {
-    // Transform content of the file into a string
+/// Returns an error if the file cannot be read or is not a legacy definition.
+fn convert_legacy(file_name: &str) -> Result<(), Error> {
     let configuration = fs::read_to_string(file_name)?;
-    let command = serde_json::from_str(&configuration)?;
-    Ok(command)
+    let conversion = legacy::convert(&configuration)?;
+    println!("{}", serde_json::to_string_pretty(&conversion.definition)?);
+    if conversion.problems.is_empty() {
+        std::process::exit(0);
+    }
+    eprintln!("Could not translate:");
+    for problem in conversion.problems {
+        eprintln!("  - {}", problem);
+    }
+    std::process::exit(1);
+}
+
+/// Runs the `walk` or `get` subcommand on `oids` and prints the
+/// responses in the `.snmpwalk` format.
+fn dump(
+    subcommand: &str,
+    url: &str,
+    version: &str,
+    community: &str,
+    oids: &[String],
+) -> Result<(), Error> {
+    let oids: Vec<&str> = oids.iter().map(|oid| oid.trim_start_matches('.')).collect();
+    let results = if subcommand == "walk" {
+        oids.iter()
+            .map(|oid| snmp_walk_varbinds(url, version, community, oid))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        let queries: Vec<Query> = oids.iter().map(|oid| Query::Get { oid, name: oid }).collect();
+        vec![snmp_collect(url, version, community, &queries, 1)?]
+    };
+    for result in results {
+        for var in result.varbinds.iter() {
+            println!("{}", snmpwalk::format_varbind(var));
+        }
+    }
+    Ok(())
+}
+
+/// Walks the table entry `oid`, from the agent or from `snmpwalk_file`, and
+/// prints a starter definition for it in the format `to`.
+fn scaffold_definition(
+    oid: &str,
+    url: &str,
+    version: &str,
+    community: &str,
+    snmpwalk_file: Option<&str>,
+    to: &str,
+) -> Result<(), Error> {
+    let to = Format::parse(to)?;
+    let oid = oid.trim_start_matches('.');
+    let result = match snmpwalk_file {
+        Some(file) => SnmpWalk::read(Path::new(file))?.walk_varbinds(oid)?,
+        None => snmp_walk_varbinds(url, version, community, oid)?,
+    };
+    let definition = scaffold::scaffold(oid, &result.varbinds)?;
+    print!("{}", to.write(&definition)?);
+    if to == Format::Json {
+        println!();
+    }
+    Ok(())
+}
+
+/// Prints the options of the plugin.
+fn print_help() {
+    println!("Usage: plugin [OPTIONS]");
+    println!("       plugin walk|get|scaffold --oid <OID> [-H <HOST>] [-p <PORT>] [-v <VERSION>] [-c <COMMUNITY>]\n");
+    println!("SUBCOMMANDS:");
+    println!("  walk --oid <OID>                 Walk the subtree of OID and print it in the .snmpwalk format");
+    println!("  get --oid <OID>                  Get OID (can be used multiple times) in the .snmpwalk format");
+    println!("  scaffold --oid <OID>             Generate a starter definition from the table entry OID, in the");
+    println!("                                   format given with --to, from the host or from --snmpwalk-file\n");
+    println!("OPTIONS:");
+    println!("  -H, --hostname <HOST>            Hostname or IP address (default: localhost)");
+    println!("  -p, --port <PORT>                SNMP port (default: 161)");
+    println!("  -v, --snmp-version <VERSION>     SNMP version (default: 2c)");
+    println!("  -c, --snmp-community <COMMUNITY> SNMP community (default: public)");
+    println!("  --snmp-concurrency <N>           Maximum number of entries collected at once, overriding");
+    println!("                                   the 'concurrency' of the definition (default: 1)");
+    println!("  -j, --json <FILE>                Command definition file (JSON, YAML or TOML) or name, or directory of local modes");
+    println!("                                   Names are looked up in $CENTREON_PLUGIN_DEFINITIONS and");
+    println!("                                   /usr/share/centreon-generic-snmp/definitions");
+    println!("  -m, --mode <MODE>                Built-in or local mode to run instead of a JSON file");
+    println!("  -D, --define <NAME>=<VALUE>      Value of the ${{NAME}} placeholders of the definition");
+    println!("  --list-modes                     List the available modes and exit");
+    println!("  --snmpwalk-file <FILE>           Answer the SNMP queries from a .snmpwalk file instead of the host");
+    println!("  -i, --filter-in <FILTER>         Include filter (can be used multiple times)");
+    println!("  -o, --filter-out <FILTER>        Exclude filter (can be used multiple times)");
+    println!("  --warning-<METRIC> <VALUE>       Warning threshold for metric");
+    println!("  --critical-<METRIC> <VALUE>      Critical threshold for metric");
+    println!("                                   VALUE may be keyed by instance: '/var=90,re:^/data=95,*=80'");
+    println!("                                   METRIC may be a glob ('cpu*') or a regex ('re:^cpu')");
+    println!("                                   For status metrics, VALUE is an expression: '{{if.status}} !~ /up/'");
+    println!("  --check-format                   Check JSON file validity and exit");
+    println!("  --list-instances                 List the instances found by the definition as JSON and exit");
+    println!("  --disco-format                   Print the attributes of the discovered instances (XML) and exit");
+    println!("  --disco-show                     Print the discovered instances (XML) and exit");
+    println!("  --convert-definition <FILE>      Translate a JSON, YAML or TOML definition and exit");
+    println!("  --to <FORMAT>                    Format of --convert-definition and scaffold: json (default), yaml or toml");
+    println!("  --convert-legacy <FILE>          Convert a legacy 'leaf/entries' definition and exit");
+    println!("  --print-schema                   Print the JSON Schema of command definitions and exit");
+    println!("  --lint                           Warn about unused collects, shared thresholds and bad OIDs, and exit");
+    println!("  -h, --help                       Print this help message, with the threshold options of");
+    println!("                                   the definition given with -j or --mode");
 }
 
 fn main() -> Result<(), Error> {
@@ -61,11 +250,22 @@ fn main() -> Result<(), Error> {
     let mut port = 161;
     let mut snmp_version = "2c".to_string();
     let mut snmp_community = "public".to_string();
+    let mut concurrency: Option<usize> = None;
     let mut filter_in = Vec::new();
     let mut filter_out = Vec::new();
     let mut check_format = false;
+    let mut lint = false;
+    let mut discovery: Option<String> = None;
+    let mut subcommand: Option<String> = None;
+    let mut oids: Vec<String> = Vec::new();
+    let mut snmpwalk_file: Option<String> = None;
     let mut json_file: Option<String> = None;
-    let mut cmd: Option<Command> = None;
+    let mut mode: Option<String> = None;
+    let mut list = false;
+    let mut help = false;
+    let mut defines = Defines::new();
+    let mut convert: Option<String> = None;
+    let mut to = "json".to_string();
     let mut warnings: Vec<(String, String)> = Vec::new();
     let mut criticals: Vec<(String, String)> = Vec::new();
     loop {
@@ -86,6 +286,19 @@ fn main() -> Result<(), Error> {
                         json_file = Some(json);
                         trace!("json file: {:?}", json_file);
                     }
+                    Short('m') | Long("mode") => {
+                        mode = Some(parser.value()?.into_string()?);
+                        trace!("mode: {:?}", mode);
+                    }
+                    Short('D') | Long("define") => {
+                        let define = parser.value()?.into_string()?;
+                        let (name, value) = placeholders::parse_define(&define)?;
+                        trace!("define: {} = {}", name, value);
+                        defines.insert(name, value);
+                    }
+                    Long("list-modes") => {
+                        list = true;
+                    }
                     Short('v') | Long("snmp-version") => {
                         snmp_version = parser.value()?.into_string()?;
                         trace!("snmp_version: {}", snmp_version);
@@ -94,6 +307,10 @@ fn main() -> Result<(), Error> {
                         snmp_community = parser.value()?.into_string()?;
                         trace!("snmp_community: {}", snmp_community);
                     }
+                    Long("snmp-concurrency") => {
+                        concurrency = Some(parser.value()?.parse::<NonZeroUsize>()?.get());
+                        trace!("concurrency: {:?}", concurrency);
+                    }
                     Short('i') | Long("filter-in") => {
                         let f = parser.value()?.into_string()?;
                         trace!("New filter_in: {}", f);
@@ -105,24 +322,44 @@ fn main() -> Result<(), Error> {
                         filter_out.push(f);
                     }
                     Short('h') | Long("help") => {
-                        println!("Usage: plugin [OPTIONS]\n");
-                        println!("OPTIONS:");
-                        println!("  -H, --hostname <HOST>            Hostname or IP address (default: localhost)");
-                        println!("  -p, --port <PORT>                SNMP port (default: 161)");
-                        println!("  -v, --snmp-version <VERSION>     SNMP version (default: 2c)");
-                        println!("  -c, --snmp-community <COMMUNITY> SNMP community (default: public)");
-                        println!("  -j, --json <FILE>                JSON command definition file (required)");
-                        println!("  -i, --filter-in <FILTER>         Include filter (can be used multiple times)");
-                        println!("  -o, --filter-out <FILTER>        Exclude filter (can be used multiple times)");
-                        println!("  --warning-<METRIC> <VALUE>       Warning threshold for metric");
-                        println!("  --critical-<METRIC> <VALUE>      Critical threshold for metric");
-                        println!("  --check-format                   Check JSON file validity and exit");
-                        println!("  -h, --help                       Print this help message");
-                        std::process::exit(0);
+                        help = true;
                     }
                     Long("check-format") => {
                         check_format = true;
                     }
+                    Long("convert-definition") => {
+                        convert = Some(parser.value()?.into_string()?);
+                    }
+                    Long("to") => {
+                        to = parser.value()?.into_string()?;
+                    }
+                    Long("convert-legacy") => {
+                        let file = parser.value()?.into_string()?;
+                        convert_legacy(&file)?;
+                    }
+                    Long("print-schema") => {
+                        println!("{}", serde_json::to_string_pretty(&Command::schema())?);
+                        std::process::exit(0);
+                    }
+                    Long("lint") => {
+                        lint = true;
+                    }
+                    Value(value) if subcommand.is_none() => {
+                        let value = value.string()?;
+                        if !["walk", "get", "scaffold"].contains(&value.as_str()) {
+                            return Err(Error::UnknownArgument { arg: value });
+                        }
+                        subcommand = Some(value);
+                    }
+                    Long("oid") => {
+                        oids.push(parser.value()?.into_string()?);
+                    }
+                    Long("snmpwalk-file") => {
+                        snmpwalk_file = Some(parser.value()?.into_string()?);
+                    }
+                    Long(name @ ("list-instances" | "disco-format" | "disco-show")) => {
+                        discovery = Some(name.to_string());
+                    }
                     t => {
                         match t {
                             Arg::Long(name) if name.starts_with("warning-") => {
@@ -165,35 +402,84 @@ fn main() -> Result<(), Error> {
             }
         }
     }
-    if let Some(file) = json_file {
-        if check_format {
-            println!("Check format of JSON file '{}'", file);
-        }
-        match json_to_command(&file) {
-            Ok(c) => {
-                cmd = Some(c);
-            }
-            Err(e) => {
-                if check_format {
-                    eprintln!("JSON is INVALID: {}", e);
-                    std::process::exit(3);
-                } else {
-                    eprintln!("UNKNOWN: Cannot read JSON file '{}': {}", file, e);
-                    std::process::exit(3);
-                }
+    if help {
+        print_help();
+        // With a definition, also describe its threshold options
+        if json_file.is_some() || mode.is_some() {
+            println!();
+            match json_to_command(json_file.as_deref(), mode.as_deref(), &defines) {
+                Ok(cmd) => print!("{}", cmd.help()),
+                Err(e) => println!("Cannot load the definition: {}", e),
             }
         }
-    } else {
-        println!("JSON file is required (use -j or --json argument)");
+        std::process::exit(0);
+    }
+    if let Some(subcommand) = subcommand {
+        if oids.is_empty() {
+            println!("The {} subcommand requires --oid", subcommand);
+            std::process::exit(3);
+        }
+        if let Some(oid) = oids.iter().find(|oid| parse_oid(oid).is_none()) {
+            println!("Invalid --oid '{}': expected numbers separated by dots", oid);
+            std::process::exit(3);
+        }
+        let url = format!("{}:{}", hostname, port);
+        if subcommand == "scaffold" {
+            let file = snmpwalk_file.as_deref();
+            scaffold_definition(&oids[0], &url, &snmp_version, &snmp_community, file, &to)?;
+        } else {
+            dump(&subcommand, &url, &snmp_version, &snmp_community, &oids)?;
+        }
+        std::process::exit(0);
+    }
+    if let Some(file) = convert {
+        convert_definition(&file, &to)?;
+        std::process::exit(0);
+    }
+    if list {
+        list_modes(json_file.as_deref())?;
+        std::process::exit(0);
+    }
+    if json_file.is_none() && mode.is_none() {
+        println!("JSON file is required (use -j or --json argument, or --mode)");
         std::process::exit(3);
     }
+    let source = match (&mode, &json_file) {
+        (Some(mode), _) => format!("mode '{}'", mode),
+        (None, Some(file)) => format!("JSON file '{}'", file),
+        (None, None) => unreachable!(),
+    };
+    if check_format {
+        println!("Check format of {}", source);
+    }
+    let mut cmd: Option<Command> = match json_to_command(json_file.as_deref(), mode.as_deref(), &defines) {
+        Ok(c) => Some(c),
+        Err(e) => {
+            if check_format {
+                eprintln!("JSON is INVALID: {}", e);
+                std::process::exit(3);
+            } else {
+                eprintln!("UNKNOWN: Cannot read {}: {}", source, e);
+                std::process::exit(3);
+            }
+        }
+    };
 
     if let Some(ref mut cmd) = cmd {
+        if let Some(concurrency) = concurrency {
+            cmd.set_concurrency(concurrency);
+        }
         for (metric, value) in warnings {
-            cmd.add_warning(&metric, value);
+            if let Err(e) = cmd.add_warning(&metric, value) {
+                eprintln!("UNKNOWN: {}", e);
+                std::process::exit(3);
+            }
         }
         for (metric, value) in criticals {
-            cmd.add_critical(&metric, value);
+            if let Err(e) = cmd.add_critical(&metric, value) {
+                eprintln!("UNKNOWN: {}", e);
+                std::process::exit(3);
+            }
         }
     }
 
@@ -205,16 +491,81 @@ fn main() -> Result<(), Error> {
         }
     };
 
+    if lint {
+        let warnings = cmd.lint();
+        if warnings.is_empty() {
+            println!("No lint warnings");
+            std::process::exit(0);
+        }
+        println!("Lint warnings:");
+        for warning in warnings {
+            println!("  - {}", warning);
+        }
+        std::process::exit(1);
+    }
+
+    if discovery.as_deref() == Some("disco-format") {
+        print!("{}", discovery::disco_format(&cmd.disco_attributes()));
+        std::process::exit(0);
+    }
+
     let url = format!("{}:{}", hostname, port);
+    let snmpwalk = match snmpwalk_file {
+        Some(file) => match SnmpWalk::read(Path::new(&file)) {
+            Ok(walk) => Some(walk),
+            Err(e) => {
+                eprintln!("UNKNOWN: Cannot read {}: {}", file, e);
+                std::process::exit(3);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(discovery) = discovery {
+        let instances = match &snmpwalk {
+            Some(walk) => cmd
+                .replay_collect(walk, &filter_in, &filter_out)
+                .and_then(|collect| cmd.instances(collect, &filter_in, &filter_out)),
+            None => cmd.discover(&url, &snmp_version, &snmp_community, &filter_in, &filter_out),
+        };
+        let instances = instances.unwrap_or_else(|e| {
+            eprintln!("UNKNOWN: {}", e);
+            std::process::exit(3);
+        });
+        if discovery == "disco-show" {
+            print!("{}", discovery::disco_show(&instances));
+        } else {
+            println!("{}", serde_json::to_string_pretty(&instances)?);
+        }
+        std::process::exit(0);
+    }
 
-    let result = cmd.execute(
-        &url,
-        &snmp_version,
-        &snmp_community,
-        &filter_in,
-        &filter_out,
-        check_format,
-    ).unwrap_or_else(|e| {
+    if check_format {
+        let problems = cmd.check();
+        if !problems.is_empty() {
+            eprintln!("JSON is INVALID:");
+            for problem in problems {
+                eprintln!("  - {}", problem);
+            }
+            std::process::exit(3);
+        }
+    }
+
+    let result = match &snmpwalk {
+        Some(walk) if !check_format => {
+            cmd.replay_collect(walk, &filter_in, &filter_out)
+                .and_then(|collect| cmd.evaluate(collect, &filter_in, &filter_out, false))
+        }
+        _ => cmd.execute(
+            &url,
+            &snmp_version,
+            &snmp_community,
+            &filter_in,
+            &filter_out,
+            check_format,
+        ),
+    };
+    let result = result.unwrap_or_else(|e| {
         if check_format {
             eprintln!("JSON is INVALID: {}", e);
         } else {
@@ -0,0 +1,110 @@
+//! Built-in command definitions, selected with `--mode <name>`.
+//!
+//! The definitions are JSON files of this directory embedded in the binary,
+//! so the standard checks run without deploying any file. A definition found
+//! in the directory given with `-j` takes precedence over a built-in mode of
+//! the same name.
+
+/// A command definition shipped with the plugin.
+pub struct Mode {
+    /// Name given to `--mode`.
+    pub name: &'static str,
+    /// One-line description shown by `--list-modes`.
+    pub description: &'static str,
+    /// The JSON definition.
+    pub definition: &'static str,
+}
+
+/// The built-in modes, sorted by name.
+pub const MODES: &[Mode] = &[
+    Mode {
+        name: "cpu",
+        description: "Usage of each processor and their average (HOST-RESOURCES-MIB)",
+        definition: include_str!("cpu.json"),
+    },
+    Mode {
+        name: "interfaces",
+        description: "Operational status of the network interfaces (IF-MIB)",
+        definition: include_str!("interfaces.json"),
+    },
+    Mode {
+        name: "load",
+        description: "Load averages (UCD-SNMP-MIB)",
+        definition: include_str!("load.json"),
+    },
+    Mode {
+        name: "memory",
+        description: "Memory usage without buffers and cache (UCD-SNMP-MIB)",
+        definition: include_str!("memory.json"),
+    },
+    Mode {
+        name: "processes",
+        description: "Number of running processes (HOST-RESOURCES-MIB)",
+        definition: include_str!("processes.json"),
+    },
+    Mode {
+        name: "storage",
+        description: "Usage of the fixed disks (HOST-RESOURCES-MIB)",
+        definition: include_str!("storage.json"),
+    },
+    Mode {
+        name: "tcp-connections",
+        description: "Number of TCP connections (TCP-MIB)",
+        definition: include_str!("tcp-connections.json"),
+    },
+    Mode {
+        name: "uptime",
+        description: "Time since the network management was started (SNMPv2-MIB)",
+        definition: include_str!("uptime.json"),
+    },
+];
+
+/// Returns the built-in mode named `name`, if any.
+pub fn find(name: &str) -> Option<&'static Mode> {
+    MODES.iter().find(|mode| mode.name == name)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_modes_are_valid() {
+        use crate::generic::Command;
+        use crate::modes::MODES;
+
+        for mode in MODES {
+            let cmd = match Command::from_json(mode.definition) {
+                Ok(cmd) => cmd,
+                Err(e) => panic!("Mode '{}': {}", mode.name, e),
+            };
+            assert!(
+                cmd.check().is_empty(),
+                "Mode '{}': {:?}",
+                mode.name,
+                cmd.check()
+            );
+            assert!(
+                cmd.lint().is_empty(),
+                "Mode '{}': {:?}",
+                mode.name,
+                cmd.lint()
+            );
+            if let Err(e) = cmd.execute("", "2c", "public", &vec![], &vec![], true) {
+                panic!("Mode '{}': {}", mode.name, e);
+            }
+        }
+        let mut names: Vec<&str> = MODES.iter().map(|mode| mode.name).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            MODES.iter().map(|mode| mode.name).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        use crate::modes::find;
+
+        assert_eq!(find("memory").map(|mode| mode.name), Some("memory"));
+        assert!(find("mem").is_none());
+    }
+}
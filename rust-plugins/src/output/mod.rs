@@ -2,15 +2,17 @@
 //!
 //! Produces output like: `STATUS message | metric1=value1;warn;crit;min;max metric2=...`
 
-use crate::compute::Parser;
+use crate::compute::Template;
 use crate::compute::ast::ExprResult;
+use crate::compute::symbols::Symbols;
 use crate::generic::{Perfdata, Status};
-use crate::snmp::SnmpResult;
 use log::error;
+use schemars::JsonSchema;
 use serde::Deserialize;
 
 /// Configurable status messages and separators for plugin output.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Output {
     /// Message template for OK status.
     #[serde(default = "default_ok")]
@@ -42,6 +44,9 @@ pub struct Output {
     /// String used to separate individual metrics in perfdata.
     #[serde(default = "default_metric_separator")]
     metric_separator: String,
+    /// The `ok` template, parsed by [`Output::compile`].
+    #[serde(skip)]
+    ok_template: Template,
 }
 
 fn default_ok() -> String {
@@ -83,14 +88,25 @@ impl Output {
             detail_unknown: true,
             instance_separator: default_instance_separator(),
             metric_separator: default_metric_separator(),
+            ok_template: Template::parse(&default_ok()),
         }
     }
+
+    /// Parses the `ok` template once, before evaluating it for each run.
+    pub fn compile(&mut self) {
+        self.ok_template = Template::parse(&self.ok);
+    }
+
+    /// Returns the `ok` template parsed by [`Output::compile`].
+    pub fn ok_template(&self) -> &Template {
+        &self.ok_template
+    }
 }
 
 /// Formats plugin results into Nagios-compatible output string.
 pub struct OutputFormatter<'a> {
     status: Status,
-    collect: &'a Vec<SnmpResult>,
+    symbols: &'a Symbols,
     metrics: &'a Vec<Perfdata<'a>>,
     output_formatter: &'a Output,
 }
@@ -99,13 +115,13 @@ impl<'a> OutputFormatter<'a> {
     /// Creates a new formatter with the given status, metrics, and output configuration.
     pub fn new(
         status: Status,
-        collect: &'a Vec<SnmpResult>,
+        symbols: &'a Symbols,
         metrics: &'a Vec<Perfdata>,
         formatter: &'a Output,
     ) -> OutputFormatter<'a> {
         OutputFormatter {
             status,
-            collect,
+            symbols,
             metrics,
             output_formatter: formatter,
         }
@@ -116,6 +132,7 @@ impl<'a> OutputFormatter<'a> {
         let metrics = self
             .metrics
             .iter()
+            .filter(|m| m.text.is_none())
             .map(|m| {
                 format!(
                     "{}={}{};{};{};{};{}",
@@ -142,8 +159,7 @@ impl<'a> OutputFormatter<'a> {
                     let detail = self.build_detail(&self.output_formatter.ok);
                     return format!("{} | {}", detail, metrics);
                 } else {
-                    let parser = Parser::new(&self.collect, false);
-                    let res = parser.eval_str(&self.output_formatter.ok);
+                    let res = self.output_formatter.ok_template.eval(self.symbols, false);
                     let output = match res {
                         Ok(output) => match output {
                             ExprResult::Str(output) => output,
@@ -208,12 +224,15 @@ impl<'a> OutputFormatter<'a> {
         for m in self.metrics.iter() {
             if let Some(status) = m.status {
                 if status.is_worse_than(self.status) {
-                    v.push(std::format!(
-                        "{} is {}{}",
-                        m.name,
-                        float_string(&m.value),
-                        m.uom
-                    ));
+                    match &m.text {
+                        Some(text) => v.push(std::format!("{} is {}", m.name, text)),
+                        None => v.push(std::format!(
+                            "{} is {}{}",
+                            m.name,
+                            float_string(&m.value),
+                            m.uom
+                        )),
+                    }
                 }
             }
         }
@@ -231,6 +250,7 @@ impl<'a> OutputFormatter<'a> {
 ///
 /// For example:
 /// ```
+/// # use centreon_generic_snmp::output::float_string;
 /// let val = 40.009;
 /// let formatted = float_string(&val);
 /// // assert_eq!(formatted, "40.01");
@@ -249,6 +269,7 @@ pub fn float_string(val: &f64) -> String {
     s
 }
 
+#[cfg(test)]
 mod test {
 
     #[test]
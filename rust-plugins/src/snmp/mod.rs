@@ -8,17 +8,25 @@ extern crate rasn;
 extern crate rasn_smi;
 extern crate rasn_snmp;
 
+pub mod planner;
+pub mod snmpwalk;
+
 use crate::compute::ast::ExprResult;
-use log::{info, trace, warn};
+use crate::generic::error::{Error, Result};
+use log::{debug, info, trace, warn};
 use rasn::types::ObjectIdentifier;
+use rasn_smi::v2::{ApplicationSyntax, ObjectSyntax, SimpleSyntax};
 use rasn_snmp::v2::BulkPdu;
 use rasn_snmp::v2::GetBulkRequest;
+use rasn_snmp::v2::GetRequest;
+use rasn_snmp::v2::Pdu;
 use rasn_snmp::v2::Pdus;
 use rasn_snmp::v2::VarBind;
 use rasn_snmp::v2::VarBindValue;
 use rasn_snmp::v2c::Message;
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::io::ErrorKind;
 use std::net::UdpSocket;
 
 /// The SNMP value type for an OID response.
@@ -38,13 +46,58 @@ pub enum ValueType {
 
 /// Result of an SNMP query operation.
 ///
-/// Stores collected values keyed by OID name, and tracks the last OID
-/// for walk-based operations.
+/// Stores collected values keyed by OID name.
 #[derive(Debug)]
 pub struct SnmpResult {
     /// Collected values from this SNMP query, indexed by OID name.
     pub items: HashMap<String, ExprResult>,
-    last_oid: Vec<u32>,
+    /// Instance index (the OID part after the column) of each walked value,
+    /// by item name, in the same order as the values of [`SnmpResult::items`].
+    pub indexes: HashMap<String, Vec<String>>,
+    /// Variable bindings received for this query, in the order of the
+    /// responses.
+    pub varbinds: Vec<VarBind>,
+}
+
+/// Converts a value received from the agent: numbers of any kind become
+/// integers, strings, OIDs and addresses become strings.
+fn value_type(value: &ObjectSyntax) -> ValueType {
+    info!("Value {:?}", value);
+    match value {
+        ObjectSyntax::Simple(SimpleSyntax::Integer(value)) => {
+            ValueType::Integer(value.try_into().unwrap())
+        }
+        ObjectSyntax::Simple(SimpleSyntax::String(value)) => {
+            // We transform the value into a rust String
+            ValueType::String(String::from_utf8_lossy(value).into_owned())
+        }
+        ObjectSyntax::Simple(SimpleSyntax::ObjectId(value)) => {
+            ValueType::String(value.iter().map(|&id| id.to_string() + ".").collect())
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Address(address)) => ValueType::String(
+            address
+                .0
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>()
+                .join("."),
+        ),
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Counter(counter)) => {
+            ValueType::Integer(counter.0.into())
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Ticks(time_ticks)) => {
+            ValueType::Integer(time_ticks.0.into())
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Unsigned(gauge)) => {
+            ValueType::Integer(gauge.0.into())
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::BigCounter(counter64)) => {
+            ValueType::Counter64(counter64.0)
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Arbitrary(_)) => {
+            ValueType::String("Other".to_string())
+        }
+    }
 }
 
 impl SnmpResult {
@@ -52,7 +105,8 @@ impl SnmpResult {
     pub fn new(items: HashMap<String, ExprResult>) -> SnmpResult {
         SnmpResult {
             items,
-            last_oid: Vec::new(),
+            indexes: HashMap::new(),
+            varbinds: Vec::new(),
         }
     }
 }
@@ -167,678 +221,433 @@ impl SnmpResult {
 //    retval
 //}
 
-/// Retrieves values for multiple OIDs in a single bulk request.
-///
-/// # Arguments
-/// * `target` - Target address in "host:port" format
-/// * `_version` - SNMP version (e.g., "2c")
-/// * `community` - SNMP community string
-/// * `non_repeaters` - Number of non-repeating OIDs (typically 0 or 1)
-/// * `max_repetitions` - Maximum repetitions per OID
-/// * `oid` - Vector of OID strings to query
-/// * `names` - Vector of logical names (one per OID)
-///
-/// # Returns
-/// An [`SnmpResult`] containing the retrieved values indexed by name
-pub fn snmp_bulk_get<'a>(
-    target: &str,
-    _version: &str,
-    community: &str,
-    non_repeaters: u32,
-    max_repetitions: u32,
-    oid: &Vec<&str>,
-    names: &Vec<&str>,
-) -> SnmpResult {
-    let oids_tab = oid
-        .iter()
-        .map(|x| {
-            x.split('.')
-                .skip_while(|d| d.is_empty()) // OIDs are generally given starting with a '.' so the first digit may be empty
-                .map(|x| x.parse::<u32>().unwrap())
-                .filter(|x| *x != 0) // As we only use bulk requests, we have to skip the trailing 0 if it exists or the first OID we are trying to get will never be requested
-                .collect::<Vec<u32>>()
-        })
-        .collect::<Vec<Vec<u32>>>();
+/// A column of a table walked by [`walk_columns`].
+struct Column {
+    /// OID of the column, `<entry>.<column>`, or of a whole subtree.
+    oid: Vec<u32>,
+    /// Name under which the values are stored, or `None` to only keep the
+    /// varbinds.
+    key: Option<String>,
+    /// OID to continue from, `None` once the end of the column is reached.
+    next: Option<Vec<u32>>,
+}
 
-    let mut retval = SnmpResult {
-        items: HashMap::new(),
-        last_oid: Vec::new(),
-    };
-    let request_id: i32 = 1;
+impl Column {
+    fn new(oid: Vec<u32>, key: Option<String>) -> Column {
+        Column {
+            next: Some(oid.clone()),
+            oid,
+            key,
+        }
+    }
+}
 
-    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    socket.connect(target).expect("connect function failed");
-    let duration = std::time::Duration::from_millis(1000);
-    socket.set_read_timeout(Some(duration)).unwrap();
+/// A scalar fetched by a `GetRequest`.
+struct Scalar {
+    /// OID of the instance, such as `1.3.6.1.2.1.1.3.0`.
+    oid: Vec<u32>,
+    /// Name under which the value is stored.
+    key: String,
+}
 
-    let variable_bindings = oids_tab
-        .iter()
-        .map(|x| VarBind {
-            name: ObjectIdentifier::new_unchecked(x.to_vec().into()),
-            value: VarBindValue::Unspecified,
+impl Scalar {
+    /// Returns `None` if `oid` is not a valid OID.
+    fn new(oid: &str, key: &str) -> Option<Scalar> {
+        Some(Scalar {
+            oid: parse_oid(oid)?,
+            key: key.to_string(),
         })
-        .collect::<Vec<VarBind>>();
+    }
+}
 
-    let pdu = BulkPdu {
-        request_id,
-        variable_bindings,
-        non_repeaters,
-        max_repetitions,
-    };
+/// Number of rows asked for each column by a `GetBulkRequest`.
+const MAX_REPETITIONS: u32 = 10;
 
-    let get_request: GetBulkRequest = GetBulkRequest(pdu);
+/// Maximum number of varbinds of a request: longer lists of OIDs are split
+/// across several PDUs.
+const MAX_VARBINDS: usize = 32;
 
-    let message: Message<GetBulkRequest> = Message {
-        version: 1.into(),
-        community: community.to_string().into(),
-        data: get_request.into(),
+/// Parses an OID written with or without a leading dot, such as
+/// `.1.3.6.1.2.1.1.3.0`, or returns `None` if one of its arcs is not a
+/// number.
+pub fn parse_oid(oid: &str) -> Option<Vec<u32>> {
+    let oid = oid.strip_prefix('.').unwrap_or(oid);
+    oid.split('.').map(|arc| arc.parse::<u32>().ok()).collect()
+}
+
+/// Returns the columns of the table entry `oid` named by `labels`, sorted
+/// by OID. Columns with an invalid OID are left out.
+fn label_columns(oid: &str, snmp_name: &str, labels: &HashMap<String, String>) -> Vec<Column> {
+    let Some(entry) = parse_oid(oid) else {
+        warn!("Invalid OID '{}' of '{}' not walked", oid, snmp_name);
+        return Vec::new();
     };
+    let mut columns: Vec<Column> = labels
+        .iter()
+        .filter_map(|(suffix, label)| {
+            let Some(suffix) = parse_oid(suffix) else {
+                warn!(
+                    "Invalid OID suffix '{}' of '{}.{}' not walked",
+                    suffix, snmp_name, label
+                );
+                return None;
+            };
+            let oid = [entry.clone(), suffix].concat();
+            Some(Column::new(oid, Some(format!("{}.{}", snmp_name, label))))
+        })
+        .collect();
+    columns.sort_by(|a, b| a.oid.cmp(&b.oid));
+    columns
+}
 
-    // Send the message through an UDP socket
-    let encoded: Vec<u8> = rasn::der::encode(&message).unwrap();
-    let res: usize = socket.send(&encoded).unwrap();
-    assert!(res == encoded.len());
+/// Sends the requests of a collection: to an agent over UDP, or to a
+/// replayed `.snmpwalk` file.
+trait Agent {
+    /// Sends a `GetBulkRequest` without non-repeaters and returns the
+    /// response.
+    fn get_bulk(&mut self, request: Vec<VarBind>, max_repetitions: u32) -> Result<Message<Pdus>>;
+    /// Sends a `GetRequest` and returns the response.
+    fn get(&mut self, request: Vec<VarBind>) -> Result<Message<Pdus>>;
+    /// Sends a `GetRequest` and a `GetBulkRequest` and returns their
+    /// responses, in the same round trip if the agent can.
+    fn get_and_get_bulk(
+        &mut self,
+        get: Vec<VarBind>,
+        bulk: Vec<VarBind>,
+        max_repetitions: u32,
+    ) -> Result<(Message<Pdus>, Message<Pdus>)> {
+        Ok((self.get(get)?, self.get_bulk(bulk, max_repetitions)?))
+    }
+}
 
-    let mut buf: [u8; 1024] = [0; 1024];
-    let resp: (usize, std::net::SocketAddr) = socket.recv_from(buf.as_mut_slice()).unwrap();
+/// Converts the value of a variable binding, `ValueType::None` if the agent
+/// has none.
+fn var_type(var: &VarBind) -> ValueType {
+    match &var.value {
+        VarBindValue::Value(value) => value_type(value),
+        other => {
+            warn!("{:?}", other);
+            ValueType::None(())
+        }
+    }
+}
 
-    trace!("Received {} bytes", resp.0);
-    assert!(resp.0 > 0);
-    let decoded: Message<Pdus> = rasn::ber::decode(&buf[0..resp.0]).unwrap();
-    let _completed = retval.build_response_with_names(decoded, "", names, false);
-    retval
+/// Returns the OID arcs after `base` joined with dots: the index of a row.
+fn index_of(name: &[u32], base: &[u32]) -> String {
+    let index: Vec<String> = name[base.len()..].iter().map(u32::to_string).collect();
+    index.join(".")
 }
 
-/// Walks a subtree of OIDs using repeated bulk requests until the subtree is exhausted.
-///
-/// Continues retrieving values until an OID outside the subtree is encountered
-/// or a timeout occurs.
+/// Returns the name of an SNMPv2 error status.
+fn error_name(status: u32) -> &'static str {
+    match status {
+        Pdu::ERROR_STATUS_TOO_BIG => "tooBig",
+        Pdu::ERROR_STATUS_NO_SUCH_NAME => "noSuchName",
+        Pdu::ERROR_STATUS_BAD_VALUE => "badValue",
+        Pdu::ERROR_STATUS_GEN_ERR => "genErr",
+        Pdu::ERROR_STATUS_NO_ACCESS => "noAccess",
+        Pdu::ERROR_STATUS_RESOURCE_UNAVAILABLE => "resourceUnavailable",
+        Pdu::ERROR_STATUS_AUTHORIZATION_ERROR => "authorizationError",
+        _ => "unknown error",
+    }
+}
+
+/// Walks `columns` in parallel: every `GetBulkRequest` sent to `agent`
+/// carries one varbind per column not finished yet, and each column stops
+/// independently at the end of its subtree.
 ///
-/// # Arguments
-/// * `target` - Target address in "host:port" format
-/// * `_version` - SNMP version (e.g., "2c")
-/// * `community` - SNMP community string
-/// * `oid` - The base OID to walk
-/// * `snmp_name` - Logical name for collected values
+/// No request carries more than [`MAX_VARBINDS`] varbinds: the columns
+/// left out of a request are walked once the previous ones are finished.
+/// The columns missing from a truncated response are requested again, and
+/// a `tooBig` response is retried with fewer repetitions, then fewer
+/// columns.
 ///
-/// # Returns
-/// An [`SnmpResult`] containing all values under the specified OID
-pub fn snmp_bulk_walk<'a>(
-    target: &str,
-    _version: &str,
-    community: &str,
-    oid: &str,
-    snmp_name: &str,
-) -> SnmpResult {
-    let oid_init = oid
-        .split('.')
-        .skip_while(|d| d.is_empty()) // OIDs are generally given starting with a '.' so the first digit may be empty
-        .map(|x| x.parse::<u32>().unwrap())
-        .collect::<Vec<u32>>();
-    let mut oid_tab = &oid_init;
-    let mut retval = SnmpResult {
-        items: HashMap::new(),
-        last_oid: Vec::new(),
-    };
-    let request_id: i32 = 1;
-
-    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    socket.connect(target).expect("connect function failed");
-    let duration = std::time::Duration::from_millis(1000);
-    socket.set_read_timeout(Some(duration)).unwrap();
-
+/// # Errors
+/// Returns an error if the agent does not answer, answers with any other
+/// error status, or with `tooBig` to a single repetition of a single column.
+fn walk_columns(
+    agent: &mut impl Agent,
+    columns: &mut [Column],
+    result: &mut SnmpResult,
+) -> Result<()> {
+    let mut repetitions = MAX_REPETITIONS;
+    let mut width = MAX_VARBINDS;
     loop {
-        let variable_bindings = vec![VarBind {
-            name: ObjectIdentifier::new_unchecked(oid_tab.to_vec().into()),
-            value: VarBindValue::Unspecified,
-        }];
-
-        let pdu = BulkPdu {
-            request_id,
-            non_repeaters: 0,
-            max_repetitions: 10,
-            variable_bindings,
+        let active: Vec<usize> = (0..columns.len())
+            .filter(|&i| columns[i].next.is_some())
+            .take(width)
+            .collect();
+        if active.is_empty() {
+            break;
+        }
+        let variable_bindings: Vec<VarBind> = active
+            .iter()
+            .filter_map(|&i| columns[i].next.clone())
+            .map(|next| VarBind {
+                name: ObjectIdentifier::new_unchecked(next.into()),
+                value: VarBindValue::Unspecified,
+            })
+            .collect();
+        let first = variable_bindings[0].name.to_string();
+        let decoded = agent.get_bulk(variable_bindings, repetitions)?;
+        let Pdus::Response(resp) = &decoded.data else {
+            warn!("Unexpected PDU in response to a GetBulkRequest");
+            break;
         };
+        let vars = &resp.0.variable_bindings;
+        let status = resp.0.error_status;
+        // An empty response cannot make progress either
+        if status == Pdu::ERROR_STATUS_TOO_BIG
+            || (status == Pdu::ERROR_STATUS_NO_ERROR && vars.is_empty())
+        {
+            if repetitions > 1 {
+                repetitions /= 2;
+            } else if active.len() > 1 {
+                width = active.len() / 2;
+            } else {
+                return Err(Error::AgentError {
+                    status,
+                    name: error_name(status).to_string(),
+                    oid: first,
+                });
+            }
+            debug!(
+                "Response too big, retrying with {} repetitions of at most {} columns",
+                repetitions, width
+            );
+            continue;
+        }
+        if status != Pdu::ERROR_STATUS_NO_ERROR {
+            return Err(Error::AgentError {
+                status,
+                name: error_name(status).to_string(),
+                oid: first,
+            });
+        }
+        // The response holds the rows one after the other, with one varbind
+        // per requested column in each row
+        for (k, var) in vars.iter().enumerate() {
+            let column = &mut columns[active[k % active.len()]];
+            let Some(next) = &column.next else {
+                continue;
+            };
+            let name: Vec<u32> = var.name.iter().copied().collect();
+            if matches!(var.value, VarBindValue::EndOfMibView)
+                || !name.starts_with(&column.oid)
+                || name <= *next
+            {
+                column.next = None;
+                continue;
+            }
+            result.varbinds.push(var.clone());
+            if let Some(key) = &column.key {
+                let typ = var_type(var);
+                if !matches!(typ, ValueType::None(())) {
+                    let index = index_of(&name, &column.oid);
+                    result.indexes.entry(key.clone()).or_default().push(index);
+                    result.push_value(key, typ);
+                }
+            }
+            column.next = Some(name);
+        }
+        // The columns missing from a truncated response keep their `next`
+        // and are requested again
+    }
+    Ok(())
+}
+
+/// An SNMP agent queried over UDP, through a single socket.
+struct UdpAgent {
+    socket: UdpSocket,
+    target: String,
+    community: String,
+    request_id: i32,
+}
 
-        let get_request: GetBulkRequest = GetBulkRequest(pdu);
+impl UdpAgent {
+    /// # Errors
+    /// Returns an error if the socket cannot be opened to `target`.
+    fn new(target: &str, community: &str) -> Result<UdpAgent> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(target)?;
+        let duration = std::time::Duration::from_millis(1000);
+        socket.set_read_timeout(Some(duration))?;
+        Ok(UdpAgent {
+            socket,
+            target: target.to_string(),
+            community: community.to_string(),
+            request_id: 0,
+        })
+    }
 
-        let message: Message<GetBulkRequest> = Message {
+    /// Sends `data` and waits for the response.
+    fn exchange<T: rasn::Encode>(&mut self, data: T) -> Result<Message<Pdus>> {
+        self.send(data)?;
+        self.receive()
+    }
+
+    /// Sends `data` without waiting for the response.
+    fn send<T: rasn::Encode>(&mut self, data: T) -> Result<()> {
+        let message: Message<T> = Message {
             version: 1.into(),
-            community: community.to_string().into(),
-            data: get_request.into(),
+            community: self.community.clone().into(),
+            data,
         };
 
         // Send the message through an UDP socket
         let encoded: Vec<u8> = rasn::der::encode(&message).unwrap();
-        let res: usize = socket.send(&encoded).unwrap();
+        let res: usize = self.socket.send(&encoded)?;
         assert!(res == encoded.len());
+        Ok(())
+    }
 
-        let mut buf: [u8; 1024] = [0; 1024];
-        let resp: (usize, std::net::SocketAddr) = socket.recv_from(buf.as_mut_slice()).unwrap();
+    /// Waits for the next response.
+    ///
+    /// # Errors
+    /// Returns [`Error::NoResponse`] once the read timeout expires, and
+    /// [`Error::InvalidResponse`] if the datagram is not an SNMP message.
+    fn receive(&mut self) -> Result<Message<Pdus>> {
+        // Several columns per request need more than the usual 1024 bytes
+        let mut buf = vec![0u8; 65535];
+        let resp: (usize, std::net::SocketAddr) = match self.socket.recv_from(buf.as_mut_slice()) {
+            Ok(resp) => resp,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(Error::NoResponse {
+                    target: self.target.clone(),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         trace!("Received {} bytes", resp.0);
-        assert!(resp.0 > 0);
-        let decoded: Message<Pdus> = rasn::ber::decode(&buf[0..resp.0]).unwrap();
-        let completed = retval.build_response(decoded, &oid, snmp_name, true);
-
-        if completed {
-            break;
-        }
-        oid_tab = &retval.last_oid;
+        rasn::ber::decode(&buf[0..resp.0]).map_err(|e| Error::InvalidResponse {
+            target: self.target.clone(),
+            message: e.to_string(),
+        })
     }
-    retval
 }
 
-/// Walks a subtree and organizes results by label matches.
-///
-/// Used for tabular SNMP data where the last segment of an OID identifies
-/// the column (labeled in the `labels` map), and values are organized per label.
-///
-/// # Arguments
-/// * `target` - Target address in "host:port" format
-/// * `_version` - SNMP version (e.g., "2c")
-/// * `community` - SNMP community string
-/// * `oid` - The base OID to walk
-/// * `snmp_name` - Logical name prefix for collected values
-/// * `labels` - Map of label identifiers to logical names
-///
-/// # Returns
-/// An [`SnmpResult`] with values organized by label as separate vectors
-pub fn snmp_bulk_walk_with_labels<'a>(
-    target: &str,
-    _version: &str,
-    community: &str,
-    oid: &str,
-    snmp_name: &str,
-    labels: &'a HashMap<String, String>,
-) -> SnmpResult {
-    let oid_init = oid
-        .split('.')
-        .map(|x| x.parse::<u32>().unwrap())
-        .collect::<Vec<u32>>();
-
-    let mut oid_tab = &oid_init;
-    let mut retval = SnmpResult {
-        items: HashMap::new(),
-        last_oid: Vec::new(),
-    };
-    let request_id: i32 = 1;
-
-    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    socket.connect(target).expect("connect function failed");
-    let duration = std::time::Duration::from_millis(1000);
-    socket.set_read_timeout(Some(duration)).unwrap();
-
-    loop {
-        let variable_bindings = vec![VarBind {
-            name: ObjectIdentifier::new_unchecked(oid_tab.to_vec().into()),
-            value: VarBindValue::Unspecified,
-        }];
-
+impl Agent for UdpAgent {
+    fn get_bulk(
+        &mut self,
+        variable_bindings: Vec<VarBind>,
+        max_repetitions: u32,
+    ) -> Result<Message<Pdus>> {
+        self.request_id += 1;
         let pdu = BulkPdu {
-            request_id,
+            request_id: self.request_id,
             non_repeaters: 0,
-            max_repetitions: 10,
+            max_repetitions,
             variable_bindings,
         };
+        self.exchange(GetBulkRequest(pdu))
+    }
 
-        let get_request: GetBulkRequest = GetBulkRequest(pdu);
-
-        let message: Message<GetBulkRequest> = Message {
-            version: 1.into(),
-            community: community.to_string().into(),
-            data: get_request.into(),
+    fn get(&mut self, variable_bindings: Vec<VarBind>) -> Result<Message<Pdus>> {
+        self.request_id += 1;
+        let pdu = Pdu {
+            request_id: self.request_id,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings,
         };
-
-        // Send the message through an UDP socket
-        let encoded: Vec<u8> = rasn::der::encode(&message).unwrap();
-        let res: usize = socket.send(&encoded).unwrap();
-        assert!(res == encoded.len());
-
-        let mut buf: [u8; 1024] = [0; 1024];
-        let resp: (usize, std::net::SocketAddr) = socket.recv_from(buf.as_mut_slice()).unwrap();
-
-        trace!("Received {} bytes", resp.0);
-        assert!(resp.0 > 0);
-        let decoded: Message<Pdus> = rasn::ber::decode(&buf[0..resp.0]).unwrap();
-        let completed = retval.build_response_with_labels(decoded, &oid, snmp_name, labels, true);
-        if completed {
-            break;
-        }
-        oid_tab = &retval.last_oid;
+        self.exchange(GetRequest(pdu))
     }
-    retval
-}
 
-impl SnmpResult {
-    /// Parses an SNMP response and organizes values by label.
-    ///
-    /// # Arguments
-    /// * `decoded` - The decoded SNMP response message
-    /// * `oid` - The base OID (for walk termination detection)
-    /// * `snmp_name` - Prefix for result keys
-    /// * `labels` - Label map for splitting results
-    /// * `walk` - If true, returns true when subtree is exhausted
-    ///
-    /// # Returns
-    /// `true` if the walk should terminate (for walk operations)
-    fn build_response_with_labels<'a>(
+    fn get_and_get_bulk(
         &mut self,
-        decoded: Message<Pdus>,
-        oid: &str,
-        snmp_name: &str,
-        labels: &'a HashMap<String, String>,
-        walk: bool,
-    ) -> bool {
-        let mut completed = false;
-
-        if let Pdus::Response(resp) = &decoded.data {
-            let vars = &resp.0.variable_bindings;
-            for var in vars {
-                let name = var.name.to_string();
-                self.last_oid = name
-                    .split('.')
-                    .map(|x| x.parse::<u32>().unwrap())
-                    .collect::<Vec<u32>>();
-                if walk {
-                    if !name.starts_with(oid) {
-                        completed = true;
-                        break;
-                    }
-                }
-                let prefix = &name[..name.rfind('.').unwrap()];
-                for l in labels {
-                    if prefix.ends_with(l.0) {
-                        let mut typ = ValueType::None(());
-                        let value = match &var.value {
-                            VarBindValue::Unspecified => {
-                                warn!("Unspecified");
-                            }
-                            VarBindValue::NoSuchObject => {
-                                warn!("NoSuchObject");
-                            }
-                            VarBindValue::NoSuchInstance => {
-                                warn!("NoSuchInstance");
-                            }
-                            VarBindValue::EndOfMibView => {
-                                warn!("EndOfMibView");
-                            }
-                            VarBindValue::Value(value) => {
-                                warn!("Value {:?}", &value);
-                                match value {
-                                    rasn_smi::v2::ObjectSyntax::Simple(value) => {
-                                        info!("Simple {:?}", value);
-                                        match value {
-                                            rasn_smi::v2::SimpleSyntax::Integer(value) => {
-                                                typ = ValueType::Integer(value.try_into().unwrap());
-                                            }
-                                            rasn_smi::v2::SimpleSyntax::String(value) => {
-                                                // We transform the value into a rust String
-                                                typ = ValueType::String(
-                                                    String::from_utf8(value.to_vec()).unwrap(),
-                                                );
-                                            }
-                                            rasn_smi::v2::SimpleSyntax::ObjectId(value) => {
-                                                let oid: String = value
-                                                    .iter()
-                                                    .map(|&id| id.to_string() + ".")
-                                                    .collect();
-                                                typ = ValueType::String(oid);
-                                            }
-                                            _ => {
-                                                typ = ValueType::String("Other".to_string());
-                                            }
-                                        };
-                                    }
-                                    rasn_smi::v2::ObjectSyntax::ApplicationWide(value) => {
-                                        info!("Application {:?}", value);
-                                    }
-                                };
-                            }
-                        };
-                        let key = format!("{}.{}", snmp_name, l.1);
-                        self.items
-                            .entry(key)
-                            .and_modify(|e| match e {
-                                ExprResult::Number(_) => panic!("Should not arrive"),
-                                ExprResult::Str(_) => panic!("Should not arrive"),
-                                ExprResult::Vector(v) => v.push(match &typ {
-                                    ValueType::Float(f) => *f,
-                                    ValueType::None(()) => {
-                                        panic!("Should not arrive");
-                                    }
-                                    ValueType::String(_) => {
-                                        panic!("Value should be a float");
-                                    }
-                                    ValueType::Integer(i) => *i as f64,
-                                    ValueType::Counter64(i) => *i as f64,
-                                }),
-                                ExprResult::StrVector(v) => v.push(match &typ {
-                                    ValueType::Float(_) => {
-                                        panic!("Value should be a string");
-                                    }
-                                    ValueType::None(()) => {
-                                        panic!("Should not arrive");
-                                    }
-                                    ValueType::String(s) => s.to_string(),
-                                    ValueType::Integer(_) => panic!("Value should be a string"),
-                                    ValueType::Counter64(_) => panic!("Value should be a string"),
-                                }),
-                                ExprResult::Empty => {
-                                    panic!("Value from SNMP query cannot be empty");
-                                }
-                            })
-                            .or_insert(match typ {
-                                ValueType::Float(f) => ExprResult::Vector(vec![f]),
-                                ValueType::None(()) => panic!("Should not arrive"),
-                                ValueType::String(s) => ExprResult::StrVector(vec![s]),
-                                ValueType::Integer(i) => ExprResult::Vector(vec![i as f64]),
-                                ValueType::Counter64(i) => ExprResult::Vector(vec![i as f64]),
-                            });
-                    }
-                }
-            }
-        }
-        completed
+        get: Vec<VarBind>,
+        bulk: Vec<VarBind>,
+        max_repetitions: u32,
+    ) -> Result<(Message<Pdus>, Message<Pdus>)> {
+        let get_id = self.request_id + 1;
+        self.request_id += 2;
+        self.send(GetRequest(Pdu {
+            request_id: get_id,
+            error_status: Pdu::ERROR_STATUS_NO_ERROR,
+            error_index: 0,
+            variable_bindings: get,
+        }))?;
+        self.send(GetBulkRequest(BulkPdu {
+            request_id: self.request_id,
+            non_repeaters: 0,
+            max_repetitions,
+            variable_bindings: bulk,
+        }))?;
+        // The responses may come in any order
+        let first = self.receive()?;
+        let second = self.receive()?;
+        Ok(match &first.data {
+            Pdus::Response(resp) if resp.0.request_id == get_id => (first, second),
+            _ => (second, first),
+        })
     }
+}
 
-    /// Parses an SNMP response from a get request using provided names.
-    ///
-    /// # Arguments
-    /// * `decoded` - The decoded SNMP response message
-    /// * `oid` - The base OID (for walk termination detection)
-    /// * `names` - Names for each OID result
-    /// * `walk` - If true, returns true when subtree is exhausted
-    ///
-    /// # Returns
-    /// `true` if the walk should terminate (for walk operations)
-    fn build_response_with_names<'a>(
-        &mut self,
-        decoded: Message<Pdus>,
-        oid: &str,
-        names: &Vec<&str>,
-        walk: bool,
-    ) -> bool {
-        let mut completed = false;
-
-        if let Pdus::Response(resp) = &decoded.data {
-            let vars = &resp.0.variable_bindings;
-            for (idx, var) in vars.iter().enumerate() {
-                let name = var.name.to_string();
-                self.last_oid = name
-                    .split('.')
-                    .map(|x| x.parse::<u32>().unwrap())
-                    .collect::<Vec<u32>>();
-                if walk {
-                    if !name.starts_with(oid) {
-                        completed = true;
-                        break;
-                    }
-                }
-                let prefix: &str = &name[..name.rfind('.').unwrap()];
-                let mut typ = ValueType::None(());
-                let value = match &var.value {
-                    VarBindValue::Unspecified => {
-                        warn!("Unspecified");
-                    }
-                    VarBindValue::NoSuchObject => {
-                        warn!("NoSuchObject");
-                    }
-                    VarBindValue::NoSuchInstance => {
-                        warn!("NoSuchInstance");
+/// Walks the subtree of `oid` and only keeps the varbinds received, whatever
+/// their types, in [`SnmpResult::varbinds`].
+///
+/// # Errors
+/// Returns an error if the agent does not answer or answers with an error
+/// status.
+pub fn snmp_walk_varbinds(
+    target: &str,
+    _version: &str,
+    community: &str,
+    oid: &str,
+) -> Result<SnmpResult> {
+    let mut retval = SnmpResult::new(HashMap::new());
+    let Some(oid) = parse_oid(oid) else {
+        warn!("Invalid OID '{}' not walked", oid);
+        return Ok(retval);
+    };
+    let mut columns = [Column::new(oid, None)];
+    let mut agent = UdpAgent::new(target, community)?;
+    walk_columns(&mut agent, &mut columns, &mut retval)?;
+    Ok(retval)
+}
+
+impl SnmpResult {
+    /// Appends a value to the vector named `key`.
+    fn push_value(&mut self, key: &str, typ: ValueType) {
+        self.items
+            .entry(key.to_string())
+            .and_modify(|e| match e {
+                ExprResult::Number(_) => panic!("Should not arrive"),
+                ExprResult::Str(_) => panic!("Should not arrive"),
+                ExprResult::Vector(v) => v.push(match &typ {
+                    ValueType::Float(f) => *f,
+                    ValueType::None(()) => {
+                        panic!("Should not arrive");
                     }
-                    VarBindValue::EndOfMibView => {
-                        warn!("EndOfMibView");
+                    ValueType::String(_) => {
+                        panic!("Value should be a float");
                     }
-                    VarBindValue::Value(value) => {
-                        warn!("Value {:?}", &value);
-                        match value {
-                            rasn_smi::v2::ObjectSyntax::Simple(value) => {
-                                info!("Simple {:?}", value);
-                                match value {
-                                    rasn_smi::v2::SimpleSyntax::Integer(value) => {
-                                        typ = ValueType::Integer(value.try_into().unwrap());
-                                    }
-                                    rasn_smi::v2::SimpleSyntax::String(value) => {
-                                        // We transform the value into a rust String
-                                        typ = ValueType::String(
-                                            String::from_utf8(value.to_vec()).unwrap(),
-                                        );
-                                    }
-                                    rasn_smi::v2::SimpleSyntax::ObjectId(value) => {
-                                        let oid: String =
-                                            value.iter().map(|&id| id.to_string() + ".").collect();
-                                        typ = ValueType::String(oid);
-                                    }
-                                    _ => {
-                                        typ = ValueType::String("Other".to_string());
-                                    }
-                                }
-                            }
-                            rasn_smi::v2::ObjectSyntax::ApplicationWide(value) => {
-                                info!("ApplicationWide {:?}", value);
-                                match value {
-                                    rasn_smi::v2::ApplicationSyntax::Address(ip_address) => todo!(),
-                                    rasn_smi::v2::ApplicationSyntax::Counter(counter) => todo!(),
-                                    rasn_smi::v2::ApplicationSyntax::Ticks(time_ticks) => {
-                                        typ = ValueType::Integer(time_ticks.0.into());
-                                    }
-                                    rasn_smi::v2::ApplicationSyntax::Arbitrary(opaque) => todo!(),
-                                    rasn_smi::v2::ApplicationSyntax::BigCounter(counter64) => {
-                                        typ = ValueType::Counter64(counter64.0);
-                                    }
-                                    rasn_smi::v2::ApplicationSyntax::Unsigned(gauge) => todo!(),
-                                }
-                            }
-                            _ => {
-                                info!("other");
-                            }
-                        }
+                    ValueType::Integer(i) => *i as f64,
+                    ValueType::Counter64(i) => *i as f64,
+                }),
+                ExprResult::StrVector(v) => v.push(match &typ {
+                    ValueType::Float(_) => {
+                        panic!("Value should be a string");
                     }
-                };
-                let key = format!("{}", names[idx]);
-                self.items
-                    .entry(key)
-                    .and_modify(|e| match e {
-                        ExprResult::Number(_) => panic!("Should not arrive"),
-                        ExprResult::Vector(v) => v.push(match &typ {
-                            ValueType::Float(f) => *f,
-                            ValueType::None(()) => {
-                                panic!("Should not arrive");
-                            }
-                            ValueType::String(_) => {
-                                panic!("Value should be a float");
-                            }
-                            ValueType::Integer(i) => *i as f64,
-                            ValueType::Counter64(i) => *i as f64,
-                        }),
-                        ExprResult::Str(_) => panic!("Should not arrive"),
-                        ExprResult::StrVector(v) => v.push(match &typ {
-                            ValueType::Float(_) => {
-                                panic!("Value should be a string");
-                            }
-                            ValueType::None(()) => {
-                                panic!("Should not arrive");
-                            }
-                            ValueType::String(s) => s.to_string(),
-                            ValueType::Integer(_) => {
-                                panic!("Value should be a string");
-                            }
-                            ValueType::Counter64(_) => panic!("Value should be a string"),
-                        }),
-                        ExprResult::Empty => {
-                            panic!("Value should not be empty");
-                        }
-                    })
-                    .or_insert(match typ {
-                        ValueType::Float(f) => ExprResult::Vector(vec![f]),
-                        ValueType::None(()) => panic!("Should not arrive"),
-                        ValueType::String(s) => ExprResult::StrVector(vec![s]),
-                        ValueType::Integer(i) => ExprResult::Vector(vec![i as f64]),
-                        ValueType::Counter64(i) => ExprResult::Vector(vec![i as f64]),
-                    });
-            }
-        }
-        completed
-    }
-    /// Parses an SNMP response and stores values under a single logical name.
-    ///
-    /// # Arguments
-    /// * `decoded` - The decoded SNMP response message
-    /// * `oid` - The base OID (for walk termination detection)
-    /// * `snmp_name` - Logical name for collected values
-    /// * `walk` - If true, returns true when subtree is exhausted
-    ///
-    /// # Returns
-    /// `true` if the walk should terminate (for walk operations)
-    fn build_response<'a>(
-        &mut self,
-        decoded: Message<Pdus>,
-        oid: &str,
-        snmp_name: &str,
-        walk: bool,
-    ) -> bool {
-        let mut completed = false;
-
-        if let Pdus::Response(resp) = &decoded.data {
-            let vars = &resp.0.variable_bindings;
-            for var in vars {
-                let name = var.name.to_string();
-                self.last_oid = name
-                    .split('.')
-                    .map(|x| x.parse::<u32>().unwrap())
-                    .collect::<Vec<u32>>();
-                if walk {
-                    if !name.starts_with(oid) {
-                        completed = true;
-                        break;
+                    ValueType::None(()) => {
+                        panic!("Should not arrive");
                     }
+                    ValueType::String(s) => s.to_string(),
+                    ValueType::Integer(_) => panic!("Value should be a string"),
+                    ValueType::Counter64(_) => panic!("Value should be a string"),
+                }),
+                ExprResult::Empty => {
+                    panic!("Value from SNMP query cannot be empty");
                 }
-                let prefix: &str = &name[..name.rfind('.').unwrap()];
-                let mut typ = ValueType::None(());
-                let value = match &var.value {
-                    VarBindValue::Unspecified => {
-                        warn!("Unspecified");
-                    }
-                    VarBindValue::NoSuchObject => {
-                        warn!("NoSuchObject");
-                    }
-                    VarBindValue::NoSuchInstance => {
-                        warn!("NoSuchInstance");
-                    }
-                    VarBindValue::EndOfMibView => {
-                        warn!("EndOfMibView");
-                    }
-                    VarBindValue::Value(value) => {
-                        warn!("Value {:?}", &value);
-                        match value {
-                            rasn_smi::v2::ObjectSyntax::Simple(value) => {
-                                info!("Simple {:?}", value);
-                                match value {
-                                    rasn_smi::v2::SimpleSyntax::Integer(value) => {
-                                        typ = ValueType::Integer(value.try_into().unwrap());
-                                    }
-                                    rasn_smi::v2::SimpleSyntax::String(value) => {
-                                        // We transform the value into a rust String
-                                        typ = ValueType::String(
-                                            String::from_utf8(value.to_vec()).unwrap(),
-                                        );
-                                    }
-                                    rasn_smi::v2::SimpleSyntax::ObjectId(value) => {
-                                        let oid: String =
-                                            value.iter().map(|&id| id.to_string() + ".").collect();
-                                        typ = ValueType::String(oid);
-                                    }
-                                    _ => {
-                                        typ = ValueType::String("Other".to_string());
-                                    }
-                                }
-                            }
-                            _ => {
-                                info!("other");
-                            }
-                        }
-                        //match value {
-                        //    rasn_smi::v2::ObjectSyntax::Simple(value) => {
-                        //        info!("Simple {:?}", value);
-                        //        match value {
-                        //            rasn_smi::v2::ObjectSyntax::Simple(value) => {
-                        //                info!("Simple {:?}", value);
-                        //                match value {
-                        //                    rasn_smi::v2::SimpleSyntax::Integer(value) => {
-                        //                        typ = ValueType::Integer(value.try_into().unwrap());
-                        //                    }
-                        //                    rasn_smi::v2::SimpleSyntax::String(value) => {
-                        //                        // We transform the value into a rust String
-                        //                        typ = ValueType::String(
-                        //                            String::from_utf8(value.to_vec()).unwrap(),
-                        //                        );
-                        //                    }
-                        //                    rasn_smi::v2::SimpleSyntax::ObjectId(value) => {
-                        //                        let oid: String = value
-                        //                            .iter()
-                        //                            .map(|&id| id.to_string() + ".")
-                        //                            .collect();
-                        //                        typ = ValueType::String(oid);
-                        //                    }
-                        //                    _ => {
-                        //                        typ = ValueType::String("Other".to_string());
-                        //                    }
-                        //                };
-                        //            }
-                        //            rasn_smi::v2::ObjectSyntax::ApplicationWide(value) => {
-                        //                info!("Application {:?}", value);
-                        //            }
-                        //        };
-                        //    }
-                        //}
-                    }
-                };
-                let key = format!("{}", snmp_name);
-                self.items
-                    .entry(key)
-                    .and_modify(|e| match e {
-                        ExprResult::Number(_) => panic!("Should not arrive"),
-                        ExprResult::Vector(v) => v.push(match &typ {
-                            ValueType::Float(f) => *f,
-                            ValueType::None(()) => {
-                                panic!("Should not arrive");
-                            }
-                            ValueType::String(_) => {
-                                panic!("Value should be a float");
-                            }
-                            ValueType::Integer(i) => *i as f64,
-                            ValueType::Counter64(i) => *i as f64,
-                        }),
-                        ExprResult::Str(_) => panic!("Should not arrive"),
-                        ExprResult::StrVector(v) => v.push(match &typ {
-                            ValueType::Float(_) => {
-                                panic!("Value should be a string");
-                            }
-                            ValueType::None(()) => {
-                                panic!("Should not arrive");
-                            }
-                            ValueType::String(s) => s.to_string(),
-                            ValueType::Integer(_) | ValueType::Counter64(_) => {
-                                panic!("Value should be a string");
-                            }
-                        }),
-                        ExprResult::Empty => {
-                            panic!("Value should be a string");
-                        }
-                    })
-                    .or_insert(match typ {
-                        ValueType::Float(f) => ExprResult::Vector(vec![f]),
-                        ValueType::None(()) => {
-                            panic!("Should not arrive");
-                        }
-                        ValueType::String(s) => ExprResult::StrVector(vec![s]),
-                        ValueType::Integer(i) => ExprResult::Vector(vec![i as f64]),
-                        ValueType::Counter64(i) => ExprResult::Vector(vec![i as f64]),
-                    });
-            }
-        }
-        completed
+            })
+            .or_insert(match typ {
+                ValueType::Float(f) => ExprResult::Vector(vec![f]),
+                ValueType::None(()) => panic!("Should not arrive"),
+                ValueType::String(s) => ExprResult::StrVector(vec![s]),
+                ValueType::Integer(i) => ExprResult::Vector(vec![i as f64]),
+                ValueType::Counter64(i) => ExprResult::Vector(vec![i as f64]),
+            });
     }
 }
 
@@ -0,0 +1,962 @@
+//! Planning of the SNMP requests of a whole collection.
+//!
+//! Instead of one exchange per entry, the queries of a definition share as
+//! few round trips as possible: the scalars of the `Get` entries are packed
+//! into `GetRequest`s, and the columns of every `Walk` entry are walked in
+//! parallel by the same `GetBulkRequest`s. The first `GetRequest` is sent
+//! along with the first `GetBulkRequest`, so that they share a round trip.
+//! No request carries more than [`MAX_VARBINDS`] varbinds, and all of them
+//! go through the same socket.
+//!
+//! With a concurrency above 1, the entries are rather collected
+//! independently by several sockets at once: the `Get` entries together,
+//! and each `Walk` entry on its own.
+
+use super::{
+    Agent, Column, MAX_VARBINDS, Scalar, SnmpResult, UdpAgent, ValueType, error_name, index_of,
+    label_columns, parse_oid, var_type, walk_columns,
+};
+use crate::generic::error::{Error, Result};
+use log::{trace, warn};
+use rasn::types::ObjectIdentifier;
+use rasn_snmp::v2::{Pdu, Pdus, VarBind, VarBindValue};
+use rasn_snmp::v2c::Message;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A function keeping the instances of a filtered table by name, shared by
+/// the threads of a concurrent collection.
+pub type Keep<'a> = &'a (dyn Fn(&str) -> bool + Sync);
+
+/// A query of the collect phase.
+#[derive(Clone, Copy)]
+pub enum Query<'a> {
+    /// A scalar, stored under `name`.
+    Get { oid: &'a str, name: &'a str },
+    /// A subtree, stored under `name`, or the columns of a table named by
+    /// `labels`, stored under `<name>.<label>`.
+    Walk {
+        oid: &'a str,
+        name: &'a str,
+        labels: Option<&'a HashMap<String, String>>,
+        /// The label of the column naming the instances and the function
+        /// keeping them: only that column is walked, the other ones are
+        /// fetched with `GetRequest`s for the instances kept.
+        filter: Option<(&'a str, Keep<'a>)>,
+    },
+}
+
+/// A table whose instances are filtered before the collection of their
+/// columns.
+struct Filtered<'a> {
+    /// Name under which the column naming the instances is stored.
+    key: String,
+    /// OID of that column, walked without storing its values.
+    oid: Vec<u32>,
+    /// The other columns, fetched for each instance kept.
+    others: Vec<Column>,
+    keep: Keep<'a>,
+}
+
+/// An instance kept by a [`Filtered`] table, whose columns are requested
+/// from `start` in the `GetRequest`s.
+struct Row {
+    /// The variable binding naming the instance.
+    var: VarBind,
+    index: String,
+    /// Names of the values: the naming column, then the other columns.
+    keys: Vec<String>,
+    start: usize,
+}
+
+/// Returns the value of `var` as the name of an instance.
+fn instance_name(var: &VarBind) -> Option<String> {
+    match var_type(var) {
+        ValueType::String(s) => Some(s),
+        ValueType::Integer(i) => Some(i.to_string()),
+        ValueType::Counter64(i) => Some(i.to_string()),
+        ValueType::Float(f) => Some(f.to_string()),
+        ValueType::None(()) => None,
+    }
+}
+
+/// Runs all the `queries` through `agent` and returns their values.
+///
+/// # Errors
+/// Returns an error if the agent does not answer, or answers with an error
+/// status.
+pub(super) fn collect(agent: &mut impl Agent, queries: &[Query]) -> Result<SnmpResult> {
+    let mut scalars = Vec::new();
+    let mut columns = Vec::new();
+    let mut filtered = Vec::new();
+    for query in queries {
+        match query {
+            Query::Get { oid, name } => match Scalar::new(oid, name) {
+                Some(scalar) => scalars.push(scalar),
+                None => warn!("Invalid OID '{}' of '{}' not collected", oid, name),
+            },
+            Query::Walk {
+                oid,
+                name,
+                labels: Some(labels),
+                filter: Some((label, keep)),
+            } => {
+                let key = format!("{}.{}", name, label);
+                let (names, others): (Vec<Column>, Vec<Column>) = label_columns(oid, name, labels)
+                    .into_iter()
+                    .partition(|column| column.key.as_deref() == Some(key.as_str()));
+                if let Some(column) = names.into_iter().next() {
+                    columns.push(Column::new(column.oid.clone(), None));
+                    filtered.push(Filtered {
+                        key,
+                        oid: column.oid,
+                        others,
+                        keep: *keep,
+                    });
+                }
+            }
+            Query::Walk {
+                oid,
+                name,
+                labels: Some(labels),
+                ..
+            } => columns.extend(label_columns(oid, name, labels)),
+            Query::Walk { oid, name, .. } => match parse_oid(oid) {
+                Some(oid) => columns.push(Column::new(oid, Some(name.to_string()))),
+                None => warn!("Invalid OID '{}' of '{}' not collected", oid, name),
+            },
+        }
+    }
+
+    let mut result = SnmpResult::new(HashMap::new());
+    let mut first = None;
+    if !columns.is_empty() {
+        let mut agent = Piggyback {
+            agent: &mut *agent,
+            get: scalars.chunks(MAX_VARBINDS).next().map(get_request),
+            response: None,
+        };
+        walk_columns(&mut agent, &mut columns, &mut result)?;
+        first = agent.response;
+    }
+    get_scalars(agent, first, &scalars, &mut result)?;
+    if !filtered.is_empty() {
+        fetch_instances(agent, &filtered, &mut result)?;
+    }
+    Ok(result)
+}
+
+/// An agent sending a pending `GetRequest` along with its first
+/// `GetBulkRequest`, and keeping the response for later.
+struct Piggyback<'a, A: Agent> {
+    agent: &'a mut A,
+    get: Option<Vec<VarBind>>,
+    response: Option<Message<Pdus>>,
+}
+
+impl<A: Agent> Agent for Piggyback<'_, A> {
+    fn get_bulk(&mut self, request: Vec<VarBind>, max_repetitions: u32) -> Result<Message<Pdus>> {
+        match self.get.take() {
+            Some(get) => {
+                let (response, bulk) =
+                    self.agent.get_and_get_bulk(get, request, max_repetitions)?;
+                self.response = Some(response);
+                Ok(bulk)
+            }
+            None => self.agent.get_bulk(request, max_repetitions),
+        }
+    }
+
+    fn get(&mut self, request: Vec<VarBind>) -> Result<Message<Pdus>> {
+        self.agent.get(request)
+    }
+}
+
+/// Returns the varbinds of a `GetRequest` for `scalars`.
+fn get_request(scalars: &[Scalar]) -> Vec<VarBind> {
+    scalars
+        .iter()
+        .map(|scalar| VarBind {
+            name: ObjectIdentifier::new_unchecked(scalar.oid.clone().into()),
+            value: VarBindValue::Unspecified,
+        })
+        .collect()
+}
+
+/// Sends a `GetRequest` for `request` and returns the variable bindings of
+/// the response, one per requested OID, in the same order.
+///
+/// # Errors
+/// See [`check_get`].
+fn get(agent: &mut impl Agent, request: &[VarBind]) -> Result<Vec<VarBind>> {
+    let response = agent.get(request.to_vec())?;
+    check_get(agent, request, response)
+}
+
+/// Returns the variable bindings of the `response` to the `GetRequest` for
+/// `request`. A `tooBig` response is retried as two requests of half the
+/// OIDs.
+///
+/// # Errors
+/// Returns an error if the agent does not answer, answers with any other
+/// error status, with `tooBig` to a single OID, or with other variable
+/// bindings than requested.
+fn check_get(
+    agent: &mut impl Agent,
+    request: &[VarBind],
+    response: Message<Pdus>,
+) -> Result<Vec<VarBind>> {
+    let Pdus::Response(resp) = response.data else {
+        return Err(Error::MismatchedResponse {
+            oid: request[0].name.to_string(),
+        });
+    };
+    let status = resp.0.error_status;
+    if status == Pdu::ERROR_STATUS_TOO_BIG && request.len() > 1 {
+        trace!(
+            "Response too big, splitting a GetRequest of {} OIDs",
+            request.len()
+        );
+        let (head, tail) = request.split_at(request.len() / 2);
+        let mut vars = get(agent, head)?;
+        vars.extend(get(agent, tail)?);
+        return Ok(vars);
+    }
+    if status != Pdu::ERROR_STATUS_NO_ERROR {
+        // The error index, from 1, points at the faulty OID if any
+        let faulty = (resp.0.error_index as usize).saturating_sub(1);
+        let var = request.get(faulty).unwrap_or(&request[0]);
+        return Err(Error::AgentError {
+            status,
+            name: error_name(status).to_string(),
+            oid: var.name.to_string(),
+        });
+    }
+    // A shorter response would leave the last OIDs without a value
+    let vars = resp.0.variable_bindings;
+    if let Some(k) = (0..request.len().max(vars.len()))
+        .find(|&k| vars.get(k).map(|var| &var.name) != request.get(k).map(|var| &var.name))
+    {
+        let var = request.get(k).unwrap_or(&request[0]);
+        return Err(Error::MismatchedResponse {
+            oid: var.name.to_string(),
+        });
+    }
+    Ok(vars)
+}
+
+/// Stores the values of the `scalars`, requested by `GetRequest`s of at
+/// most [`MAX_VARBINDS`] OIDs. The response to the first one may have been
+/// received already, as `first`.
+///
+/// A scalar the agent has no value for, `noSuchObject` or
+/// `noSuchInstance`, only gets its variable binding in `result`.
+///
+/// # Errors
+/// See [`check_get`].
+fn get_scalars(
+    agent: &mut impl Agent,
+    mut first: Option<Message<Pdus>>,
+    scalars: &[Scalar],
+    result: &mut SnmpResult,
+) -> Result<()> {
+    for chunk in scalars.chunks(MAX_VARBINDS) {
+        let request = get_request(chunk);
+        let vars = match first.take() {
+            Some(response) => check_get(agent, &request, response)?,
+            None => get(agent, &request)?,
+        };
+        for (scalar, var) in chunk.iter().zip(vars) {
+            let typ = var_type(&var);
+            result.varbinds.push(var);
+            if !matches!(typ, ValueType::None(())) {
+                result.push_value(&scalar.key, typ);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the columns of the instances of the `filtered` tables kept by
+/// their filters, packed into as few `GetRequest`s as possible.
+///
+/// The columns naming the instances must have been walked into `result`.
+///
+/// # Errors
+/// See [`check_get`]: an instance is never dropped because its response
+/// was truncated.
+fn fetch_instances(
+    agent: &mut impl Agent,
+    filtered: &[Filtered],
+    result: &mut SnmpResult,
+) -> Result<()> {
+    let mut rows = Vec::new();
+    let mut request = Vec::new();
+    for table in filtered {
+        let (walked, varbinds) = std::mem::take(&mut result.varbinds)
+            .into_iter()
+            .partition(|var| var.name.starts_with(&table.oid));
+        result.varbinds = varbinds;
+        for var in walked {
+            let Some(instance) = instance_name(&var) else {
+                continue;
+            };
+            if !(table.keep)(&instance) {
+                trace!("Instance '{}' filtered out before its collection", instance);
+                continue;
+            }
+            let name: Vec<u32> = var.name.iter().copied().collect();
+            let suffix = &name[table.oid.len()..];
+            let mut keys = vec![table.key.clone()];
+            keys.extend(table.others.iter().filter_map(|c| c.key.clone()));
+            rows.push(Row {
+                index: index_of(&name, &table.oid),
+                var,
+                keys,
+                start: request.len(),
+            });
+            request.extend(table.others.iter().map(|other| VarBind {
+                name: ObjectIdentifier::new_unchecked([&other.oid[..], suffix].concat().into()),
+                value: VarBindValue::Unspecified,
+            }));
+        }
+    }
+
+    let mut values = Vec::with_capacity(request.len());
+    for chunk in request.chunks(MAX_VARBINDS) {
+        values.extend(get(agent, chunk)?);
+    }
+
+    for row in rows {
+        let vars = &values[row.start..row.start + row.keys.len() - 1];
+        let types: Vec<ValueType> = vars.iter().map(var_type).collect();
+        if types.iter().any(|t| matches!(t, ValueType::None(()))) {
+            warn!(
+                "Instance '{}' skipped: some of its columns are missing",
+                row.index
+            );
+            continue;
+        }
+        let typ = var_type(&row.var);
+        result.varbinds.push(row.var);
+        result.varbinds.extend(vars.iter().cloned());
+        for (key, typ) in row.keys.iter().zip(std::iter::once(typ).chain(types)) {
+            result
+                .indexes
+                .entry(key.clone())
+                .or_default()
+                .push(row.index.clone());
+            result.push_value(key, typ);
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `queries` through at most `concurrency` agents made by
+/// `new_agent`, each one collecting a unit at a time: all the `Get`
+/// entries, or a single `Walk` entry.
+///
+/// The results are merged in the order of the units, whatever the order in
+/// which they complete. At a concurrency of 1, all the queries share the
+/// requests of a single agent, as with [`collect`].
+///
+/// # Errors
+/// Returns an error if an agent cannot be made, else the error of the first
+/// unit that failed.
+pub(super) fn collect_concurrently<A: Agent + Send>(
+    new_agent: impl Fn() -> Result<A>,
+    queries: &[Query],
+    concurrency: usize,
+) -> Result<SnmpResult> {
+    let mut units: Vec<Vec<Query>> = Vec::new();
+    let gets: Vec<Query> = queries
+        .iter()
+        .filter(|query| matches!(query, Query::Get { .. }))
+        .copied()
+        .collect();
+    if !gets.is_empty() {
+        units.push(gets);
+    }
+    units.extend(
+        queries
+            .iter()
+            .filter(|query| matches!(query, Query::Walk { .. }))
+            .map(|query| vec![*query]),
+    );
+    let workers = concurrency.min(units.len());
+    if workers <= 1 {
+        return collect(&mut new_agent()?, queries);
+    }
+
+    trace!("Collecting {} units with {} agents", units.len(), workers);
+    let agents = (0..workers)
+        .map(|_| new_agent())
+        .collect::<Result<Vec<A>>>()?;
+    let next = AtomicUsize::new(0);
+    let mut done: Vec<(usize, Result<SnmpResult>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = agents
+            .into_iter()
+            .map(|mut agent| {
+                let (units, next) = (&units, &next);
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(unit) = units.get(i) else {
+                            break done;
+                        };
+                        done.push((i, collect(&mut agent, unit)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("SNMP collect thread panicked"))
+            .collect()
+    });
+    done.sort_by_key(|(i, _)| *i);
+
+    let mut result = SnmpResult::new(HashMap::new());
+    for (_, unit) in done {
+        let unit = unit?;
+        result.items.extend(unit.items);
+        result.indexes.extend(unit.indexes);
+        result.varbinds.extend(unit.varbinds);
+    }
+    Ok(result)
+}
+
+/// Runs all the `queries` of a collection against `target`.
+///
+/// At a `concurrency` of 1, they go through a single socket, in as few
+/// requests as possible. Above, up to `concurrency` sockets collect the
+/// entries independently, so that the collection lasts about as long as
+/// its slowest walk.
+///
+/// # Arguments
+/// * `target` - Target address in "host:port" format
+/// * `_version` - SNMP version (e.g., "2c")
+/// * `community` - SNMP community string
+/// * `queries` - The queries of the collection
+/// * `concurrency` - Maximum number of entries collected at once
+///
+/// # Returns
+/// An [`SnmpResult`] holding the values of all the queries
+///
+/// # Errors
+/// Returns an error if the agent does not answer, answers with an invalid
+/// message, or answers with an error status.
+pub fn snmp_collect(
+    target: &str,
+    _version: &str,
+    community: &str,
+    queries: &[Query],
+    concurrency: usize,
+) -> Result<SnmpResult> {
+    collect_concurrently(|| UdpAgent::new(target, community), queries, concurrency)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_collect() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::error::Result;
+        use crate::snmp::planner::{Query, collect};
+        use crate::snmp::snmpwalk::SnmpWalk;
+        use crate::snmp::{Agent, MAX_VARBINDS};
+        use rasn_snmp::v2::{Pdus, VarBind};
+        use rasn_snmp::v2c::Message;
+        use std::collections::HashMap;
+
+        /// Counts the varbinds of the requests sent to a replayed walk, per
+        /// round trip.
+        struct Counter<'a> {
+            walk: &'a SnmpWalk,
+            requests: Vec<usize>,
+        }
+        impl Agent for Counter<'_> {
+            fn get_bulk(
+                &mut self,
+                request: Vec<VarBind>,
+                max_repetitions: u32,
+            ) -> Result<Message<Pdus>> {
+                self.requests.push(request.len());
+                Agent::get_bulk(&mut self.walk, request, max_repetitions)
+            }
+            fn get(&mut self, request: Vec<VarBind>) -> Result<Message<Pdus>> {
+                self.requests.push(request.len());
+                Agent::get(&mut self.walk, request)
+            }
+            fn get_and_get_bulk(
+                &mut self,
+                get: Vec<VarBind>,
+                bulk: Vec<VarBind>,
+                max_repetitions: u32,
+            ) -> Result<(Message<Pdus>, Message<Pdus>)> {
+                self.requests.push(get.len() + bulk.len());
+                Agent::get_and_get_bulk(&mut self.walk, get, bulk, max_repetitions)
+            }
+        }
+
+        let mut text = String::from(
+            r#".1.3.6.1.2.1.1.3.0 = Timeticks: (500) 0:00:05.00
+.1.3.6.1.2.1.1.5.0 = STRING: "host"
+.1.3.6.1.2.1.2.2.1.2.1 = STRING: "lo"
+.1.3.6.1.2.1.2.2.1.2.2 = STRING: "eth0"
+.1.3.6.1.2.1.2.2.1.2.3 = STRING: "eth1"
+.1.3.6.1.2.1.2.2.1.8.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.8.2 = INTEGER: 2
+.1.3.6.1.2.1.2.2.1.10.2 = Counter32: 7
+.1.3.6.1.2.1.2.2.1.10.3 = Counter32: 9
+.1.3.6.1.2.1.25.2.3.1.3.1 = STRING: "/"
+.1.3.6.1.2.1.25.2.3.1.6.1 = INTEGER: 42
+.1.3.6.1.4.1.9.9.0.1.0 = INTEGER: 3
+"#,
+        );
+        for i in 1..=40 {
+            text.push_str(&format!(".1.3.6.1.4.1.99.{}.0 = INTEGER: {}\n", i, i));
+        }
+        let walk = SnmpWalk::parse(&text).unwrap();
+        let labels = HashMap::from([
+            (".2".to_string(), "descr".to_string()),
+            (".8".to_string(), "status".to_string()),
+            (".10".to_string(), "in".to_string()),
+        ]);
+        let disk = HashMap::from([
+            (".3".to_string(), "label".to_string()),
+            (".6".to_string(), "used".to_string()),
+        ]);
+        let names: Vec<String> = (1..=40).map(|i| format!("s{}", i)).collect();
+        let oids: Vec<String> = (1..=40)
+            .map(|i| format!("1.3.6.1.4.1.99.{}.0", i))
+            .collect();
+
+        // The scalars in a GetRequest and two tables in a single
+        // GetBulkRequest, sent in the same round trip
+        let mut agent = Counter {
+            walk: &walk,
+            requests: vec![],
+        };
+        let queries = [
+            Query::Get {
+                oid: ".1.3.6.1.2.1.1.3.0",
+                name: "uptime",
+            },
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.5.0",
+                name: "sysname",
+            },
+            Query::Get {
+                oid: "1.3.6.1.4.1.9.9.0.1.0",
+                name: "zero",
+            },
+            Query::Walk {
+                oid: "1.3.6.1.2.1.2.2.1",
+                name: "if",
+                labels: Some(&labels),
+                filter: None,
+            },
+            Query::Walk {
+                oid: "1.3.6.1.2.1.25.2.3.1",
+                name: "disk",
+                labels: Some(&disk),
+                filter: None,
+            },
+        ];
+        let result = collect(&mut agent, &queries).unwrap();
+        assert_eq!(agent.requests, vec![3 + 5]);
+        assert!(matches!(&result.items["uptime"], ExprResult::Vector(v) if v == &[500.0]));
+        assert!(matches!(&result.items["sysname"], ExprResult::StrVector(v) if v == &["host"]));
+        assert!(matches!(&result.items["zero"], ExprResult::Vector(v) if v == &[3.0]));
+        assert!(
+            matches!(&result.items["if.descr"], ExprResult::StrVector(v) if v == &["lo", "eth0", "eth1"])
+        );
+        assert_eq!(result.indexes["if.in"], vec!["2", "3"]);
+        assert!(matches!(&result.items["disk.used"], ExprResult::Vector(v) if v == &[42.0]));
+
+        // Long lists of OIDs are split across PDUs
+        let mut agent = Counter {
+            walk: &walk,
+            requests: vec![],
+        };
+        let mut queries: Vec<Query> = oids
+            .iter()
+            .zip(names.iter())
+            .map(|(oid, name)| Query::Get { oid, name })
+            .collect();
+        queries.push(Query::Walk {
+            oid: "1.3.6.1.2.1.2.2.1",
+            name: "if",
+            labels: Some(&labels),
+            filter: None,
+        });
+        let result = collect(&mut agent, &queries).unwrap();
+        assert_eq!(agent.requests, vec![MAX_VARBINDS + 3, 8]);
+        assert!(matches!(&result.items["s40"], ExprResult::Vector(v) if v == &[40.0]));
+        assert!(matches!(&result.items["if.status"], ExprResult::Vector(v) if v == &[1.0, 2.0]));
+
+        // Only the column naming the instances is walked, then the columns
+        // of the instances kept are fetched by a single GetRequest
+        let mut agent = Counter {
+            walk: &walk,
+            requests: vec![],
+        };
+        let keep = |name: &str| name.starts_with("eth");
+        let queries = [
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.3.0",
+                name: "uptime",
+            },
+            Query::Walk {
+                oid: "1.3.6.1.2.1.2.2.1",
+                name: "if",
+                labels: Some(&labels),
+                filter: Some(("descr", &keep)),
+            },
+        ];
+        let result = collect(&mut agent, &queries).unwrap();
+        assert_eq!(agent.requests, vec![1 + 1, 4]);
+        // eth1 has no status and is skipped
+        assert!(matches!(&result.items["if.descr"], ExprResult::StrVector(v) if v == &["eth0"]));
+        assert!(matches!(&result.items["if.status"], ExprResult::Vector(v) if v == &[2.0]));
+        assert!(matches!(&result.items["if.in"], ExprResult::Vector(v) if v == &[7.0]));
+        assert_eq!(result.indexes["if.in"], vec!["2"]);
+        assert_eq!(result.varbinds.len(), 4);
+    }
+
+    #[test]
+    fn test_get_errors() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::error::{Error, Result};
+        use crate::snmp::Agent;
+        use crate::snmp::planner::{Query, collect};
+        use crate::snmp::snmpwalk::SnmpWalk;
+        use rasn_snmp::v2::{Pdu, Pdus, VarBind};
+        use rasn_snmp::v2c::Message;
+        use std::collections::HashMap;
+
+        /// An agent answering `GetRequest`s of more than `limit` OIDs with
+        /// `status`, or truncated if there is no error.
+        struct Faulty<'a> {
+            walk: &'a SnmpWalk,
+            limit: usize,
+            status: u32,
+            requests: Vec<usize>,
+        }
+        impl Agent for Faulty<'_> {
+            fn get_bulk(
+                &mut self,
+                request: Vec<VarBind>,
+                max_repetitions: u32,
+            ) -> Result<Message<Pdus>> {
+                Agent::get_bulk(&mut self.walk, request, max_repetitions)
+            }
+            fn get(&mut self, request: Vec<VarBind>) -> Result<Message<Pdus>> {
+                self.requests.push(request.len());
+                let mut message = Agent::get(&mut self.walk, request)?;
+                if let Pdus::Response(resp) = &mut message.data
+                    && resp.0.variable_bindings.len() > self.limit
+                {
+                    if self.status == Pdu::ERROR_STATUS_NO_ERROR {
+                        resp.0.variable_bindings.truncate(self.limit);
+                    } else {
+                        resp.0.error_status = self.status;
+                        resp.0.error_index = 2;
+                    }
+                }
+                Ok(message)
+            }
+        }
+
+        let walk = SnmpWalk::parse(
+            r#".1.3.6.1.2.1.1.3.0 = Timeticks: (500) 0:00:05.00
+.1.3.6.1.2.1.1.5.0 = STRING: "host"
+.1.3.6.1.2.1.1.7.0 = INTEGER: 72
+.1.3.6.1.2.1.2.2.1.2.1 = STRING: "lo"
+.1.3.6.1.2.1.2.2.1.2.2 = STRING: "eth0"
+.1.3.6.1.2.1.2.2.1.8.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.8.2 = INTEGER: 2
+.1.3.6.1.2.1.2.2.1.10.1 = Counter32: 5
+.1.3.6.1.2.1.2.2.1.10.2 = Counter32: 7"#,
+        )
+        .unwrap();
+        let queries = [
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.3.0",
+                name: "uptime",
+            },
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.5.0",
+                name: "sysname",
+            },
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.7.0",
+                name: "services",
+            },
+        ];
+        let faulty = |limit, status| Faulty {
+            walk: &walk,
+            limit,
+            status,
+            requests: vec![],
+        };
+
+        // tooBig is retried with halves of the request
+        let mut agent = faulty(1, Pdu::ERROR_STATUS_TOO_BIG);
+        let result = collect(&mut agent, &queries).unwrap();
+        assert_eq!(agent.requests, vec![3, 1, 2, 1, 1]);
+        assert!(matches!(&result.items["uptime"], ExprResult::Vector(v) if v == &[500.0]));
+        assert!(matches!(&result.items["sysname"], ExprResult::StrVector(v) if v == &["host"]));
+        assert!(matches!(&result.items["services"], ExprResult::Vector(v) if v == &[72.0]));
+
+        // Other errors are reported, on the OID given by the error index
+        let err = collect(&mut faulty(1, Pdu::ERROR_STATUS_GEN_ERR), &queries)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "The agent answered the request for 1.3.6.1.2.1.1.5.0 with the error 5 (genErr)"
+        );
+
+        // A truncated response does not leave the last scalars missing
+        let err = collect(&mut faulty(2, Pdu::ERROR_STATUS_NO_ERROR), &queries)
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, Error::MismatchedResponse { oid } if oid == "1.3.6.1.2.1.1.7.0"),
+            "{}",
+            err
+        );
+
+        // The same goes for the columns of the instances kept by a filter
+        let labels = HashMap::from([
+            (".2".to_string(), "descr".to_string()),
+            (".8".to_string(), "status".to_string()),
+            (".10".to_string(), "in".to_string()),
+        ]);
+        let keep = |_: &str| true;
+        let queries = [Query::Walk {
+            oid: "1.3.6.1.2.1.2.2.1",
+            name: "if",
+            labels: Some(&labels),
+            filter: Some(("descr", &keep)),
+        }];
+        let mut agent = faulty(3, Pdu::ERROR_STATUS_TOO_BIG);
+        let result = collect(&mut agent, &queries).unwrap();
+        assert_eq!(agent.requests, vec![4, 2, 2]);
+        assert!(matches!(&result.items["if.in"], ExprResult::Vector(v) if v == &[5.0, 7.0]));
+        let err = collect(&mut faulty(3, Pdu::ERROR_STATUS_NO_ERROR), &queries)
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, Error::MismatchedResponse { oid } if oid == "1.3.6.1.2.1.2.2.1.10.2"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_collect_concurrently() {
+        use crate::compute::ast::ExprResult;
+        use crate::snmp::planner::{Query, collect_concurrently};
+        use crate::snmp::snmpwalk::SnmpWalk;
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let walk = SnmpWalk::parse(
+            r#".1.3.6.1.2.1.1.3.0 = Timeticks: (500) 0:00:05.00
+.1.3.6.1.2.1.1.5.0 = STRING: "host"
+.1.3.6.1.2.1.2.2.1.2.1 = STRING: "lo"
+.1.3.6.1.2.1.2.2.1.2.2 = STRING: "eth0"
+.1.3.6.1.2.1.2.2.1.8.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.8.2 = INTEGER: 2
+.1.3.6.1.2.1.6.13.1.1.1 = INTEGER: 5
+.1.3.6.1.2.1.6.13.1.1.2 = INTEGER: 2
+.1.3.6.1.2.1.25.2.3.1.3.1 = STRING: "/"
+.1.3.6.1.2.1.25.2.3.1.6.1 = INTEGER: 42"#,
+        )
+        .unwrap();
+        let labels = HashMap::from([
+            (".2".to_string(), "descr".to_string()),
+            (".8".to_string(), "status".to_string()),
+        ]);
+        let disk = HashMap::from([
+            (".3".to_string(), "label".to_string()),
+            (".6".to_string(), "used".to_string()),
+        ]);
+        let queries = [
+            Query::Walk {
+                oid: "1.3.6.1.2.1.2.2.1",
+                name: "if",
+                labels: Some(&labels),
+                filter: None,
+            },
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.3.0",
+                name: "uptime",
+            },
+            Query::Walk {
+                oid: "1.3.6.1.2.1.6.13.1.1",
+                name: "tcp",
+                labels: None,
+                filter: None,
+            },
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.5.0",
+                name: "sysname",
+            },
+            Query::Walk {
+                oid: "1.3.6.1.2.1.25.2.3.1",
+                name: "disk",
+                labels: Some(&disk),
+                filter: None,
+            },
+        ];
+
+        // Four units, the Gets then each Walk, but only two agents
+        let agents = AtomicUsize::new(0);
+        let new_agent = || {
+            agents.fetch_add(1, Ordering::Relaxed);
+            Ok(&walk)
+        };
+        let result = collect_concurrently(new_agent, &queries, 2).unwrap();
+        assert_eq!(agents.load(Ordering::Relaxed), 2);
+        assert!(matches!(&result.items["uptime"], ExprResult::Vector(v) if v == &[500.0]));
+        assert!(matches!(&result.items["sysname"], ExprResult::StrVector(v) if v == &["host"]));
+        assert!(matches!(&result.items["if.status"], ExprResult::Vector(v) if v == &[1.0, 2.0]));
+        assert!(matches!(&result.items["tcp"], ExprResult::Vector(v) if v == &[5.0, 2.0]));
+        assert!(matches!(&result.items["disk.used"], ExprResult::Vector(v) if v == &[42.0]));
+        assert_eq!(result.indexes["if.descr"], vec!["1", "2"]);
+
+        // The varbinds are merged in the order of the units, whatever the
+        // concurrency
+        let names = |concurrency| {
+            collect_concurrently(|| Ok(&walk), &queries, concurrency)
+                .unwrap()
+                .varbinds
+                .iter()
+                .map(|var| var.name.to_string())
+                .collect::<Vec<String>>()
+        };
+        let expected = names(4);
+        assert_eq!(expected.len(), 10);
+        assert_eq!(expected[0], "1.3.6.1.2.1.1.3.0");
+        assert_eq!(expected[9], "1.3.6.1.2.1.25.2.3.1.6.1");
+        for _ in 0..10 {
+            assert_eq!(names(3), expected);
+        }
+
+        // A single agent packs all the queries
+        agents.store(0, Ordering::Relaxed);
+        let result = collect_concurrently(new_agent, &queries, 1).unwrap();
+        assert_eq!(agents.load(Ordering::Relaxed), 1);
+        assert_eq!(result.varbinds.len(), 10);
+    }
+
+    #[test]
+    fn test_snmp_collect_pipelined() {
+        use crate::compute::ast::ExprResult;
+        use crate::snmp::Agent;
+        use crate::snmp::planner::{Query, snmp_collect};
+        use crate::snmp::snmpwalk::SnmpWalk;
+        use rasn_snmp::v2::Pdus;
+        use rasn_snmp::v2c::Message;
+        use std::net::UdpSocket;
+
+        let walk = SnmpWalk::parse(
+            r#".1.3.6.1.2.1.1.3.0 = Timeticks: (500) 0:00:05.00
+.1.3.6.1.2.1.25.2.3.1.3.1 = STRING: "/"
+.1.3.6.1.2.1.25.2.3.1.6.1 = INTEGER: 42"#,
+        )
+        .unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = socket.local_addr().unwrap().to_string();
+
+        // An agent answering the GetBulkRequest before the GetRequest sent
+        // just before it
+        let agent = std::thread::spawn(move || {
+            let mut buf = vec![0u8; 65535];
+            let mut receive = || {
+                let (len, from) = socket.recv_from(&mut buf).unwrap();
+                let message: Message<Pdus> = rasn::ber::decode(&buf[..len]).unwrap();
+                (message.data, from)
+            };
+            let (Pdus::GetRequest(get), from) = receive() else {
+                panic!("Expected a GetRequest first");
+            };
+            let (Pdus::GetBulkRequest(bulk), _) = receive() else {
+                panic!("Expected a GetBulkRequest second");
+            };
+            let answers = [
+                (
+                    bulk.0.request_id,
+                    Agent::get_bulk(&mut &walk, bulk.0.variable_bindings, bulk.0.max_repetitions)
+                        .unwrap(),
+                ),
+                (
+                    get.0.request_id,
+                    Agent::get(&mut &walk, get.0.variable_bindings).unwrap(),
+                ),
+            ];
+            for (request_id, mut message) in answers {
+                if let Pdus::Response(resp) = &mut message.data {
+                    resp.0.request_id = request_id;
+                }
+                let encoded = rasn::ber::encode(&message).unwrap();
+                socket.send_to(&encoded, from).unwrap();
+            }
+        });
+
+        let queries = [
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.3.0",
+                name: "uptime",
+            },
+            Query::Walk {
+                oid: "1.3.6.1.2.1.25.2.3.1.6",
+                name: "used",
+                labels: None,
+                filter: None,
+            },
+        ];
+        let result = snmp_collect(&target, "2c", "public", &queries, 1).unwrap();
+        agent.join().unwrap();
+        assert!(matches!(&result.items["uptime"], ExprResult::Vector(v) if v == &[500.0]));
+        assert!(matches!(&result.items["used"], ExprResult::Vector(v) if v == &[42.0]));
+    }
+
+    #[test]
+    fn test_snmp_collect_errors() {
+        use crate::generic::error::Error;
+        use crate::snmp::planner::{Query, snmp_collect};
+        use std::net::UdpSocket;
+
+        let queries = [Query::Get {
+            oid: "1.3.6.1.2.1.1.3.0",
+            name: "uptime",
+        }];
+
+        // An agent answering garbage
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = socket.local_addr().unwrap().to_string();
+        let agent = std::thread::spawn(move || {
+            let mut buf = vec![0u8; 65535];
+            let (_, from) = socket.recv_from(&mut buf).unwrap();
+            socket.send_to(b"not snmp", from).unwrap();
+            socket
+        });
+        let err = snmp_collect(&target, "2c", "public", &queries, 1).unwrap_err();
+        assert!(matches!(err, Error::InvalidResponse { .. }), "{}", err);
+
+        // then not answering at all
+        let _socket = agent.join().unwrap();
+        let err = snmp_collect(&target, "2c", "public", &queries, 1).unwrap_err();
+        assert!(matches!(err, Error::NoResponse { .. }), "{}", err);
+        assert_eq!(
+            err.to_string(),
+            format!("No response from the agent {}", target)
+        );
+    }
+}
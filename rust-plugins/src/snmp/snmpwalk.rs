@@ -0,0 +1,588 @@
+//! The `.snmpwalk` text format: one `.oid = TYPE: value` line per variable
+//! binding, as printed by Net-SNMP's `snmpwalk -On`.
+//!
+//! The `walk` and `get` subcommands print their responses in this format, and
+//! a saved file can be replayed with [`SnmpWalk`] instead of querying an agent.
+
+use super::planner::{self, Query};
+use super::{Agent, Column, SnmpResult, parse_oid, walk_columns};
+use crate::generic::error::{Error, Result};
+use rasn::types::{Integer, ObjectIdentifier, OctetString};
+use rasn_smi::v1::{Counter, Gauge, IpAddress, TimeTicks};
+use rasn_smi::v2::{ApplicationSyntax, Counter64, ObjectSyntax, SimpleSyntax};
+use rasn_snmp::v2::{Pdu, Pdus, Response, VarBind, VarBindValue};
+use rasn_snmp::v2c::Message;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::ops::Bound;
+use std::path::Path;
+
+const NO_SUCH_OBJECT: &str = "No Such Object available on this agent at this OID";
+const NO_SUCH_INSTANCE: &str = "No Such Instance currently exists at this OID";
+const END_OF_MIB_VIEW: &str =
+    "No more variables left in this MIB View (It is past the end of the MIB tree)";
+
+/// Writes `ticks` (hundredths of a second) as Net-SNMP does, e.g.
+/// `(8640123) 1 day, 0:00:01.23`.
+fn format_ticks(ticks: u32) -> String {
+    let days = ticks / 8_640_000;
+    let hours = ticks / 360_000 % 24;
+    let minutes = ticks / 6_000 % 60;
+    let seconds = ticks / 100 % 60;
+    let time = format!("{}:{:02}:{:02}.{:02}", hours, minutes, seconds, ticks % 100);
+    match days {
+        0 => format!("({}) {}", ticks, time),
+        1 => format!("({}) 1 day, {}", ticks, time),
+        _ => format!("({}) {} days, {}", ticks, days, time),
+    }
+}
+
+/// Writes `bytes` as the space separated hexadecimal digits of a
+/// `Hex-STRING`.
+fn format_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Formats the value of a variable binding as `TYPE: value`.
+fn format_value(value: &VarBindValue) -> String {
+    let value = match value {
+        VarBindValue::Value(value) => value,
+        VarBindValue::Unspecified => return "NULL".to_string(),
+        VarBindValue::NoSuchObject => return NO_SUCH_OBJECT.to_string(),
+        VarBindValue::NoSuchInstance => return NO_SUCH_INSTANCE.to_string(),
+        VarBindValue::EndOfMibView => return END_OF_MIB_VIEW.to_string(),
+    };
+    match value {
+        ObjectSyntax::Simple(SimpleSyntax::Integer(i)) => format!("INTEGER: {}", i),
+        ObjectSyntax::Simple(SimpleSyntax::String(s)) => match std::str::from_utf8(s) {
+            Ok(text) if !text.chars().any(char::is_control) => format!(
+                "STRING: \"{}\"",
+                text.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            _ => format!("Hex-STRING: {}", format_hex(s)),
+        },
+        ObjectSyntax::Simple(SimpleSyntax::ObjectId(oid)) => format!("OID: .{}", oid),
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Address(address)) => {
+            let arcs: Vec<String> = address.0.iter().map(u8::to_string).collect();
+            format!("IpAddress: {}", arcs.join("."))
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Counter(c)) => {
+            format!("Counter32: {}", c.0)
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Ticks(t)) => {
+            format!("Timeticks: {}", format_ticks(t.0))
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Arbitrary(opaque)) => {
+            format!("Opaque: {}", format_hex(opaque.as_ref()))
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::BigCounter(c)) => {
+            format!("Counter64: {}", c.0)
+        }
+        ObjectSyntax::ApplicationWide(ApplicationSyntax::Unsigned(g)) => {
+            format!("Gauge32: {}", g.0)
+        }
+    }
+}
+
+/// Formats a variable binding as a `.snmpwalk` line, without end of line.
+pub fn format_varbind(var: &VarBind) -> String {
+    format!(".{} = {}", var.name, format_value(&var.value))
+}
+
+/// Parses the number of a value such as `up(1)` or `42`.
+fn parse_number<T: std::str::FromStr>(value: &str) -> Option<T> {
+    let value = match (value.rfind('('), value.strip_suffix(')')) {
+        (Some(start), Some(rest)) => &rest[start + 1..],
+        _ => value,
+    };
+    value.trim().parse().ok()
+}
+
+/// Parses the `TYPE: value` part of a `.snmpwalk` line.
+fn parse_value(value: &str) -> std::result::Result<VarBindValue, String> {
+    match value {
+        "NULL" => return Ok(VarBindValue::Unspecified),
+        "\"\"" => {
+            return Ok(VarBindValue::Value(
+                SimpleSyntax::String(OctetString::new()).into(),
+            ));
+        }
+        NO_SUCH_OBJECT => return Ok(VarBindValue::NoSuchObject),
+        NO_SUCH_INSTANCE => return Ok(VarBindValue::NoSuchInstance),
+        END_OF_MIB_VIEW => return Ok(VarBindValue::EndOfMibView),
+        _ => {}
+    }
+    let Some((kind, value)) = value.split_once(": ") else {
+        return Err(format!("the value '{}' has no type", value));
+    };
+    let bad = || format!("bad {} value '{}'", kind, value);
+    let syntax: ObjectSyntax = match kind {
+        "INTEGER" => {
+            let i: i64 = parse_number(value).ok_or_else(bad)?;
+            SimpleSyntax::Integer(Integer::from(i)).into()
+        }
+        "STRING" => {
+            let text = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+                None => value.to_string(),
+            };
+            SimpleSyntax::String(OctetString::from(text.into_bytes())).into()
+        }
+        "Hex-STRING" => {
+            let bytes = value
+                .split_whitespace()
+                .map(|b| u8::from_str_radix(b, 16).ok())
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(bad)?;
+            SimpleSyntax::String(OctetString::from(bytes)).into()
+        }
+        "OID" => {
+            let oid = parse_oid(value).ok_or_else(bad)?;
+            SimpleSyntax::ObjectId(ObjectIdentifier::new_unchecked(oid.into())).into()
+        }
+        "IpAddress" => {
+            let bytes = value
+                .split('.')
+                .map(|b| b.parse::<u8>().ok())
+                .collect::<Option<Vec<u8>>>()
+                .and_then(|b| <[u8; 4]>::try_from(b).ok())
+                .ok_or_else(bad)?;
+            ApplicationSyntax::Address(IpAddress(bytes.into())).into()
+        }
+        "Counter32" => {
+            ApplicationSyntax::Counter(Counter(parse_number(value).ok_or_else(bad)?)).into()
+        }
+        "Gauge32" | "Unsigned32" => {
+            ApplicationSyntax::Unsigned(Gauge(parse_number(value).ok_or_else(bad)?)).into()
+        }
+        "Timeticks" => {
+            // `(123) 0:00:01.23`, or the bare number printed with `-Ot`
+            let ticks = match value.strip_prefix('(').and_then(|v| v.split_once(')')) {
+                Some((ticks, _)) => ticks,
+                None => value,
+            };
+            ApplicationSyntax::Ticks(TimeTicks(ticks.trim().parse().map_err(|_| bad())?)).into()
+        }
+        "Counter64" => {
+            ApplicationSyntax::BigCounter(Counter64(parse_number(value).ok_or_else(bad)?)).into()
+        }
+        _ => return Err(format!("unsupported type '{}'", kind)),
+    };
+    Ok(VarBindValue::Value(syntax))
+}
+
+/// The variable bindings of a `.snmpwalk` file, sorted by OID, answering
+/// the queries of a definition as an agent would.
+#[derive(Debug, Default)]
+pub struct SnmpWalk {
+    varbinds: BTreeMap<Vec<u32>, VarBindValue>,
+}
+
+impl SnmpWalk {
+    /// Parses the lines of a `.snmpwalk` file. Until its closing quote, a
+    /// string continues on the lines that do not start with an OID; blank
+    /// lines are skipped otherwise.
+    ///
+    /// # Errors
+    /// Returns an error with its line number if a line is malformed.
+    pub fn parse(text: &str) -> Result<SnmpWalk> {
+        let mut walk = SnmpWalk::default();
+        let mut last: Option<Vec<u32>> = None;
+        // Whether the string of `last` still waits for its closing quote
+        let mut open_string = false;
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| Error::InvalidSnmpWalk {
+                line: number + 1,
+                message,
+            };
+            let parsed = line
+                .split_once(" = ")
+                .and_then(|(oid, value)| parse_oid(oid.trim()).map(|oid| (oid, value)));
+            let Some((oid, value)) = parsed else {
+                // The next line of a multi-line string, or a blank line
+                match last.as_ref().and_then(|oid| walk.varbinds.get_mut(oid)) {
+                    Some(VarBindValue::Value(ObjectSyntax::Simple(SimpleSyntax::String(s))))
+                        if open_string =>
+                    {
+                        let text = format!("{}\n{}", String::from_utf8_lossy(s), line);
+                        open_string = !text.ends_with('"');
+                        let text = text.strip_suffix('"').unwrap_or(&text);
+                        *s = OctetString::from(text.as_bytes().to_vec());
+                    }
+                    _ if line.trim().is_empty() => {}
+                    _ => {
+                        return Err(error(format!(
+                            "'{}' is not an 'OID = TYPE: value' line",
+                            line
+                        )));
+                    }
+                }
+                continue;
+            };
+            let mut value = value.trim_end().to_string();
+            // An opening quote without its closing one continues on the next lines
+            open_string =
+                value.starts_with("STRING: \"") && (value.len() == 9 || !value.ends_with('"'));
+            if open_string {
+                value.push('"');
+            }
+            let value = parse_value(&value).map_err(error)?;
+            walk.varbinds.insert(oid.clone(), value);
+            last = Some(oid);
+        }
+        Ok(walk)
+    }
+
+    /// Reads and parses the `.snmpwalk` file `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is malformed.
+    pub fn read(path: &Path) -> Result<SnmpWalk> {
+        SnmpWalk::parse(&fs::read_to_string(path)?)
+    }
+
+    fn varbind(oid: &[u32], value: &VarBindValue) -> VarBind {
+        VarBind {
+            name: ObjectIdentifier::new_unchecked(oid.to_vec().into()),
+            value: value.clone(),
+        }
+    }
+
+    /// Answers a `GetBulkRequest` without non-repeaters as an agent would:
+    /// `max_repetitions` rows holding the successor of each requested OID.
+    fn answer_get_bulk(&self, request: Vec<VarBind>, max_repetitions: u32) -> Message<Pdus> {
+        let mut next: Vec<Vec<u32>> = request
+            .iter()
+            .map(|var| var.name.iter().copied().collect())
+            .collect();
+        let mut vars = Vec::new();
+        for _ in 0..max_repetitions {
+            for oid in next.iter_mut() {
+                let after = (Bound::Excluded(oid.clone()), Bound::Unbounded);
+                match self.varbinds.range(after).next() {
+                    Some((name, value)) => {
+                        vars.push(SnmpWalk::varbind(name, value));
+                        *oid = name.clone();
+                    }
+                    None => vars.push(SnmpWalk::varbind(oid, &VarBindValue::EndOfMibView)),
+                }
+            }
+        }
+        SnmpWalk::response(vars)
+    }
+
+    /// Answers a `GetRequest` as an agent would: `noSuchInstance` if the
+    /// object has other instances, else `noSuchObject`.
+    fn answer_get(&self, request: Vec<VarBind>) -> Message<Pdus> {
+        let vars = request
+            .into_iter()
+            .map(|var| {
+                let oid: Vec<u32> = var.name.iter().copied().collect();
+                let object = &oid[..oid.len().saturating_sub(1)];
+                let missing = match self.varbinds.range(object.to_vec()..).next() {
+                    Some((name, _)) if name.starts_with(object) => VarBindValue::NoSuchInstance,
+                    _ => VarBindValue::NoSuchObject,
+                };
+                SnmpWalk::varbind(&oid, self.varbinds.get(&oid).unwrap_or(&missing))
+            })
+            .collect();
+        SnmpWalk::response(vars)
+    }
+
+    /// Wraps `vars` into the response an agent would have sent.
+    fn response(vars: Vec<VarBind>) -> Message<Pdus> {
+        Message {
+            version: 1.into(),
+            community: String::new().into(),
+            data: Pdus::Response(Response(Pdu {
+                request_id: 1,
+                error_status: Pdu::ERROR_STATUS_NO_ERROR,
+                error_index: 0,
+                variable_bindings: vars,
+            })),
+        }
+    }
+
+    /// Replays [`super::snmp_walk_varbinds`].
+    ///
+    /// # Errors
+    /// Never fails: a replayed walk answers without error status.
+    pub fn walk_varbinds(&self, oid: &str) -> Result<SnmpResult> {
+        let mut result = SnmpResult::new(HashMap::new());
+        let Some(oid) = parse_oid(oid) else {
+            return Ok(result);
+        };
+        walk_columns(&mut &*self, &mut [Column::new(oid, None)], &mut result)?;
+        Ok(result)
+    }
+
+    /// Replays [`super::planner::snmp_collect`].
+    ///
+    /// # Errors
+    /// Never fails: a replayed walk answers without error status.
+    pub fn collect(&self, queries: &[Query]) -> Result<SnmpResult> {
+        planner::collect(&mut &*self, queries)
+    }
+}
+
+impl Agent for &SnmpWalk {
+    fn get_bulk(&mut self, request: Vec<VarBind>, max_repetitions: u32) -> Result<Message<Pdus>> {
+        Ok(self.answer_get_bulk(request, max_repetitions))
+    }
+
+    fn get(&mut self, request: Vec<VarBind>) -> Result<Message<Pdus>> {
+        Ok(self.answer_get(request))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_round_trip() {
+        use crate::snmp::snmpwalk::{SnmpWalk, format_varbind};
+
+        let text = r#".1.3.6.1.2.1.1.1.0 = STRING: "Linux \"box\" 6.1"
+.1.3.6.1.2.1.1.2.0 = OID: .1.3.6.1.4.1.8072.3.2.10
+.1.3.6.1.2.1.1.3.0 = Timeticks: (8640123) 1 day, 0:00:01.23
+.1.3.6.1.2.1.2.2.1.2.1 = STRING: "lo"
+.1.3.6.1.2.1.2.2.1.6.2 = Hex-STRING: 00 1A 2B 3C 4D 5E
+.1.3.6.1.2.1.2.2.1.8.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.10.1 = Counter32: 1234
+.1.3.6.1.2.1.4.20.1.1.127.0.0.1 = IpAddress: 127.0.0.1
+.1.3.6.1.2.1.25.2.3.1.6.1 = Gauge32: 42
+.1.3.6.1.2.1.31.1.1.1.6.1 = Counter64: 12345678901
+.1.3.6.1.2.1.99.1.0 = No Such Object available on this agent at this OID"#;
+        let walk = SnmpWalk::parse(text).unwrap();
+        let lines: Vec<String> = walk
+            .varbinds
+            .iter()
+            .map(|(oid, value)| format_varbind(&SnmpWalk::varbind(oid, value)))
+            .collect();
+        assert_eq!(lines.join("\n"), text);
+
+        // Net-SNMP output without -Oe and -Ot, and a string on two lines
+        let walk = SnmpWalk::parse(
+            ".1.3.6.1.2.1.2.2.1.8.2 = INTEGER: up(1)\n.1.3.6.1.2.1.1.3.0 = Timeticks: 123\n\
+             .1.3.6.1.2.1.1.1.0 = STRING: \"two\nlines\"\n\n\
+             .1.3.6.1.2.1.2.2.1.2.1 = STRING: \"lo\"\n\n",
+        )
+        .unwrap();
+        let lines: Vec<String> = walk
+            .varbinds
+            .iter()
+            .map(|(oid, value)| format_varbind(&SnmpWalk::varbind(oid, value)))
+            .collect();
+        assert_eq!(lines[1], ".1.3.6.1.2.1.1.3.0 = Timeticks: (123) 0:00:01.23");
+        assert_eq!(
+            lines[0],
+            ".1.3.6.1.2.1.1.1.0 = Hex-STRING: 74 77 6F 0A 6C 69 6E 65 73"
+        );
+        // Blank lines after a closed string are skipped
+        assert_eq!(lines[2], ".1.3.6.1.2.1.2.2.1.2.1 = STRING: \"lo\"");
+        assert_eq!(lines[3], ".1.3.6.1.2.1.2.2.1.8.2 = INTEGER: 1");
+
+        let error = SnmpWalk::parse(".1.3.6.1.2.1.1.3.0 = Timeticks: soon").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line 1 of the snmpwalk: bad Timeticks value 'soon'"
+        );
+    }
+
+    #[test]
+    fn test_replay() {
+        use crate::compute::ast::ExprResult;
+        use crate::snmp::planner::Query;
+        use crate::snmp::snmpwalk::SnmpWalk;
+        use rasn_snmp::v2::VarBindValue;
+        use std::collections::HashMap;
+
+        let walk = SnmpWalk::parse(
+            r#".1.3.6.1.2.1.1.3.0 = Timeticks: (500) 0:00:05.00
+.1.3.6.1.2.1.2.2.1.2.1 = STRING: "lo"
+.1.3.6.1.2.1.2.2.1.2.2 = STRING: "eth0"
+.1.3.6.1.2.1.2.2.1.8.1 = INTEGER: 1
+.1.3.6.1.2.1.2.2.1.8.2 = INTEGER: 2
+.1.3.6.1.2.1.2.2.1.10.1 = Counter32: 7"#,
+        )
+        .unwrap();
+
+        let labels = HashMap::from([
+            (".2".to_string(), "descr".to_string()),
+            (".8".to_string(), "status".to_string()),
+        ]);
+        let result = walk
+            .collect(&[Query::Walk {
+                oid: ".1.3.6.1.2.1.2.2.1",
+                name: "if",
+                labels: Some(&labels),
+                filter: None,
+            }])
+            .unwrap();
+        assert!(
+            matches!(&result.items["if.descr"], ExprResult::StrVector(v) if v == &["lo", "eth0"])
+        );
+        assert!(matches!(&result.items["if.status"], ExprResult::Vector(v) if v == &[1.0, 2.0]));
+        assert_eq!(result.indexes["if.status"], vec!["1", "2"]);
+        // Only the columns of the labels are walked
+        assert_eq!(result.varbinds.len(), 4);
+        assert!(!result.items.contains_key("if.10"));
+
+        // Columns of different lengths, longer than one GetBulkRequest
+        let mut text = String::new();
+        for i in 1..=25 {
+            text.push_str(&format!(
+                ".1.3.6.1.2.1.25.4.2.1.2.{} = STRING: \"p{}\"\n",
+                i, i
+            ));
+        }
+        text.push_str(".1.3.6.1.2.1.25.4.2.1.7.3 = INTEGER: 1\n");
+        let table = SnmpWalk::parse(&text).unwrap();
+        let labels = HashMap::from([
+            (".2".to_string(), "name".to_string()),
+            (".7".to_string(), "status".to_string()),
+        ]);
+        let result = table
+            .collect(&[Query::Walk {
+                oid: "1.3.6.1.2.1.25.4.2.1",
+                name: "proc",
+                labels: Some(&labels),
+                filter: None,
+            }])
+            .unwrap();
+        assert!(matches!(&result.items["proc.name"], ExprResult::StrVector(v) if v.len() == 25));
+        assert_eq!(result.indexes["proc.name"][24], "25");
+        assert_eq!(result.indexes["proc.status"], vec!["3"]);
+
+        let queries = [
+            Query::Get {
+                oid: "1.3.6.1.2.1.1.3.0",
+                name: "uptime",
+            },
+            Query::Get {
+                oid: "1.3.6.1.2.1.2.2.1.8.3",
+                name: "status3",
+            },
+            Query::Get {
+                oid: "1.3.6.1.4.1.9.9.0.1.0",
+                name: "missing",
+            },
+        ];
+        let result = walk.collect(&queries).unwrap();
+        assert!(matches!(&result.items["uptime"], ExprResult::Vector(v) if v == &[500.0]));
+        assert_eq!(result.items.len(), 1);
+        assert!(matches!(
+            result.varbinds[1].value,
+            VarBindValue::NoSuchInstance
+        ));
+        assert!(matches!(
+            result.varbinds[2].value,
+            VarBindValue::NoSuchObject
+        ));
+    }
+
+    #[test]
+    fn test_walk_columns_retries() {
+        use crate::compute::ast::ExprResult;
+        use crate::generic::error::Result;
+        use crate::snmp::snmpwalk::SnmpWalk;
+        use crate::snmp::{Agent, SnmpResult, label_columns, walk_columns};
+        use rasn_snmp::v2::{Pdu, Pdus, VarBind};
+        use rasn_snmp::v2c::Message;
+        use std::collections::HashMap;
+
+        /// An agent answering at most `limit` varbinds: it truncates larger
+        /// responses, or answers them with `status`.
+        struct Small<'a> {
+            walk: &'a SnmpWalk,
+            limit: usize,
+            status: u32,
+            requests: Vec<(usize, u32)>,
+        }
+        impl Agent for Small<'_> {
+            fn get_bulk(
+                &mut self,
+                request: Vec<VarBind>,
+                max_repetitions: u32,
+            ) -> Result<Message<Pdus>> {
+                self.requests.push((request.len(), max_repetitions));
+                let mut message = Agent::get_bulk(&mut self.walk, request, max_repetitions)?;
+                if let Pdus::Response(resp) = &mut message.data
+                    && resp.0.variable_bindings.len() > self.limit
+                {
+                    if self.status == Pdu::ERROR_STATUS_NO_ERROR {
+                        resp.0.variable_bindings.truncate(self.limit);
+                    } else {
+                        resp.0.error_status = self.status;
+                        resp.0.variable_bindings.clear();
+                    }
+                }
+                Ok(message)
+            }
+            fn get(&mut self, request: Vec<VarBind>) -> Result<Message<Pdus>> {
+                Agent::get(&mut self.walk, request)
+            }
+        }
+
+        let mut text = String::new();
+        for i in 1..=5 {
+            text.push_str(&format!(
+                ".1.3.6.1.2.1.2.2.1.2.{} = STRING: \"if{}\"\n",
+                i, i
+            ));
+            text.push_str(&format!(".1.3.6.1.2.1.2.2.1.8.{} = INTEGER: 1\n", i));
+            text.push_str(&format!(".1.3.6.1.2.1.2.2.1.10.{} = Counter32: {}\n", i, i));
+        }
+        let walk = SnmpWalk::parse(&text).unwrap();
+        let labels = HashMap::from([
+            (".2".to_string(), "descr".to_string()),
+            (".8".to_string(), "status".to_string()),
+            (".10".to_string(), "in".to_string()),
+        ]);
+        let run = |agent: &mut Small| {
+            let mut columns = label_columns("1.3.6.1.2.1.2.2.1", "if", &labels);
+            let mut result = SnmpResult::new(HashMap::new());
+            walk_columns(agent, &mut columns, &mut result).map(|_| result)
+        };
+        let complete = |result: &SnmpResult| {
+            matches!(&result.items["if.in"], ExprResult::Vector(v) if v == &[1.0, 2.0, 3.0, 4.0, 5.0])
+                && matches!(&result.items["if.descr"], ExprResult::StrVector(v) if v.len() == 5)
+                && matches!(&result.items["if.status"], ExprResult::Vector(v) if v.len() == 5)
+        };
+        let small = |status| Small {
+            walk: &walk,
+            limit: 4,
+            status,
+            requests: vec![],
+        };
+
+        // The columns cut from truncated responses are requested again
+        let mut agent = small(Pdu::ERROR_STATUS_NO_ERROR);
+        let result = run(&mut agent).unwrap();
+        assert!(complete(&result));
+        assert_eq!(agent.requests[1], (3, 10));
+
+        // tooBig is retried with fewer repetitions
+        let mut agent = small(Pdu::ERROR_STATUS_TOO_BIG);
+        let result = run(&mut agent).unwrap();
+        assert!(complete(&result));
+        assert_eq!(agent.requests[..4], [(3, 10), (3, 5), (3, 2), (3, 1)]);
+
+        // then with fewer columns, until a single varbind is too big
+        let mut agent = small(Pdu::ERROR_STATUS_TOO_BIG);
+        agent.limit = 0;
+        let err = run(&mut agent).err().unwrap();
+        assert_eq!(agent.requests[3..], [(3, 1), (1, 1)]);
+        assert!(err.to_string().contains("error 1 (tooBig)"), "{}", err);
+
+        // Other errors are reported
+        let err = run(&mut small(Pdu::ERROR_STATUS_GEN_ERR)).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "The agent answered the request for 1.3.6.1.2.1.2.2.1.2 with the error 5 (genErr)"
+        );
+    }
+}